@@ -0,0 +1,30 @@
+//! Write the JSON Schema of every event type into a directory
+//!
+//! Usage: `event-schemas [DIR]`, where `DIR` defaults to `schemas`.
+
+use products::schema::{json_schema, EVENT_TYPES, SCHEMA_VERSION};
+use std::path::PathBuf;
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+fn main() -> Result<(), E> {
+    let dir = PathBuf::from(
+        std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "schemas".to_string()),
+    );
+    std::fs::create_dir_all(&dir)?;
+
+    for event_type in EVENT_TYPES {
+        let schema = json_schema(event_type).expect("event types have a schema");
+        let path = dir.join(format!(
+            "product-{}.v{}.json",
+            event_type.to_lowercase(),
+            SCHEMA_VERSION
+        ));
+        std::fs::write(&path, serde_json::to_string_pretty(&schema)? + "\n")?;
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
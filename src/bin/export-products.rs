@@ -0,0 +1,36 @@
+//! Write every product of the table as JSON lines on stdout
+//!
+//! Usage: `export-products [SEGMENTS]`, where `SEGMENTS` is the number of
+//! segments scanned in parallel and defaults to 8. The table is read from
+//! `TABLE_NAME`, as in the Lambda functions.
+
+use futures::TryStreamExt;
+use products::{domain, utils::*};
+use std::io::Write;
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::main]
+async fn main() -> Result<(), E> {
+    let segments = match std::env::args().nth(1) {
+        Some(segments) => segments.parse()?,
+        None => 8,
+    };
+
+    // Initialize store
+    let store = get_store().await;
+
+    // Stream products to stdout
+    let mut products = domain::export_products(&store, segments)?;
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut count = 0;
+    while let Some(product) = products.try_next().await? {
+        serde_json::to_writer(&mut stdout, &product)?;
+        stdout.write_all(b"\n")?;
+        count += 1;
+    }
+    stdout.flush()?;
+
+    eprintln!("Exported {} product(s)", count);
+    Ok(())
+}
@@ -13,6 +13,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     // Initialize store
     let store = get_store().await;
 
+    // Deletes of missing products return a 404 unless strict mode is off
+    let strict = strict_delete();
+
     // Run the Lambda function
     //
     // This is the entry point for the Lambda function. The `lambda_runtime`
@@ -29,7 +32,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     // which matches the signature of the lambda function.
     // See https://github.com/rust-lang/rust/issues/62290
     lambda_runtime::run(handler(|event: Request, ctx: Context| {
-        delete_product(&store, event, ctx)
+        delete_product(&store, strict, event, ctx)
     }))
     .await?;
     Ok(())
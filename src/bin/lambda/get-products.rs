@@ -12,6 +12,9 @@ async fn main() -> Result<(), E> {
     // Initialize logger
     setup_tracing();
 
+    // Load the cursor secret
+    setup_cursors()?;
+
     // Initialize store
     let store = get_store().await;
 
@@ -0,0 +1,41 @@
+use lambda_http::{
+    handler,
+    lambda_runtime::{self, Context},
+    Request,
+};
+use products::{entrypoints::lambda::apigateway::get_trash, utils::*};
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::main]
+async fn main() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Load the cursor secret
+    setup_cursors()?;
+
+    // Initialize store
+    let store = get_store().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_runtime`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `get_trash` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // This uses a closure to pass the Service without having to reinstantiate
+    // it for every call. This is a bit of a hack, but it's the only way to
+    // pass a store to a lambda function.
+    //
+    // Furthermore, we don't await the result of `get_trash` because
+    // async closures aren't stable yet. This way, the closure returns a Future,
+    // which matches the signature of the lambda function.
+    // See https://github.com/rust-lang/rust/issues/62290
+    lambda_runtime::run(handler(|event: Request, ctx: Context| {
+        get_trash(&store, event, ctx)
+    }))
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use lambda_runtime::{handler_fn, Context};
+use products::{entrypoints::lambda::outbox::relay_outbox, utils::*};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize store and event bus
+    //
+    // The store must be in outbox mode, otherwise there is nothing to relay.
+    let store = get_store().await;
+    let event_bus = get_event_bus().await;
+
+    // Run the Lambda function
+    //
+    // This is the entry point for the Lambda function. The `lambda_runtime`
+    // crate will take care of contacting the Lambda runtime API and invoking
+    // the `relay_outbox` function.
+    // See https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+    //
+    // This uses a closure to pass the store and event bus without having to
+    // reinstantiate them for every call.
+    lambda_runtime::run(handler_fn(|event: serde_json::Value, ctx: Context| {
+        relay_outbox(&store, &event_bus, event, ctx)
+    }))
+    .await?;
+    Ok(())
+}
@@ -3,7 +3,9 @@ use lambda_http::{
     lambda_runtime::{self, Context},
     Request,
 };
-use products::{entrypoints::lambda::apigateway::put_product, utils::*};
+use products::{
+    domain::validation::ProductRules, entrypoints::lambda::apigateway::put_product, utils::*,
+};
 
 type E = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -15,6 +17,9 @@ async fn main() -> Result<(), E> {
     // Initialize store
     let store = get_store().await;
 
+    // Initialize validation rules
+    let rules = ProductRules::default();
+
     // Run the Lambda function
     //
     // This is the entry point for the Lambda function. The `lambda_runtime`
@@ -31,7 +36,7 @@ async fn main() -> Result<(), E> {
     // which matches the signature of the lambda function.
     // See https://github.com/rust-lang/rust/issues/62290
     lambda_runtime::run(handler(|event: Request, ctx: Context| {
-        put_product(&store, event, ctx)
+        put_product(&store, &rules, event, ctx)
     }))
     .await?;
     Ok(())
@@ -0,0 +1,43 @@
+use products::{
+    domain::validation::ProductRules, entrypoints::local::serve, store::MemoryStore, utils::*,
+};
+use std::net::SocketAddr;
+use tracing::info;
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Default port of the local server
+const DEFAULT_PORT: u16 = 3000;
+
+#[tokio::main]
+async fn main() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Read the listening port
+    //
+    // This only binds to localhost, as the server is meant for development.
+    let port = match std::env::var("PORT") {
+        Ok(port) => port.parse()?,
+        Err(_) => DEFAULT_PORT,
+    };
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let rules = ProductRules::default();
+
+    // Initialize store and serve requests
+    //
+    // `STORE=dynamodb` uses the table from `TABLE_NAME`, like the Lambda
+    // functions. Otherwise, products are kept in memory and lost on exit.
+    match std::env::var("STORE").as_deref() {
+        Ok("dynamodb") => serve(get_store().await, rules, addr).await,
+        Ok("memory") | Err(_) => {
+            info!("Initializing in-memory store");
+            let store = match soft_delete_retention() {
+                Some(retention) => MemoryStore::new().with_soft_delete(retention),
+                None => MemoryStore::new(),
+            };
+            serve(store, rules, addr).await
+        }
+        Ok(store) => Err(format!("Unknown store '{}'", store).into()),
+    }
+}
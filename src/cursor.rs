@@ -0,0 +1,184 @@
+//! # Pagination cursors
+//!
+//! Stores return the raw key of the last evaluated item as their `next`
+//! value. Exposing that key to clients directly would leak storage details
+//! and let them forge arbitrary start keys, so the domain layer wraps it into
+//! an opaque token signed with HMAC-SHA256.
+//!
+//! The signature also covers a scope naming the listing the cursor belongs
+//! to, such as a category and sort key, so that a cursor cannot be replayed
+//! against another listing whose keys have another shape.
+//!
+//! The signing key is read from the `CURSOR_SECRET` environment variable by
+//! `init`, when a function starts. All instances of the service must share the
+//! same secret, otherwise tokens issued by one instance will be rejected by
+//! another.
+
+use crate::Error;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::OnceLock;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Fallback secret used by the local server when `CURSOR_SECRET` is not set
+///
+/// This keeps local development working out of the box, but tokens signed
+/// with it are only tamper-evident, not secret.
+#[cfg(any(test, feature = "local"))]
+static DEFAULT_SECRET: &str = "rust-products";
+
+static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Read the signing key from `CURSOR_SECRET`
+///
+/// This fails if the variable is not set, so that a function never signs
+/// cursors with a secret anyone can read in this repository.
+pub fn init() -> Result<(), Error> {
+    let secret = load(std::env::var("CURSOR_SECRET").ok())?;
+    // A second call keeps the secret of the first one
+    let _ = SECRET.set(secret);
+    Ok(())
+}
+
+/// Read the signing key like `init`, falling back to `DEFAULT_SECRET`
+///
+/// Only the local server uses this.
+#[cfg(feature = "local")]
+pub fn init_local() {
+    if init().is_err() {
+        tracing::warn!("CURSOR_SECRET is not set, using the default cursor secret");
+        let _ = SECRET.set(DEFAULT_SECRET.as_bytes().to_vec());
+    }
+}
+
+fn load(secret: Option<String>) -> Result<Vec<u8>, Error> {
+    match secret {
+        Some(secret) if !secret.is_empty() => Ok(secret.into_bytes()),
+        _ => Err(Error::InitError("CURSOR_SECRET must be set")),
+    }
+}
+
+/// Signing key read by `init`
+///
+/// Entrypoints that never called `init` get an `InitError` rather than a
+/// panic.
+fn secret() -> Result<&'static [u8], Error> {
+    #[cfg(test)]
+    SECRET.get_or_init(|| DEFAULT_SECRET.as_bytes().to_vec());
+    SECRET
+        .get()
+        .map(Vec::as_slice)
+        .ok_or(Error::InitError("Cursor secret is not initialized"))
+}
+
+fn mac(secret: &[u8], scope: &str, key: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    // The length keeps the boundary between the scope and the key unambiguous
+    mac.update(&(scope.len() as u64).to_be_bytes());
+    mac.update(scope.as_bytes());
+    mac.update(key);
+    mac
+}
+
+/// Encode a store key into an opaque cursor for the listing `scope`
+///
+/// This returns an `InitError` if `init` was not called.
+pub fn encode(scope: &str, key: &str) -> Result<String, Error> {
+    Ok(encode_with(secret()?, scope, key))
+}
+
+/// Decode an opaque cursor back into a store key
+///
+/// This returns a `ClientError` if the cursor is malformed, if its
+/// signature does not match its content, or if it was issued for another
+/// scope, and an `InitError` if `init` was not called.
+pub fn decode(scope: &str, cursor: &str) -> Result<String, Error> {
+    decode_with(secret()?, scope, cursor)
+}
+
+fn encode_with(secret: &[u8], scope: &str, key: &str) -> String {
+    let signature = mac(secret, scope, key.as_bytes()).finalize().into_bytes();
+    format!(
+        "{}.{}",
+        base64::encode_config(key, base64::URL_SAFE_NO_PAD),
+        base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+fn decode_with(secret: &[u8], scope: &str, cursor: &str) -> Result<String, Error> {
+    let (key, signature) = cursor.split_once('.').ok_or_else(invalid)?;
+    let key = base64::decode_config(key, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+    let signature =
+        base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+
+    mac(secret, scope, &key)
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+
+    String::from_utf8(key).map_err(|_| invalid())
+}
+
+fn invalid() -> Error {
+    Error::ClientError("Invalid pagination cursor")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"test-secret";
+    const SCOPE: &str = "products";
+
+    #[test]
+    fn test_load() {
+        assert_eq!(load(Some("secret".to_string())).unwrap(), b"secret");
+        assert!(matches!(
+            load(Some("".to_string())),
+            Err(Error::InitError(_))
+        ));
+        assert!(matches!(load(None), Err(Error::InitError(_))));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let cursor = encode_with(SECRET, SCOPE, "product-1");
+
+        assert!(!cursor.contains("product-1"));
+        assert_eq!(decode_with(SECRET, SCOPE, &cursor).unwrap(), "product-1");
+    }
+
+    #[test]
+    fn test_decode_tampered() {
+        let cursor = encode_with(SECRET, SCOPE, "product-1");
+        let (_, signature) = cursor.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            base64::encode_config("product-2", base64::URL_SAFE_NO_PAD),
+            signature
+        );
+
+        assert!(decode_with(SECRET, SCOPE, &forged).is_err());
+    }
+
+    #[test]
+    fn test_decode_other_secret() {
+        let cursor = encode_with(b"other-secret", SCOPE, "product-1");
+
+        assert!(decode_with(SECRET, SCOPE, &cursor).is_err());
+    }
+
+    #[test]
+    fn test_decode_other_scope() {
+        let cursor = encode_with(SECRET, "category=books&sort=Name", "product-1");
+
+        assert!(decode_with(SECRET, "category=books&sort=Price", &cursor).is_err());
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert!(decode_with(SECRET, SCOPE, "").is_err());
+        assert!(decode_with(SECRET, SCOPE, "product-1").is_err());
+        assert!(decode_with(SECRET, SCOPE, "!!!.???").is_err());
+    }
+}
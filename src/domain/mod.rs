@@ -0,0 +1,959 @@
+//! Domain logic for the application.
+
+use crate::{
+    cursor,
+    error::Error,
+    event_bus::EventBus,
+    model::{
+        BatchRequest, BatchResponse, BatchResult, Event, IdempotencyRecord, MergePatch, Product,
+        ProductRange, PutOutcome, RecordedResponse,
+    },
+    store::{
+        Condition, SortKey, StoreBatchDelete, StoreBatchGet, StoreBatchPut, StoreDelete,
+        StoreExport, StoreGet, StoreGetAll, StoreIdempotency, StoreOutbox, StorePatch, StorePut,
+        StoreQuery, StoreTrash,
+    },
+};
+use chrono::{Duration, Utc};
+use futures::stream::BoxStream;
+use std::collections::{HashMap, HashSet};
+use ulid::Ulid;
+
+pub mod validation;
+use validation::ProductRules;
+
+/// Maximum number of operations in a bulk request
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Maximum number of segments read in parallel by `export_products`
+pub const MAX_SEGMENTS: u32 = 64;
+
+/// How long a response is replayed for a repeated idempotency key
+pub const IDEMPOTENCY_TTL_HOURS: i64 = 24;
+/// How long a key stays claimed by a request that has not completed
+///
+/// This is longer than the timeout of the functions, so that the key is only
+/// claimed again once a request that timed out or crashed is gone.
+pub const IDEMPOTENCY_LEASE_SECONDS: i64 = 60;
+/// Maximum length of an idempotency key
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Default number of products returned by `get_products`
+pub const DEFAULT_LIMIT: usize = 20;
+/// Maximum number of products that can be requested at once
+pub const MAX_LIMIT: usize = 100;
+
+/// Retrieve a page of products
+///
+/// `next` is an opaque cursor returned by a previous call. The cursor in the
+/// returned range is encoded the same way, so clients never see the raw
+/// store keys.
+pub async fn get_products(
+    store: &dyn StoreGetAll,
+    next: Option<&str>,
+    limit: Option<usize>,
+) -> Result<ProductRange, Error> {
+    let limit = check_limit(limit)?;
+    let next = next
+        .map(|next| cursor::decode("products", next))
+        .transpose()?;
+
+    let mut range = store.all(next.as_deref(), limit).await?;
+    range.next = range
+        .next
+        .map(|next| cursor::encode("products", &next))
+        .transpose()?;
+    Ok(range)
+}
+
+/// Retrieve a page of the products of a category, sorted by `sort`
+///
+/// Pagination works as in `get_products`, and a cursor is only accepted for
+/// the category and sort key it was returned for.
+pub async fn get_products_by_category(
+    store: &dyn StoreQuery,
+    category: &str,
+    sort: SortKey,
+    next: Option<&str>,
+    limit: Option<usize>,
+) -> Result<ProductRange, Error> {
+    let limit = check_limit(limit)?;
+    let scope = format!("category={}&sort={:?}", category, sort);
+    let next = next.map(|next| cursor::decode(&scope, next)).transpose()?;
+
+    let mut range = store
+        .by_category(category, sort, next.as_deref(), limit)
+        .await?;
+    range.next = range
+        .next
+        .map(|next| cursor::encode(&scope, &next))
+        .transpose()?;
+    Ok(range)
+}
+
+fn check_limit(limit: Option<usize>) -> Result<usize, Error> {
+    match limit {
+        Some(limit) if !(1..=MAX_LIMIT).contains(&limit) => Err(Error::Validation {
+            field: "limit".to_string(),
+            reason: format!("must be between 1 and {}", MAX_LIMIT),
+        }),
+        Some(limit) => Ok(limit),
+        None => Ok(DEFAULT_LIMIT),
+    }
+}
+
+/// Stream every product of the catalog
+///
+/// The store reads `segments` parts of the catalog in parallel. Products come
+/// in no particular order.
+pub fn export_products(
+    store: &dyn StoreExport,
+    segments: u32,
+) -> Result<BoxStream<'_, Result<Product, Error>>, Error> {
+    if !(1..=MAX_SEGMENTS).contains(&segments) {
+        return Err(Error::Validation {
+            field: "segments".to_string(),
+            reason: format!("must be between 1 and {}", MAX_SEGMENTS),
+        });
+    }
+    Ok(store.export(segments))
+}
+
+pub async fn get_product(store: &dyn StoreGet, id: &str) -> Result<Option<Product>, Error> {
+    store.get(id).await
+}
+
+/// Create or replace a product
+///
+/// The product is checked against the rules before being stored. Returns the
+/// product as stored, with its new version, and the product it replaced if
+/// there was one.
+///
+/// The timestamps given by the caller are ignored: `updated_at` is set to the
+/// current time, and so is `created_at` unless the store keeps the one of the
+/// product being replaced.
+pub async fn put_product(
+    store: &dyn StorePut,
+    rules: &ProductRules,
+    product: &Product,
+    condition: Condition,
+) -> Result<PutOutcome, Error> {
+    rules.validate(product)?;
+    store.put(&stamp(product), condition).await
+}
+
+/// Create a product with an id generated by the service
+///
+/// The id of `product` is replaced by a new ULID, which sorts by creation
+/// time and is unique without coordination. The write is conditional on the
+/// id not being taken, so a collision can never overwrite a product.
+pub async fn create_product(
+    store: &dyn StorePut,
+    rules: &ProductRules,
+    product: &Product,
+) -> Result<PutOutcome, Error> {
+    let product = Product {
+        id: Ulid::new().to_string(),
+        ..product.clone()
+    };
+    rules.validate(&product)?;
+    match store.put(&stamp(&product), Condition::NotExists).await {
+        Err(Error::PreconditionFailed(_)) => {
+            Err(Error::Conflict("Generated product id is already taken"))
+        }
+        res => res,
+    }
+}
+
+/// Partially update a product with a JSON merge patch
+///
+/// The patch cannot change the id of the product. The version, creation and
+/// deletion times are managed by the store, so they are ignored if present,
+/// and the update time is set to now. The patched product is checked against
+/// the rules before being stored.
+pub async fn patch_product(
+    store: &dyn StorePatch,
+    rules: &ProductRules,
+    id: &str,
+    patch: &MergePatch,
+    condition: Condition,
+) -> Result<PutOutcome, Error> {
+    let mut patch = patch.clone();
+    if let Some(patch_id) = patch.0.remove("id") {
+        if patch_id.as_str() != Some(id) {
+            return Err(Error::Validation {
+                field: "id".to_string(),
+                reason: "Product ID in path does not match product ID in body".to_string(),
+            });
+        }
+    }
+    patch.0.remove("version");
+    patch.0.remove("created_at");
+    patch.0.remove("deleted_at");
+    patch
+        .0
+        .insert("updated_at".to_string(), serde_json::json!(Utc::now()));
+
+    store
+        .patch(id, &patch, &|product| rules.validate(product), condition)
+        .await
+}
+
+/// Set the timestamps of a product about to be stored
+///
+/// A product is never stored as deleted: only the store deletes products.
+fn stamp(product: &Product) -> Product {
+    let now = Utc::now();
+    Product {
+        created_at: Some(now),
+        updated_at: Some(now),
+        deleted_at: None,
+        ..product.clone()
+    }
+}
+
+/// Apply a bulk request
+///
+/// Products are retrieved first, then stored, then deleted. Every operation
+/// has its own result, so that an invalid product or a failed write does not
+/// fail the others. Products to store are checked against the rules and
+/// timestamped as with `put_product`, and products to delete have the same
+/// outcomes as with `delete_product` in `strict` mode or not.
+///
+/// The whole request is rejected if it has more than `MAX_BATCH_SIZE`
+/// operations, or if an id appears more than once across `put` and
+/// `delete`.
+pub async fn batch_products<S>(
+    store: &S,
+    rules: &ProductRules,
+    strict: bool,
+    request: &BatchRequest,
+) -> Result<BatchResponse, Error>
+where
+    S: StoreBatchGet + StoreBatchPut + StoreBatchDelete + ?Sized,
+{
+    let size = request.get.len() + request.put.len() + request.delete.len();
+    if size > MAX_BATCH_SIZE {
+        return Err(Error::Validation {
+            field: "batch".to_string(),
+            reason: format!("must have at most {} operations", MAX_BATCH_SIZE),
+        });
+    }
+    let mut ids = HashSet::new();
+    let written = request.put.iter().map(|p| &p.id).chain(&request.delete);
+    for id in written {
+        if !ids.insert(id) {
+            return Err(Error::Validation {
+                field: "batch".to_string(),
+                reason: format!("product {} is written more than once", id),
+            });
+        }
+    }
+
+    let mut response = BatchResponse::default();
+
+    // Retrieve products, once per id even if it is requested more than once
+    if !request.get.is_empty() {
+        let mut ids = request.get.clone();
+        ids.sort();
+        ids.dedup();
+        let found = store
+            .get_many(&ids)
+            .await?
+            .into_iter()
+            .map(|p| (p.id.clone(), p))
+            .collect::<HashMap<_, _>>();
+        response.get = request
+            .get
+            .iter()
+            .map(|id| match found.get(id).cloned() {
+                Some(product) => BatchResult::ok(id, 200, Some(product)),
+                None => BatchResult::error(id, &Error::NotFound("Product not found")),
+            })
+            .collect();
+    }
+
+    // Store the valid products
+    let mut invalid = request
+        .put
+        .iter()
+        .map(|product| rules.validate(product).err())
+        .collect::<Vec<_>>();
+    let valid = request
+        .put
+        .iter()
+        .zip(&invalid)
+        .filter(|(_, err)| err.is_none())
+        .map(|(product, _)| stamp(product))
+        .collect::<Vec<_>>();
+    let mut outcomes = if valid.is_empty() {
+        Vec::new()
+    } else {
+        store.put_many(&valid).await?
+    }
+    .into_iter();
+    response.put = request
+        .put
+        .iter()
+        .zip(invalid.iter_mut())
+        .map(|(product, err)| match err.take() {
+            Some(err) => BatchResult::error(&product.id, &err),
+            None => match outcomes.next() {
+                Some(Ok(outcome)) => {
+                    let status = if outcome.is_created() { 201 } else { 200 };
+                    BatchResult::ok(&product.id, status, Some(outcome.product))
+                }
+                Some(Err(err)) => BatchResult::error(&product.id, &err),
+                None => BatchResult::error(&product.id, &Error::InternalError("Missing result")),
+            },
+        })
+        .collect();
+
+    // Delete products, with the same outcomes as `delete_product`
+    if !request.delete.is_empty() {
+        response.delete = request
+            .delete
+            .iter()
+            .zip(store.delete_many(&request.delete).await?)
+            .map(|(id, res)| match res {
+                Ok(Some(product)) => BatchResult::ok(id, 200, Some(product)),
+                Ok(None) if strict => BatchResult::error(id, &Error::NotFound("Product not found")),
+                Ok(None) => BatchResult::ok(id, 204, None),
+                Err(err) => BatchResult::error(id, &err),
+            })
+            .collect();
+    }
+
+    Ok(response)
+}
+
+/// Delete a product
+///
+/// Returns the deleted product. If there was no product with this id, this
+/// returns `Error::NotFound` in `strict` mode, and `None` otherwise.
+pub async fn delete_product(
+    store: &dyn StoreDelete,
+    id: &str,
+    condition: Condition,
+    strict: bool,
+) -> Result<Option<Product>, Error> {
+    match store.delete(id, condition).await? {
+        None if strict => Err(Error::NotFound("Product not found")),
+        deleted => Ok(deleted),
+    }
+}
+
+/// Retrieve a page of soft-deleted products
+///
+/// Pagination works as in `get_products`.
+pub async fn get_trash(
+    store: &dyn StoreTrash,
+    next: Option<&str>,
+    limit: Option<usize>,
+) -> Result<ProductRange, Error> {
+    let limit = check_limit(limit)?;
+    let next = next.map(|next| cursor::decode("trash", next)).transpose()?;
+
+    let mut range = store.trash(next.as_deref(), limit).await?;
+    range.next = range
+        .next
+        .map(|next| cursor::encode("trash", &next))
+        .transpose()?;
+    Ok(range)
+}
+
+/// Restore a soft-deleted product
+///
+/// Returns the restored product, or `Error::NotFound` if the product is not
+/// in the trash.
+pub async fn restore_product(store: &dyn StoreTrash, id: &str) -> Result<Product, Error> {
+    store.restore(id).await
+}
+
+/// Claim an idempotency key for a request
+///
+/// `fingerprint` identifies the request, so that a key cannot be reused for
+/// another one. Returns `None` if the request should be handled, and the
+/// recorded response if it was already handled with this key.
+///
+/// The key is claimed for `IDEMPOTENCY_LEASE_SECONDS`, after which a retry
+/// can claim it again if the request never completed.
+pub async fn claim_idempotency_key(
+    store: &dyn StoreIdempotency,
+    key: &str,
+    fingerprint: &str,
+) -> Result<Option<RecordedResponse>, Error> {
+    if key.is_empty()
+        || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH
+        || !key.chars().all(|c| c.is_ascii_graphic())
+    {
+        return Err(Error::Validation {
+            field: "Idempotency-Key".to_string(),
+            reason: format!(
+                "must be 1 to {} visible ASCII characters",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            ),
+        });
+    }
+
+    let record = IdempotencyRecord {
+        fingerprint: fingerprint.to_string(),
+        response: None,
+        expires_at: Utc::now() + Duration::seconds(IDEMPOTENCY_LEASE_SECONDS),
+    };
+    match store.claim(key, &record).await? {
+        None => Ok(None),
+        Some(current) if current.fingerprint != fingerprint => Err(Error::UnprocessableEntity(
+            "Idempotency key was already used for another request",
+        )),
+        Some(IdempotencyRecord { response: None, .. }) => Err(Error::Conflict(
+            "A request with this idempotency key is in progress",
+        )),
+        Some(IdempotencyRecord { response, .. }) => Ok(response),
+    }
+}
+
+/// Record the response of a request handled under an idempotency key
+pub async fn complete_idempotency_key(
+    store: &dyn StoreIdempotency,
+    key: &str,
+    fingerprint: &str,
+    response: RecordedResponse,
+) -> Result<(), Error> {
+    let record = IdempotencyRecord {
+        fingerprint: fingerprint.to_string(),
+        response: Some(response),
+        expires_at: Utc::now() + Duration::hours(IDEMPOTENCY_TTL_HOURS),
+    };
+    store.complete(key, &record).await
+}
+
+/// Free an idempotency key after a request failed, so that it can be retried
+pub async fn release_idempotency_key(store: &dyn StoreIdempotency, key: &str) -> Result<(), Error> {
+    store.release(key).await
+}
+
+pub async fn send_events(
+    event_bus: &dyn EventBus<E = Event>,
+    events: &[Event],
+) -> Result<(), Error> {
+    event_bus.send_events(events).await
+}
+
+/// Publish pending events from the outbox
+///
+/// Events are only removed from the outbox once the bus accepted them, so
+/// they are delivered at least once: if the relay stops between publishing
+/// and acknowledging, they are published again on the next run. Consumers
+/// can discard duplicates using the event id.
+///
+/// Returns the number of events published. If the bus rejected some events,
+/// the others are still acknowledged and the rejected ones stay pending.
+pub async fn relay_outbox(
+    outbox: &dyn StoreOutbox,
+    event_bus: &dyn EventBus<E = Event>,
+    limit: usize,
+) -> Result<usize, Error> {
+    let events = outbox.pending(limit).await?;
+    if events.is_empty() {
+        return Ok(0);
+    }
+
+    let (res, failed) = match event_bus.send_events(&events).await {
+        Ok(()) => (Ok(events.len()), Vec::new()),
+        Err(Error::PublishFailed(failed)) => {
+            let indexes = failed.iter().map(|f| f.index).collect::<Vec<_>>();
+            (Err(Error::PublishFailed(failed)), indexes)
+        }
+        Err(err) => return Err(err),
+    };
+
+    let published = events
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !failed.contains(index))
+        .map(|(_, event)| event.event_id().to_string())
+        .collect::<Vec<_>>();
+    outbox.acknowledge(&published).await?;
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::{FailedEvent, MemoryBus, VoidBus};
+    use crate::store::MemoryStore;
+    use crate::Money;
+    use async_trait::async_trait;
+
+    fn product(id: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "test".to_string(),
+            price: Money::parse("10").unwrap(),
+            ..Default::default()
+        }
+    }
+
+    /// Event bus that rejects the first event of every batch
+    struct RejectFirstBus;
+
+    #[async_trait]
+    impl EventBus for RejectFirstBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            Err(Error::PublishFailed(vec![FailedEvent {
+                index: 0,
+                id: events[0].id().to_string(),
+                code: "InternalFailure".to_string(),
+                message: "Internal failure".to_string(),
+            }]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_product_timestamps() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        let rules = ProductRules::default();
+        let created = put_product(&store, &rules, &product("1"), Condition::Any)
+            .await?
+            .product;
+        assert!(created.created_at.is_some());
+        assert_eq!(created.created_at, created.updated_at);
+
+        // WHEN replacing it with a product that has its own timestamps
+        let product = Product {
+            created_at: Some(Utc::now()),
+            updated_at: None,
+            ..product("1")
+        };
+        let updated = put_product(&store, &rules, &product, Condition::Any)
+            .await?
+            .product;
+
+        // THEN the creation time is kept and the update time is set
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at >= created.updated_at);
+        assert!(updated.updated_at.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_products_by_category_cursor() -> Result<(), Error> {
+        // GIVEN a store with two products in a category
+        let store = MemoryStore::new();
+        for id in ["1", "2"] {
+            let product = Product {
+                category: Some("books".to_string()),
+                ..product(id)
+            };
+            store.put(&product, Condition::Any).await?;
+        }
+
+        // WHEN getting the first page sorted by name
+        let page = get_products_by_category(&store, "books", SortKey::Name, None, Some(1)).await?;
+        let next = page.next.unwrap();
+
+        // THEN the cursor gets the next page of the same listing
+        let page =
+            get_products_by_category(&store, "books", SortKey::Name, Some(&next), Some(1)).await?;
+        assert_eq!(page.products.len(), 1);
+        // AND it is rejected for another sort key or category
+        let res =
+            get_products_by_category(&store, "books", SortKey::Price, Some(&next), Some(1)).await;
+        assert!(matches!(res, Err(Error::ClientError(_))));
+        let res =
+            get_products_by_category(&store, "games", SortKey::Name, Some(&next), Some(1)).await;
+        assert!(matches!(res, Err(Error::ClientError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_product() -> Result<(), Error> {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let rules = ProductRules::default();
+
+        // WHEN creating two products with the same id
+        let first = create_product(&store, &rules, &product("1")).await?;
+        let second = create_product(&store, &rules, &product("1")).await?;
+
+        // THEN each one gets a new id
+        assert!(first.is_created());
+        assert_ne!(first.product.id, "1");
+        assert_ne!(first.product.id, second.product.id);
+        // AND both are stored
+        assert!(store.get(&first.product.id).await?.is_some());
+        assert!(store.get(&second.product.id).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_product() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        let rules = ProductRules::default();
+        let created = put_product(&store, &rules, &product("1"), Condition::Any)
+            .await?
+            .product;
+
+        // WHEN patching its name along with read-only fields
+        let patch: MergePatch =
+            serde_json::from_str(r#"{"id":"1","name":"new","version":42,"created_at":null}"#)
+                .unwrap();
+        let patched = patch_product(&store, &rules, "1", &patch, Condition::Any)
+            .await?
+            .product;
+
+        // THEN only the name and the update time change
+        assert_eq!(patched.name, "new");
+        assert_eq!(patched.version, created.version + 1);
+        assert_eq!(patched.created_at, created.created_at);
+        assert!(patched.updated_at >= created.updated_at);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_product_invalid() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        let rules = ProductRules::default();
+        store.put(&product("1"), Condition::Any).await?;
+
+        // WHEN changing its id, or breaking the rules
+        let id: MergePatch = serde_json::from_str(r#"{"id":"2"}"#).unwrap();
+        let name: MergePatch = serde_json::from_str(r#"{"name":""}"#).unwrap();
+
+        // THEN the patch is rejected
+        assert!(matches!(
+            patch_product(&store, &rules, "1", &id, Condition::Any).await,
+            Err(Error::Validation { .. })
+        ));
+        assert!(matches!(
+            patch_product(&store, &rules, "1", &name, Condition::Any).await,
+            Err(Error::Violations(_))
+        ));
+        // AND the product is unchanged
+        assert_eq!(store.get("1").await?.unwrap().version, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() -> Result<(), Error> {
+        // GIVEN a store
+        let store = MemoryStore::new();
+        let response = RecordedResponse {
+            status: 201,
+            headers: Default::default(),
+            body: "{}".to_string(),
+        };
+
+        // WHEN claiming a new key
+        // THEN the request should be handled
+        assert_eq!(claim_idempotency_key(&store, "key-1", "a").await?, None);
+        // AND a retry while it is handled is rejected
+        assert!(matches!(
+            claim_idempotency_key(&store, "key-1", "a").await,
+            Err(Error::Conflict(_))
+        ));
+        // AND the key is only leased until the request should be done
+        let lease = Utc::now() + Duration::seconds(IDEMPOTENCY_LEASE_SECONDS);
+        let claimed = store
+            .claim(
+                "key-1",
+                &IdempotencyRecord {
+                    fingerprint: "a".to_string(),
+                    response: None,
+                    expires_at: Utc::now(),
+                },
+            )
+            .await?
+            .unwrap();
+        assert!(claimed.expires_at <= lease);
+
+        // WHEN the response is recorded
+        complete_idempotency_key(&store, "key-1", "a", response.clone()).await?;
+        // THEN a retry gets it back
+        assert_eq!(
+            claim_idempotency_key(&store, "key-1", "a").await?,
+            Some(response)
+        );
+        // AND the key cannot be used for another request
+        assert!(matches!(
+            claim_idempotency_key(&store, "key-1", "b").await,
+            Err(Error::UnprocessableEntity(_))
+        ));
+
+        // WHEN a key is released
+        claim_idempotency_key(&store, "key-2", "a").await?;
+        release_idempotency_key(&store, "key-2").await?;
+        // THEN it can be claimed again
+        assert_eq!(claim_idempotency_key(&store, "key-2", "a").await?, None);
+
+        // WHEN the key is invalid
+        // THEN it is rejected
+        assert!(matches!(
+            claim_idempotency_key(&store, "", "a").await,
+            Err(Error::Validation { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_product_missing() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        store.put(&product("1"), Condition::Any).await?;
+
+        // WHEN deleting it
+        let deleted = delete_product(&store, "1", Condition::Any, true).await?;
+        // THEN the deleted product is returned
+        assert_eq!(deleted.unwrap().id, "1");
+
+        // WHEN deleting it again
+        // THEN it is not found in strict mode only
+        assert!(matches!(
+            delete_product(&store, "1", Condition::Any, true).await,
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(
+            delete_product(&store, "1", Condition::Any, false).await?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trash() -> Result<(), Error> {
+        // GIVEN a store in soft-delete mode with two deleted products
+        let store = MemoryStore::new().with_soft_delete(Duration::days(30));
+        for id in ["1", "2"] {
+            store.put(&product(id), Condition::Any).await?;
+            delete_product(&store, id, Condition::Any, true).await?;
+        }
+
+        // WHEN listing the trash one product at a time
+        let first = get_trash(&store, None, Some(1)).await?;
+        let second = get_trash(&store, first.next.as_deref(), Some(1)).await?;
+
+        // THEN each page has one deleted product
+        assert_eq!(first.products[0].id, "1");
+        assert_eq!(second.products[0].id, "2");
+        assert!(second.next.is_none());
+
+        // WHEN restoring the first one
+        let restored = restore_product(&store, "1").await?;
+
+        // THEN it is back in the catalog
+        assert_eq!(get_product(&store, "1").await?, Some(restored));
+        // AND it cannot be restored twice
+        assert!(matches!(
+            restore_product(&store, "1").await,
+            Err(Error::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_product_deleted_at() -> Result<(), Error> {
+        // GIVEN a product marked as deleted
+        let store = MemoryStore::new().with_soft_delete(Duration::days(30));
+        let product = Product {
+            deleted_at: Some(Utc::now()),
+            ..product("1")
+        };
+
+        // WHEN putting it
+        put_product(&store, &ProductRules::default(), &product, Condition::Any).await?;
+
+        // THEN it is stored as a live product
+        assert!(get_product(&store, "1").await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_products() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        let rules = ProductRules::default();
+        store.put(&product("1"), Condition::Any).await?;
+
+        // WHEN applying a batch with a valid and an invalid product
+        let request = BatchRequest {
+            get: vec!["1".to_string(), "missing".to_string()],
+            put: vec![
+                product("2"),
+                Product {
+                    name: "".to_string(),
+                    ..product("3")
+                },
+            ],
+            delete: vec!["1".to_string()],
+        };
+        let res = batch_products(&store, &rules, true, &request).await?;
+
+        // THEN every operation has its own outcome
+        let statuses =
+            |results: &[BatchResult]| results.iter().map(|r| r.status).collect::<Vec<_>>();
+        assert_eq!(statuses(&res.get), vec![200, 404]);
+        assert_eq!(statuses(&res.put), vec![201, 400]);
+        assert_eq!(statuses(&res.delete), vec![200]);
+        // AND the valid product is timestamped
+        assert!(res.put[0].product.as_ref().unwrap().created_at.is_some());
+        // AND only the valid product is stored
+        assert!(store.get("2").await?.is_some());
+        assert!(store.get("3").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_products_delete_missing() -> Result<(), Error> {
+        // GIVEN a batch that deletes a missing product
+        let store = MemoryStore::new();
+        let request = BatchRequest {
+            delete: vec!["missing".to_string()],
+            ..Default::default()
+        };
+
+        // WHEN applying it in strict mode
+        let res = batch_products(&store, &ProductRules::default(), true, &request).await?;
+        // THEN the delete is not found
+        assert_eq!(res.delete[0].status, 404);
+
+        // WHEN applying it otherwise
+        let res = batch_products(&store, &ProductRules::default(), false, &request).await?;
+        // THEN the delete succeeds without content
+        assert_eq!(res.delete[0].status, 204);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_products_duplicate_get() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        store.put(&product("1"), Condition::Any).await?;
+
+        // WHEN getting it twice in a batch
+        let request = BatchRequest {
+            get: vec!["1".to_string(), "1".to_string()],
+            ..Default::default()
+        };
+        let res = batch_products(&store, &ProductRules::default(), true, &request).await?;
+
+        // THEN it is returned for both
+        assert_eq!(res.get.len(), 2);
+        for result in &res.get {
+            assert_eq!(result.status, 200);
+            assert_eq!(result.product.as_ref().unwrap().id, "1");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_products_duplicate() {
+        // GIVEN a batch that writes a product twice
+        let request = BatchRequest {
+            put: vec![product("1")],
+            delete: vec!["1".to_string()],
+            ..Default::default()
+        };
+
+        // WHEN applying it
+        let res = batch_products(
+            &MemoryStore::new(),
+            &ProductRules::default(),
+            true,
+            &request,
+        )
+        .await;
+
+        // THEN it is rejected as a whole
+        assert!(matches!(res, Err(Error::Validation { .. })));
+    }
+
+    #[test]
+    fn test_export_products_segments() {
+        let store = MemoryStore::new();
+
+        assert!(export_products(&store, 0).is_err());
+        assert!(export_products(&store, MAX_SEGMENTS + 1).is_err());
+        assert!(export_products(&store, MAX_SEGMENTS).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_relay_outbox() -> Result<(), Error> {
+        // GIVEN a store in outbox mode with two changes
+        let store = MemoryStore::with_outbox();
+        let bus = MemoryBus::new();
+        store.put(&product("1"), Condition::Any).await?;
+        store.delete("1", Condition::Any).await?;
+
+        // WHEN we relay the outbox
+        let count = relay_outbox(&store, &bus, 10).await?;
+
+        // THEN both events are published in order
+        assert_eq!(count, 2);
+        assert!(matches!(
+            bus.events()[..],
+            [Event::Created { .. }, Event::Deleted { .. }]
+        ));
+        // AND the outbox is empty
+        assert!(store.pending(10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relay_outbox_partial_failure() -> Result<(), Error> {
+        // GIVEN a store in outbox mode with two changes
+        let store = MemoryStore::with_outbox();
+        store.put(&product("1"), Condition::Any).await?;
+        store.put(&product("2"), Condition::Any).await?;
+        let events = store.pending(10).await?;
+
+        // WHEN the bus rejects the first event
+        let res = relay_outbox(&store, &RejectFirstBus, 10).await;
+
+        // THEN the relay fails
+        assert!(matches!(res, Err(Error::PublishFailed(_))));
+        // AND only the rejected event is still pending
+        assert_eq!(store.pending(10).await?, vec![events[0].clone()]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relay_outbox_error() -> Result<(), Error> {
+        // GIVEN a store in outbox mode with a change
+        let store = MemoryStore::with_outbox();
+        store.put(&product("1"), Condition::Any).await?;
+
+        // WHEN the bus fails entirely
+        let res = relay_outbox(&store, &VoidBus, 10).await;
+
+        // THEN the event is still pending
+        assert!(res.is_err());
+        assert_eq!(store.pending(10).await?.len(), 1);
+
+        Ok(())
+    }
+}
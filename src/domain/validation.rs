@@ -0,0 +1,434 @@
+//! # Product validation
+//!
+//! Rules that a product must satisfy before it is stored. All the rules are
+//! checked, so that clients get every violation in a single response rather
+//! than having to fix them one at a time.
+
+use crate::{Error, Product};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Ids that are path segments of other routes, such as `GET /trash`, and so
+/// cannot be read back as `/{id}`
+pub const RESERVED_IDS: [&str; 2] = ["batch", "trash"];
+
+/// A rule that a field of a product does not satisfy
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Violation {
+    pub field: String,
+    pub reason: String,
+}
+
+impl Violation {
+    fn new(field: &str, reason: String) -> Self {
+        Self {
+            field: field.to_owned(),
+            reason,
+        }
+    }
+}
+
+/// Validation rules for products
+#[derive(Clone, Debug)]
+pub struct ProductRules {
+    /// Maximum length of the id, in characters
+    pub id_max_length: usize,
+    /// Characters allowed in the id
+    pub id_charset: fn(char) -> bool,
+    /// Maximum length of the name, in characters
+    pub name_max_length: usize,
+    /// Minimum price, inclusive
+    pub price_min: Decimal,
+    /// Maximum price, inclusive
+    pub price_max: Decimal,
+    /// Maximum number of significant decimal places of the price
+    pub price_max_decimals: u32,
+    /// Accepted currencies, as ISO 4217 codes
+    ///
+    /// If this is empty, any well-formed currency code is accepted.
+    pub currencies: Vec<String>,
+    /// Maximum length of the description, in characters
+    pub description_max_length: usize,
+    /// Maximum length of the SKU, in characters
+    pub sku_max_length: usize,
+    /// Maximum length of the category, in characters
+    pub category_max_length: usize,
+    /// Maximum number of tags
+    pub tags_max_count: usize,
+    /// Maximum length of a tag, in characters
+    pub tag_max_length: usize,
+    /// Maximum number of image URLs
+    pub image_urls_max_count: usize,
+    /// Maximum length of an image URL, in characters
+    pub image_url_max_length: usize,
+    /// Maximum number of attributes
+    pub attributes_max_count: usize,
+    /// Maximum length of an attribute name or value, in characters
+    pub attribute_max_length: usize,
+}
+
+impl Default for ProductRules {
+    fn default() -> Self {
+        Self {
+            id_max_length: 64,
+            id_charset: |c| c.is_ascii_alphanumeric() || c == '-' || c == '_',
+            name_max_length: 256,
+            price_min: Decimal::ZERO,
+            price_max: Decimal::new(1_000_000, 0),
+            price_max_decimals: 2,
+            currencies: Vec::new(),
+            description_max_length: 4096,
+            sku_max_length: 64,
+            category_max_length: 128,
+            tags_max_count: 20,
+            tag_max_length: 64,
+            image_urls_max_count: 10,
+            image_url_max_length: 2048,
+            attributes_max_count: 50,
+            attribute_max_length: 256,
+        }
+    }
+}
+
+impl ProductRules {
+    /// Check a product against the rules
+    ///
+    /// Returns all the violations at once, as an `Error::Violations`.
+    pub fn validate(&self, product: &Product) -> Result<(), Error> {
+        let mut violations = Vec::new();
+
+        // Id
+        let id_length = product.id.chars().count();
+        if id_length == 0 {
+            violations.push(Violation::new("id", "must not be empty".to_string()));
+        } else if id_length > self.id_max_length {
+            violations.push(Violation::new(
+                "id",
+                format!("must be at most {} characters long", self.id_max_length),
+            ));
+        }
+        if !product.id.chars().all(self.id_charset) {
+            violations.push(Violation::new(
+                "id",
+                "contains characters that are not allowed".to_string(),
+            ));
+        }
+        if RESERVED_IDS.contains(&product.id.as_str()) {
+            violations.push(Violation::new("id", "is reserved".to_string()));
+        }
+
+        // Name
+        if product.name.trim().is_empty() {
+            violations.push(Violation::new("name", "must not be empty".to_string()));
+        } else if product.name.chars().count() > self.name_max_length {
+            violations.push(Violation::new(
+                "name",
+                format!("must be at most {} characters long", self.name_max_length),
+            ));
+        }
+
+        // Price
+        let amount = product.price.amount;
+        if amount < self.price_min || amount > self.price_max {
+            violations.push(Violation::new(
+                "price",
+                format!("must be between {} and {}", self.price_min, self.price_max),
+            ));
+        }
+        if amount.normalize().scale() > self.price_max_decimals {
+            violations.push(Violation::new(
+                "price",
+                format!(
+                    "must have at most {} decimal places",
+                    self.price_max_decimals
+                ),
+            ));
+        }
+
+        // Currency
+        let currency = &product.price.currency;
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            violations.push(Violation::new(
+                "currency",
+                "must be an ISO 4217 currency code".to_string(),
+            ));
+        } else if !self.currencies.is_empty() && !self.currencies.contains(currency) {
+            violations.push(Violation::new(
+                "currency",
+                format!("must be one of {}", self.currencies.join(", ")),
+            ));
+        }
+
+        // Description
+        if let Some(description) = &product.description {
+            if description.chars().count() > self.description_max_length {
+                violations.push(Violation::new(
+                    "description",
+                    format!(
+                        "must be at most {} characters long",
+                        self.description_max_length
+                    ),
+                ));
+            }
+        }
+
+        // SKU
+        if let Some(sku) = &product.sku {
+            if sku.is_empty() || sku.chars().count() > self.sku_max_length {
+                violations.push(Violation::new(
+                    "sku",
+                    format!("must be 1 to {} characters long", self.sku_max_length),
+                ));
+            }
+            if !sku.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                violations.push(Violation::new(
+                    "sku",
+                    "must only contain letters, digits and dashes".to_string(),
+                ));
+            }
+        }
+
+        // Category
+        if let Some(category) = &product.category {
+            if category.trim().is_empty() || category.chars().count() > self.category_max_length {
+                violations.push(Violation::new(
+                    "category",
+                    format!("must be 1 to {} characters long", self.category_max_length),
+                ));
+            }
+        }
+
+        // Tags
+        if product.tags.len() > self.tags_max_count {
+            violations.push(Violation::new(
+                "tags",
+                format!("must have at most {} tags", self.tags_max_count),
+            ));
+        }
+        if product
+            .tags
+            .iter()
+            .any(|tag| tag.trim().is_empty() || tag.chars().count() > self.tag_max_length)
+        {
+            violations.push(Violation::new(
+                "tags",
+                format!("must each be 1 to {} characters long", self.tag_max_length),
+            ));
+        }
+
+        // Image URLs
+        if product.image_urls.len() > self.image_urls_max_count {
+            violations.push(Violation::new(
+                "image_urls",
+                format!("must have at most {} URLs", self.image_urls_max_count),
+            ));
+        }
+        if product.image_urls.iter().any(|url| {
+            !url.starts_with("https://") || url.chars().count() > self.image_url_max_length
+        }) {
+            violations.push(Violation::new(
+                "image_urls",
+                format!(
+                    "must each be an HTTPS URL of at most {} characters",
+                    self.image_url_max_length
+                ),
+            ));
+        }
+
+        // Attributes
+        if product.attributes.len() > self.attributes_max_count {
+            violations.push(Violation::new(
+                "attributes",
+                format!("must have at most {} entries", self.attributes_max_count),
+            ));
+        }
+        if product.attributes.iter().any(|(name, value)| {
+            name.is_empty()
+                || name.chars().count() > self.attribute_max_length
+                || value.chars().count() > self.attribute_max_length
+        }) {
+            violations.push(Violation::new(
+                "attributes",
+                format!(
+                    "names and values must be at most {} characters long",
+                    self.attribute_max_length
+                ),
+            ));
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Violations(violations))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Money;
+
+    fn product() -> Product {
+        Product {
+            id: "product-1".to_string(),
+            name: "Product".to_string(),
+            price: Money::new(Decimal::new(1050, 2), "USD"),
+            ..Default::default()
+        }
+    }
+
+    fn violations(product: &Product) -> Vec<Violation> {
+        match ProductRules::default().validate(product) {
+            Ok(()) => Vec::new(),
+            Err(Error::Violations(violations)) => violations,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(violations(&product()), vec![]);
+    }
+
+    #[test]
+    fn test_invalid_id() {
+        let mut product = product();
+        product.id = "invalid id".to_string();
+        assert_eq!(
+            violations(&product),
+            vec![Violation::new(
+                "id",
+                "contains characters that are not allowed".to_string()
+            )]
+        );
+
+        product.id = "a".repeat(65);
+        assert_eq!(violations(&product)[0].field, "id");
+
+        product.id = "".to_string();
+        assert_eq!(violations(&product)[0].field, "id");
+    }
+
+    #[test]
+    fn test_reserved_id() {
+        let mut product = product();
+        for id in RESERVED_IDS {
+            product.id = id.to_string();
+            assert_eq!(
+                violations(&product),
+                vec![Violation::new("id", "is reserved".to_string())]
+            );
+        }
+
+        product.id = "trash-can".to_string();
+        assert_eq!(violations(&product), vec![]);
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        let mut product = product();
+        product.name = "  ".to_string();
+        assert_eq!(violations(&product)[0].field, "name");
+
+        product.name = "a".repeat(257);
+        assert_eq!(violations(&product)[0].field, "name");
+    }
+
+    #[test]
+    fn test_invalid_price() {
+        let mut product = product();
+        product.price.amount = Decimal::new(-1, 0);
+        assert_eq!(violations(&product)[0].field, "price");
+
+        product.price.amount = Decimal::new(30000000000000004, 17);
+        assert_eq!(violations(&product)[0].field, "price");
+    }
+
+    #[test]
+    fn test_trailing_zeros() {
+        let mut product = product();
+        product.price.amount = Decimal::new(105000, 4);
+        assert_eq!(violations(&product), vec![]);
+    }
+
+    #[test]
+    fn test_invalid_currency() {
+        let mut product = product();
+        product.price.currency = "usd".to_string();
+        assert_eq!(violations(&product)[0].field, "currency");
+
+        let rules = ProductRules {
+            currencies: vec!["EUR".to_string()],
+            ..Default::default()
+        };
+        assert!(rules.validate(&self::product()).is_err());
+    }
+
+    #[test]
+    fn test_catalog_fields() {
+        let mut product = product();
+        product.description = Some("A product".to_string());
+        product.sku = Some("SKU-1".to_string());
+        product.category = Some("books".to_string());
+        product.tags = vec!["new".to_string()];
+        product.image_urls = vec!["https://example.com/1.png".to_string()];
+        product
+            .attributes
+            .insert("color".to_string(), "red".to_string());
+        assert_eq!(violations(&product), vec![]);
+
+        product.sku = Some("SKU 1".to_string());
+        assert_eq!(violations(&product)[0].field, "sku");
+        product.sku = None;
+
+        product.category = Some(" ".to_string());
+        assert_eq!(violations(&product)[0].field, "category");
+        product.category = None;
+
+        product.tags = vec!["".to_string()];
+        assert_eq!(violations(&product)[0].field, "tags");
+        product.tags = vec!["tag".to_string(); 21];
+        assert_eq!(violations(&product)[0].field, "tags");
+        product.tags = Vec::new();
+
+        product.image_urls = vec!["http://example.com/1.png".to_string()];
+        assert_eq!(violations(&product)[0].field, "image_urls");
+        product.image_urls = Vec::new();
+
+        product
+            .attributes
+            .insert("".to_string(), "empty".to_string());
+        assert_eq!(violations(&product)[0].field, "attributes");
+    }
+
+    #[test]
+    fn test_all_violations() {
+        let product = Product {
+            id: "".to_string(),
+            name: "".to_string(),
+            price: Money::new(Decimal::new(-1, 3), "$"),
+            sku: Some("".to_string()),
+            ..Default::default()
+        };
+
+        let fields = violations(&product)
+            .into_iter()
+            .map(|v| v.field)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec!["id", "name", "price", "price", "currency", "sku"]
+        );
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let rules = ProductRules {
+            price_max_decimals: 0,
+            ..Default::default()
+        };
+
+        assert!(rules.validate(&product()).is_err());
+    }
+}
@@ -1,59 +1,93 @@
-use crate::{domain, store, Product};
+use crate::{
+    domain::{self, validation::ProductRules},
+    store,
+    store::{Condition, SortKey},
+    BatchRequest, Error, MergePatch, Product, RecordedResponse,
+};
+use futures::Future;
 use lambda_http::{
-    ext::RequestExt, lambda_runtime::Context, Body, IntoResponse, Request, Response,
+    ext::RequestExt, http::StatusCode, lambda_runtime::Context, Body, IntoResponse, Request,
+    Response,
 };
-use serde_json::json;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use tracing::{error, info, instrument, warn};
 
 type E = Box<dyn std::error::Error + Sync + Send + 'static>;
 
+/// Header carrying the idempotency key of a mutating request
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Headers that are part of the fingerprint of a request
+const PRECONDITION_HEADERS: [&str; 2] = ["If-Match", "If-None-Match"];
+
+/// Headers kept when recording a response for an idempotency key
+const REPLAYED_HEADERS: [&str; 3] = ["Content-Type", "ETag", "Location"];
+
 /// Delete a product
+///
+/// If the product existed, we return a 200 OK with the deleted product. If
+/// it did not, we return a 404 Not Found in `strict` mode, and a 204 No
+/// Content otherwise, so that deletes can be repeated safely.
+///
+/// Supports the `Idempotency-Key` header, see `idempotent`.
 #[instrument(skip(store))]
-pub async fn delete_product(
-    store: &dyn store::StoreDelete,
+pub async fn delete_product<S>(
+    store: &S,
+    strict: bool,
     event: Request,
     _: Context,
-) -> Result<impl IntoResponse, E> {
+) -> Result<impl IntoResponse, E>
+where
+    S: store::StoreDelete + store::StoreIdempotency,
+{
+    Ok(idempotent(store, &event, || {
+        handle_delete_product(store, strict, &event)
+    })
+    .await)
+}
+
+async fn handle_delete_product(
+    store: &dyn store::StoreDelete,
+    strict: bool,
+    event: &Request,
+) -> Response<String> {
     // Retrieve product ID from event
     //
     // If the event doesn't contain a product ID, we return a 400 Bad Request.
     let path_parameters = event.path_parameters();
     let id = match path_parameters.get("id") {
         Some(id) => id,
-        None => {
-            warn!("Missing 'id' parameter in path");
-            return Ok(response(
-                400,
-                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
-            ));
-        }
+        None => return error_response(&Error::ClientError("Missing 'id' parameter in path")),
+    };
+
+    // Retrieve precondition from headers
+    let condition = match parse_condition(event) {
+        Ok(condition) => condition,
+        Err(err) => return error_response(&err),
     };
 
     // Delete product
     info!("Deleting product {}", id);
-    let res = domain::delete_product(store, id).await;
+    let res = domain::delete_product(store, id, condition, strict).await;
 
     // Return response
     //
-    // The service returns a Result based on the success of the operation. If
-    // the operation was successful, the Result is Ok(()), otherwise it will
-    // contain an Err with the reason.
+    // The service returns the deleted product, if there was one. Otherwise,
+    // it will contain an Err with the reason.
     match res {
-        Ok(_) => {
+        Ok(Some(product)) => {
             info!("Product {} deleted", id);
-            Ok(response(
+            response(
                 200,
-                json!({"message": "Product deleted"}).to_string(),
-            ))
+                json!({"message": "Product deleted", "product": product}).to_string(),
+            )
         }
-        Err(err) => {
-            // Log the error message
-            error!("Error deleting the product {}: {}", id, err);
-            Ok(response(
-                500,
-                json!({"message": "Failed to delete product"}).to_string(),
-            ))
+        Ok(None) => {
+            info!("Product {} did not exist", id);
+            Response::builder().status(204).body(String::new()).unwrap()
         }
+        Err(err) => error_response(&err),
     }
 }
 
@@ -71,11 +105,9 @@ pub async fn get_product(
     let id = match path_parameters.get("id") {
         Some(id) => id,
         None => {
-            warn!("Missing 'id' parameter in path");
-            return Ok(response(
-                400,
-                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
-            ));
+            return Ok(error_response(&Error::ClientError(
+                "Missing 'id' parameter in path",
+            )))
         }
     };
 
@@ -90,90 +122,123 @@ pub async fn get_product(
     // an error.
     Ok(match product {
         // Product exists
-        Ok(Some(product)) => response(200, json!(product).to_string()),
+        Ok(Some(product)) => response_with_etag(200, json!(product).to_string(), product.version),
         // Product doesn't exist
-        Ok(None) => {
-            warn!("Product not found: {}", id);
-            response(404, json!({"message": "Product not found"}).to_string())
-        }
+        Ok(None) => error_response(&Error::NotFound("Product not found")),
         // Error
-        Err(err) => {
-            error!("Error fetching product: {}", err);
-            response(
-                500,
-                json!({"message": "Error fetching product"}).to_string(),
-            )
-        }
+        Err(err) => error_response(&err),
     })
 }
 
 /// Retrieve products
+///
+/// Supports pagination through the `next` and `limit` query parameters. The
+/// `next` value is an opaque cursor taken from a previous response.
+///
+/// With a `category` query parameter, only the products of that category are
+/// returned, sorted by the `sort` query parameter (`name` or `price`).
 #[instrument(skip(store))]
-pub async fn get_products(
-    store: &dyn store::StoreGetAll,
-    _event: Request,
-    _: Context,
-) -> Result<impl IntoResponse, E> {
+pub async fn get_products<S>(store: &S, event: Request, _: Context) -> Result<impl IntoResponse, E>
+where
+    S: store::StoreGetAll + store::StoreQuery,
+{
+    // Retrieve pagination parameters from the query string
+    //
+    // If the limit is not a valid number, we return a 400 Bad Request.
+    let query_parameters = event.query_string_parameters();
+    let next = query_parameters.get("next");
+    let limit = match query_parameters.get("limit").map(str::parse::<usize>) {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(_)) => {
+            return Ok(error_response(&Error::Validation {
+                field: "limit".to_string(),
+                reason: "must be a positive integer".to_string(),
+            }))
+        }
+        None => None,
+    };
+
+    // Retrieve the filter, if any
+    let sort = match query_parameters.get("sort").map(str::parse::<SortKey>) {
+        Some(Ok(sort)) => Some(sort),
+        Some(Err(_)) => {
+            return Ok(error_response(&Error::Validation {
+                field: "sort".to_string(),
+                reason: "must be 'name' or 'price'".to_string(),
+            }))
+        }
+        None => None,
+    };
+
     // Retrieve products
-    // TODO: Add pagination
-    let res = domain::get_products(store, None).await;
+    let res = match (query_parameters.get("category"), sort) {
+        (Some(category), sort) => {
+            domain::get_products_by_category(store, category, sort.unwrap_or_default(), next, limit)
+                .await
+        }
+        (None, Some(_)) => {
+            return Ok(error_response(&Error::Validation {
+                field: "sort".to_string(),
+                reason: "can only be used with a category".to_string(),
+            }))
+        }
+        (None, None) => domain::get_products(store, next, limit).await,
+    };
 
     // Return response
     Ok(match res {
         // Return a list of products
         Ok(res) => response(200, json!(res).to_string()),
         // Return an error
-        Err(err) => {
-            error!("Something went wrong: {:?}", err);
-            response(
-                500,
-                json!({ "message": format!("Something went wrong: {:?}", err) }).to_string(),
-            )
-        }
+        Err(err) => error_response(&err),
     })
 }
 
 /// Put a product
-#[instrument(skip(store))]
-pub async fn put_product(
-    store: &dyn store::StorePut,
+///
+/// The product is validated against `rules`: if it breaks any of them, we
+/// return a 400 Bad Request listing every invalid field.
+///
+/// Supports the `Idempotency-Key` header, see `idempotent`.
+#[instrument(skip(store, rules))]
+pub async fn put_product<S>(
+    store: &S,
+    rules: &ProductRules,
     event: Request,
     _: Context,
-) -> Result<impl IntoResponse, E> {
+) -> Result<impl IntoResponse, E>
+where
+    S: store::StorePut + store::StoreIdempotency,
+{
+    Ok(idempotent(store, &event, || handle_put_product(store, rules, &event)).await)
+}
+
+async fn handle_put_product(
+    store: &dyn store::StorePut,
+    rules: &ProductRules,
+    event: &Request,
+) -> Response<String> {
     // Retrieve product ID from event.
     //
     // If the event doesn't contain a product ID, we return a 400 Bad Request.
     let path_parameters = event.path_parameters();
     let id = match path_parameters.get("id") {
         Some(id) => id,
-        None => {
-            warn!("Missing 'id' parameter in path");
-            return Ok(response(
-                400,
-                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
-            ));
-        }
+        None => return error_response(&Error::ClientError("Missing 'id' parameter in path")),
     };
 
     // Read product from request
     let product_res: Result<Product, serde_json::Error> = match event.body() {
         Body::Text(body) => serde_json::from_str(body),
         Body::Binary(body) => serde_json::from_slice(body),
-        _ => {
-            warn!("Empty request body");
-            return Ok(response(
-                400,
-                json!({"message": "Empty request body"}).to_string(),
-            ));
-        }
+        _ => return error_response(&Error::ClientError("Empty request body")),
     };
     let product = match product_res {
         Ok(product) => product,
         Err(err) => {
             warn!("Failed to parse product from request body: {}", err);
-            return Ok(response(
-                400,
-                json!({"message": "Failed to parse product from request body"}).to_string(),
+            return error_response(&Error::ClientError(
+                "Failed to parse product from request body",
             ));
         }
     };
@@ -185,36 +250,468 @@ pub async fn put_product(
             "Product ID in path ({}) does not match product ID in body ({})",
             id, product.id
         );
-        return Ok(response(
-            400,
-            json!({"message": "Product ID in path does not match product ID in body"}).to_string(),
-        ));
+        return error_response(&Error::Validation {
+            field: "id".to_string(),
+            reason: "Product ID in path does not match product ID in body".to_string(),
+        });
     }
 
+    // Retrieve precondition from headers
+    let condition = match parse_condition(event) {
+        Ok(condition) => condition,
+        Err(err) => return error_response(&err),
+    };
+
     // Put product
-    let res = domain::put_product(store, &product).await;
+    let res = domain::put_product(store, rules, &product, condition).await;
 
     // Return response
     //
-    // If the put was successful, we return a 201 Created. Otherwise, we return
-    // a 500 Internal Server Error.
-    Ok(match res {
+    // If the put created the product, we return a 201 Created, and if it
+    // replaced an existing product, a 200 OK, both with the new ETag.
+    // Otherwise, we return the status code matching the error.
+    match res {
         // Product created
-        Ok(_) => {
-            info!("Created product {:?}", product.id);
-            response(201, json!({"message": "Product created"}).to_string())
+        Ok(outcome) if outcome.is_created() => {
+            info!("Created product {:?}", outcome.product.id);
+            response_with_etag(
+                201,
+                json!({"message": "Product created"}).to_string(),
+                outcome.product.version,
+            )
+        }
+        // Product updated
+        Ok(outcome) => {
+            info!("Updated product {:?}", outcome.product.id);
+            response_with_etag(
+                200,
+                json!({"message": "Product updated"}).to_string(),
+                outcome.product.version,
+            )
         }
         // Error creating product
+        Err(err) => error_response(&err),
+    }
+}
+
+/// Create a product
+///
+/// The service generates the id of the product, so the body must not have
+/// one. If the product is created, we return a 201 Created with the product,
+/// its ETag, and its URL in the `Location` header.
+#[instrument(skip(store, rules))]
+pub async fn create_product(
+    store: &dyn store::StorePut,
+    rules: &ProductRules,
+    event: Request,
+    _: Context,
+) -> Result<impl IntoResponse, E> {
+    // Read product fields from request
+    let fields_res: Result<serde_json::Map<String, Value>, serde_json::Error> = match event.body() {
+        Body::Text(body) => serde_json::from_str(body),
+        Body::Binary(body) => serde_json::from_slice(body),
+        _ => return Ok(error_response(&Error::ClientError("Empty request body"))),
+    };
+    let mut fields = match fields_res {
+        Ok(fields) => fields,
         Err(err) => {
-            error!("Failed to create product {}: {}", product.id, err);
-            response(
-                500,
-                json!({"message": "Failed to create product"}).to_string(),
+            warn!("Failed to parse product from request body: {}", err);
+            return Ok(error_response(&Error::ClientError(
+                "Failed to parse product from request body",
+            )));
+        }
+    };
+    if fields.contains_key("id") {
+        return Ok(error_response(&Error::Validation {
+            field: "id".to_string(),
+            reason: "Product ID is generated by the service".to_string(),
+        }));
+    }
+
+    // The id is replaced by the domain layer
+    fields.insert("id".to_string(), Value::String(String::new()));
+    let product: Product = match serde_json::from_value(Value::Object(fields)) {
+        Ok(product) => product,
+        Err(err) => {
+            warn!("Failed to parse product from request body: {}", err);
+            return Ok(error_response(&Error::ClientError(
+                "Failed to parse product from request body",
+            )));
+        }
+    };
+
+    // Create product
+    let res = domain::create_product(store, rules, &product).await;
+
+    // Return response
+    Ok(match res {
+        Ok(outcome) => {
+            info!("Created product {:?}", outcome.product.id);
+            let mut res = response_with_etag(
+                201,
+                json!(outcome.product).to_string(),
+                outcome.product.version,
+            );
+            res.headers_mut().insert(
+                "Location",
+                format!("/{}", outcome.product.id).parse().unwrap(),
+            );
+            res
+        }
+        Err(err) => error_response(&err),
+    })
+}
+
+/// Partially update a product
+///
+/// The body is a JSON merge patch (RFC 7386), sent as
+/// `application/merge-patch+json`. If the update succeeds, we return a 200 OK
+/// with the updated product and its new ETag.
+#[instrument(skip(store, rules))]
+pub async fn patch_product(
+    store: &dyn store::StorePatch,
+    rules: &ProductRules,
+    event: Request,
+    _: Context,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.get("id") {
+        Some(id) => id,
+        None => {
+            return Ok(error_response(&Error::ClientError(
+                "Missing 'id' parameter in path",
+            )))
+        }
+    };
+
+    // Check the media type of the patch
+    //
+    // Plain JSON is accepted as well, since the patch is a JSON object either
+    // way.
+    let content_type = event
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim);
+    if !matches!(
+        content_type,
+        Some("application/merge-patch+json") | Some("application/json")
+    ) {
+        return Ok(error_response(&Error::UnsupportedMediaType(
+            "Patch must be sent as application/merge-patch+json",
+        )));
+    }
+
+    // Read patch from request
+    let patch_res: Result<MergePatch, serde_json::Error> = match event.body() {
+        Body::Text(body) => serde_json::from_str(body),
+        Body::Binary(body) => serde_json::from_slice(body),
+        _ => return Ok(error_response(&Error::ClientError("Empty request body"))),
+    };
+    let patch = match patch_res {
+        Ok(patch) => patch,
+        Err(err) => {
+            warn!("Failed to parse patch from request body: {}", err);
+            return Ok(error_response(&Error::ClientError(
+                "Failed to parse patch from request body",
+            )));
+        }
+    };
+
+    // Retrieve precondition from headers
+    let condition = match parse_condition(&event) {
+        Ok(condition) => condition,
+        Err(err) => return Ok(error_response(&err)),
+    };
+
+    // Patch product
+    let res = domain::patch_product(store, rules, id, &patch, condition).await;
+
+    // Return response
+    Ok(match res {
+        Ok(outcome) => {
+            info!("Patched product {:?}", outcome.product.id);
+            response_with_etag(
+                200,
+                json!(outcome.product).to_string(),
+                outcome.product.version,
             )
         }
+        Err(err) => error_response(&err),
+    })
+}
+
+/// Restore a soft-deleted product
+///
+/// If the product is in the trash, we return a 200 OK with the restored
+/// product and its new ETag, and a 404 Not Found otherwise.
+#[instrument(skip(store))]
+pub async fn restore_product(
+    store: &dyn store::StoreTrash,
+    event: Request,
+    _: Context,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve product ID from event.
+    //
+    // If the event doesn't contain a product ID, we return a 400 Bad Request.
+    let path_parameters = event.path_parameters();
+    let id = match path_parameters.get("id") {
+        Some(id) => id,
+        None => {
+            return Ok(error_response(&Error::ClientError(
+                "Missing 'id' parameter in path",
+            )))
+        }
+    };
+
+    // Restore product
+    info!("Restoring product {}", id);
+    let res = domain::restore_product(store, id).await;
+
+    // Return response
+    Ok(match res {
+        Ok(product) => {
+            info!("Product {} restored", id);
+            response_with_etag(200, json!(product).to_string(), product.version)
+        }
+        Err(err) => error_response(&err),
+    })
+}
+
+/// Retrieve soft-deleted products
+///
+/// Supports pagination through the `next` and `limit` query parameters, as
+/// with `get_products`.
+#[instrument(skip(store))]
+pub async fn get_trash(
+    store: &dyn store::StoreTrash,
+    event: Request,
+    _: Context,
+) -> Result<impl IntoResponse, E> {
+    // Retrieve pagination parameters from the query string
+    //
+    // If the limit is not a valid number, we return a 400 Bad Request.
+    let query_parameters = event.query_string_parameters();
+    let next = query_parameters.get("next");
+    let limit = match query_parameters.get("limit").map(str::parse::<usize>) {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(_)) => {
+            return Ok(error_response(&Error::Validation {
+                field: "limit".to_string(),
+                reason: "must be a positive integer".to_string(),
+            }))
+        }
+        None => None,
+    };
+
+    // Retrieve products
+    let res = domain::get_trash(store, next, limit).await;
+
+    // Return response
+    Ok(match res {
+        Ok(res) => response(200, json!(res).to_string()),
+        Err(err) => error_response(&err),
+    })
+}
+
+/// Apply a bulk request
+///
+/// The body lists the ids to `get`, the products to `put` and the ids to
+/// `delete`. Unless the request as a whole is invalid, we return a 200 OK with
+/// the outcome of every operation, each with its own status code. Deletes of
+/// missing products are reported as in `delete_product`.
+#[instrument(skip(store, rules))]
+pub async fn batch_products<S>(
+    store: &S,
+    rules: &ProductRules,
+    strict: bool,
+    event: Request,
+    _: Context,
+) -> Result<impl IntoResponse, E>
+where
+    S: store::StoreBatchGet + store::StoreBatchPut + store::StoreBatchDelete,
+{
+    // Read operations from request
+    let request_res: Result<BatchRequest, serde_json::Error> = match event.body() {
+        Body::Text(body) => serde_json::from_str(body),
+        Body::Binary(body) => serde_json::from_slice(body),
+        _ => return Ok(error_response(&Error::ClientError("Empty request body"))),
+    };
+    let request = match request_res {
+        Ok(request) => request,
+        Err(err) => {
+            warn!("Failed to parse operations from request body: {}", err);
+            return Ok(error_response(&Error::ClientError(
+                "Failed to parse operations from request body",
+            )));
+        }
+    };
+
+    // Apply operations
+    let res = domain::batch_products(store, rules, strict, &request).await;
+
+    // Return response
+    Ok(match res {
+        Ok(res) => response(200, json!(res).to_string()),
+        Err(err) => error_response(&err),
     })
 }
 
+/// Handle a mutating request at most once per `Idempotency-Key`
+///
+/// Without the header, the handler always runs. With it, the first request
+/// claims the key and its response is recorded, so that retries with the
+/// same key get that response back without running the handler again. A key
+/// cannot be reused for a request with another method, path, preconditions or
+/// body.
+///
+/// Server errors and throttled requests are not recorded, so that the
+/// request can be retried under the same key.
+async fn idempotent<S, F, Fut>(store: &S, event: &Request, handler: F) -> Response<String>
+where
+    S: store::StoreIdempotency,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Response<String>>,
+{
+    let key = match event.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) => match key.to_str() {
+            Ok(key) => key,
+            Err(_) => return error_response(&Error::ClientError("Invalid Idempotency-Key header")),
+        },
+        None => return handler().await,
+    };
+
+    // Claim the key, or replay the response recorded for it
+    let fingerprint = fingerprint(event);
+    match domain::claim_idempotency_key(store, key, &fingerprint).await {
+        Ok(None) => (),
+        Ok(Some(recorded)) => {
+            info!("Replaying response for idempotency key {}", key);
+            return replay(recorded);
+        }
+        Err(err) => return error_response(&err),
+    }
+
+    // Handle the request and record its response
+    let res = handler().await;
+    let recorded =
+        if res.status().is_server_error() || res.status() == StatusCode::TOO_MANY_REQUESTS {
+            domain::release_idempotency_key(store, key).await
+        } else {
+            domain::complete_idempotency_key(store, key, &fingerprint, record(&res)).await
+        };
+    if let Err(err) = recorded {
+        // The request was handled, so its response is still returned
+        error!("Failed to record idempotency key {}: {}", key, err);
+    }
+    res
+}
+
+/// Hash of the method, path, precondition headers and body of a request
+fn fingerprint(event: &Request) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(event.method().as_str());
+    hasher.update(b" ");
+    hasher.update(event.uri().path());
+    hasher.update(b"\n");
+    for name in PRECONDITION_HEADERS {
+        if let Some(value) = event.headers().get(name) {
+            hasher.update(name);
+            hasher.update(b": ");
+            hasher.update(value.as_bytes());
+            hasher.update(b"\n");
+        }
+    }
+    hasher.update(b"\n");
+    hasher.update(event.body().as_ref());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Keep what is needed to replay a response
+fn record(res: &Response<String>) -> RecordedResponse {
+    RecordedResponse {
+        status: res.status().as_u16(),
+        headers: REPLAYED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = res.headers().get(*name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect(),
+        body: res.body().clone(),
+    }
+}
+
+/// Rebuild a recorded response
+///
+/// The `Idempotent-Replayed` header tells the client that the request was
+/// not applied again.
+fn replay(recorded: RecordedResponse) -> Response<String> {
+    let mut builder = Response::builder()
+        .status(recorded.status)
+        .header("Idempotent-Replayed", "true");
+    for (name, value) in &recorded.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder.body(recorded.body).unwrap()
+}
+
+/// Parse the `If-Match` and `If-None-Match` headers into a store condition
+///
+/// Both headers accept either `*` or a single ETag, as returned by
+/// `get_product` and `put_product`.
+fn parse_condition(event: &Request) -> Result<Condition, Error> {
+    let header = |name| {
+        event
+            .headers()
+            .get(name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| Error::ClientError("Invalid precondition header"))
+            })
+            .transpose()
+    };
+
+    match (header("If-Match")?, header("If-None-Match")?) {
+        (None, None) => Ok(Condition::Any),
+        (Some(_), Some(_)) => Err(Error::ClientError(
+            "Only one of If-Match and If-None-Match can be set",
+        )),
+        (Some("*"), None) => Ok(Condition::Exists),
+        (Some(etag), None) => parse_etag(etag)
+            .map(Condition::Version)
+            .ok_or(Error::ClientError("Invalid If-Match header")),
+        (None, Some("*")) => Ok(Condition::NotExists),
+        (None, Some(etag)) => parse_etag(etag)
+            .map(Condition::NotVersion)
+            .ok_or(Error::ClientError("Invalid If-None-Match header")),
+    }
+}
+
+/// Format a product version as an ETag
+fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Parse an ETag into a product version
+///
+/// Weak ETags are accepted, as product versions are always compared as a
+/// whole.
+fn parse_etag(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let value = value.strip_prefix("W/").unwrap_or(value);
+    value
+        .strip_prefix('"')?
+        .strip_suffix('"')?
+        .parse::<u64>()
+        .ok()
+}
+
 /// HTTP Response with a JSON payload
 fn response(status_code: u16, body: String) -> Response<String> {
     Response::builder()
@@ -223,3 +720,110 @@ fn response(status_code: u16, body: String) -> Response<String> {
         .body(body)
         .unwrap()
 }
+
+/// HTTP Response describing an error
+///
+/// The body is an RFC 7807 problem document. Validation errors also list the
+/// offending fields in the `invalid-params` extension member.
+fn error_response(err: &Error) -> Response<String> {
+    let status_code = err.status_code();
+    if status_code >= 500 {
+        error!("Request failed: {}", err);
+    } else {
+        warn!("Request failed: {}", err);
+    }
+
+    let mut problem = json!({
+        "type": "about:blank",
+        "title": StatusCode::from_u16(status_code)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or("Unknown Error"),
+        "status": status_code,
+        "detail": err.detail(),
+    });
+    match err {
+        Error::Validation { field, reason } => {
+            problem["invalid-params"] = json!([{ "name": field, "reason": reason }]);
+        }
+        Error::Violations(violations) => {
+            problem["invalid-params"] = violations
+                .iter()
+                .map(|v| json!({ "name": v.field, "reason": v.reason }))
+                .collect();
+        }
+        _ => (),
+    }
+
+    Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/problem+json")
+        .body(problem.to_string())
+        .unwrap()
+}
+
+/// HTTP Response with a JSON payload and the ETag of a product version
+fn response_with_etag(status_code: u16, body: String, version: u64) -> Response<String> {
+    Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/json")
+        .header("ETag", etag(version))
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use lambda_http::http;
+
+    fn put(body: &str) -> Request {
+        http::Request::builder()
+            .method("PUT")
+            .uri("/product-1")
+            .header(IDEMPOTENCY_KEY_HEADER, "key-1")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fingerprint_preconditions() {
+        // GIVEN the same request with and without a precondition
+        let body = r#"{"id":"product-1","name":"test","price":"10.5"}"#;
+        let mut conditional = put(body);
+        conditional
+            .headers_mut()
+            .insert("If-Match", http::HeaderValue::from_static("\"1\""));
+
+        // WHEN we fingerprint them
+        // THEN they differ
+        assert_eq!(fingerprint(&put(body)), fingerprint(&put(body)));
+        assert_ne!(fingerprint(&put(body)), fingerprint(&conditional));
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_throttled() {
+        // GIVEN a request with an idempotency key
+        let store = MemoryStore::new();
+        let event = put(r#"{"id":"product-1","name":"test","price":"10.5"}"#);
+
+        // WHEN the request is throttled
+        let res = idempotent(&store, &event, || async {
+            error_response(&Error::Throttled("test".to_string()))
+        })
+        .await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // THEN it can be retried under the same key
+        let res = idempotent(&store, &event, || async {
+            Response::builder()
+                .status(StatusCode::CREATED)
+                .body(String::new())
+                .unwrap()
+        })
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(res.headers().get("Idempotent-Replayed").is_none());
+    }
+}
@@ -1,29 +1,356 @@
-use crate::{domain, event_bus::EventBus, Event};
+use crate::{domain, event_bus::EventBus, Error, Event};
 use lambda_runtime::Context;
 use rayon::prelude::*;
-use tracing::{info, instrument};
+use tracing::{error, info, instrument};
 
 pub mod model;
 
 type E = Box<dyn std::error::Error + Sync + Send + 'static>;
 
+/// Maximum number of events published at once
+///
+/// This matches the limit of a single EventBridge `put_events()` request.
+const MAX_PUBLISH_BATCH_SIZE: usize = 10;
+
+/// Maximum number of chunks of events published in a single invocation
+///
+/// Chunks are published one after the other, so this bounds the time spent
+/// publishing to stay well within the function timeout.
+const MAX_PUBLISH_CHUNKS: usize = 10;
+
 /// Parse events from DynamoDB Streams
+///
+/// Records are processed in order. If a record cannot be converted or its
+/// event cannot be published, we report it as a batch item failure so that
+/// Lambda retries the batch from that record onwards, while the records
+/// before it are checkpointed and never sent again.
+///
+/// Events are published in order, in chunks of `MAX_PUBLISH_BATCH_SIZE`, and
+/// we stop at the first chunk with a failure. Only the events of that chunk
+/// may already have been published and be sent again on retry.
+///
+/// At most `MAX_PUBLISH_CHUNKS` chunks are published per invocation. The
+/// records after them are reported as failed, so that Lambda sends them
+/// again in the next invocation.
 #[instrument(skip(event_bus, event))]
 pub async fn parse_events(
     event_bus: &dyn EventBus<E = Event>,
     event: model::DynamoDBEvent,
     _: Context,
-) -> Result<(), E> {
+) -> Result<model::DynamoDBEventResponse, E> {
     info!("Transform events");
-    let events = event
+    let results = event
         .records
         .par_iter()
         .map(|record| record.try_into())
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect::<Vec<Result<Event, Error>>>();
+
+    // Only publish the events up to the first record that failed to convert
+    //
+    // Updates that did not change any field are not published, so we keep
+    // the position of the record of each event to report failures.
+    let mut events = Vec::with_capacity(results.len());
+    let mut record_indices = Vec::with_capacity(results.len());
+    let mut first_failure = None;
+    for (index, res) in results.into_iter().enumerate() {
+        match res {
+            Ok(event) if event.is_noop() => {
+                info!("Skipping update of {} without changes", event.id());
+            }
+            Ok(event) => {
+                events.push(event);
+                record_indices.push(index);
+            }
+            Err(err) => {
+                error!("Failed to transform record {}: {}", index, err);
+                first_failure = Some(index);
+                break;
+            }
+        }
+    }
+
+    // Leave the events past the limit for the next invocation
+    let max_events = MAX_PUBLISH_CHUNKS * MAX_PUBLISH_BATCH_SIZE;
+    if events.len() > max_events {
+        info!(
+            "Deferring {} events to the next invocation",
+            events.len() - max_events
+        );
+        first_failure = Some(record_indices[max_events]);
+        events.truncate(max_events);
+        record_indices.truncate(max_events);
+    }
 
     info!("Dispatching {} events", events.len());
-    domain::send_events(event_bus, &events).await?;
+    let chunks = events
+        .chunks(MAX_PUBLISH_BATCH_SIZE)
+        .zip(record_indices.chunks(MAX_PUBLISH_BATCH_SIZE));
+    for (events, record_indices) in chunks {
+        match domain::send_events(event_bus, events).await {
+            Ok(()) => (),
+            Err(Error::PublishFailed(failed)) => {
+                error!("Failed to publish {} events", failed.len());
+                first_failure = Some(
+                    failed
+                        .iter()
+                        .map(|f| record_indices[f.index])
+                        .min()
+                        .unwrap_or(record_indices[0]),
+                );
+                break;
+            }
+            Err(err) => {
+                error!("Failed to publish events: {}", err);
+                first_failure = Some(record_indices[0]);
+                break;
+            }
+        }
+    }
     info!("Done dispatching events");
 
-    Ok(())
+    Ok(model::DynamoDBEventResponse {
+        batch_item_failures: first_failure
+            .map(|index| model::BatchItemFailure {
+                item_identifier: event.records[index].dynamodb.sequence_number.clone(),
+            })
+            .into_iter()
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::{FailedEvent, MemoryBus};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Event bus that records events and rejects the ones at given positions
+    #[derive(Default)]
+    struct TestBus {
+        reject: Vec<usize>,
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EventBus for TestBus {
+        type E = Event;
+
+        async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+            self.send_events(std::slice::from_ref(event)).await
+        }
+
+        async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+            let mut failed = Vec::new();
+            for (index, event) in events.iter().enumerate() {
+                if self.reject.contains(&index) {
+                    failed.push(FailedEvent {
+                        index,
+                        id: event.id().to_string(),
+                        code: "InternalFailure".to_string(),
+                        message: "Internal failure".to_string(),
+                    });
+                } else {
+                    self.sent.lock().unwrap().push(event.id().to_string());
+                }
+            }
+            if failed.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::PublishFailed(failed))
+            }
+        }
+    }
+
+    fn record(sequence_number: &str, id: &str) -> String {
+        format!(
+            r#"{{
+                "eventID": "{seq}",
+                "eventVersion": "1.1",
+                "eventName": "INSERT",
+                "eventSource": "aws:dynamodb",
+                "eventSourceARN": "someARN",
+                "awsRegion": "us-west-2",
+                "dynamodb": {{
+                    "Keys": {{"id": {{"S": "{id}"}}}},
+                    "NewImage": {{"id": {{"S": "{id}"}}, "name": {{"S": "name"}}, "price": {{"N": "1.5"}}}},
+                    "SequenceNumber": "{seq}",
+                    "SizeBytes": 26,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES"
+                }}
+            }}"#,
+            seq = sequence_number,
+            id = id
+        )
+    }
+
+    /// Record of an update from version 1 to 2 of a product
+    fn modify_record(sequence_number: &str, id: &str, old_name: &str, new_name: &str) -> String {
+        let image = |name: &str, version: u32| {
+            format!(
+                r#"{{"id": {{"S": "{}"}}, "name": {{"S": "{}"}}, "price": {{"N": "1.5"}}, "version": {{"N": "{}"}}}}"#,
+                id, name, version
+            )
+        };
+        record(sequence_number, id)
+            .replace("INSERT", "MODIFY")
+            .replace(
+                &format!(
+                    r#""NewImage": {{"id": {{"S": "{id}"}}, "name": {{"S": "name"}}, "price": {{"N": "1.5"}}}}"#,
+                    id = id
+                ),
+                &format!(
+                    r#""OldImage": {}, "NewImage": {}"#,
+                    image(old_name, 1),
+                    image(new_name, 2)
+                ),
+            )
+    }
+
+    fn ddb_event(records: &[String]) -> model::DynamoDBEvent {
+        serde_json::from_str(&format!(r#"{{"Records": [{}]}}"#, records.join(","))).unwrap()
+    }
+
+    fn failures(response: &model::DynamoDBEventResponse) -> Vec<&str> {
+        response
+            .batch_item_failures
+            .iter()
+            .map(|f| f.item_identifier.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_parse_events() -> Result<(), E> {
+        // GIVEN a batch of valid records
+        let bus = MemoryBus::new();
+        let event = ddb_event(&[record("1", "a"), record("2", "b")]);
+
+        // WHEN we parse the events
+        let res = parse_events(&bus, event, Context::default()).await?;
+
+        // THEN no records are reported as failed
+        assert_eq!(res, model::DynamoDBEventResponse::default());
+        // AND a created event is published for each record
+        let events = bus.wait_for(2, Duration::from_secs(1)).await?;
+        let ids = events.iter().map(|e| e.id()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(bus.created().len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_events_invalid_record() -> Result<(), E> {
+        // GIVEN a batch where the second record is missing its price
+        let bus = TestBus::default();
+        let invalid = record("2", "b").replace(r#""price": {"N": "1.5"}"#, r#""foo": {"N": "1"}"#);
+        let event = ddb_event(&[record("1", "a"), invalid, record("3", "c")]);
+
+        // WHEN we parse the events
+        let res = parse_events(&bus, event, Context::default()).await?;
+
+        // THEN the invalid record is reported
+        assert_eq!(failures(&res), vec!["2"]);
+        // AND only the records before it are published
+        assert_eq!(*bus.sent.lock().unwrap(), vec!["a"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_events_publish_failure() -> Result<(), E> {
+        // GIVEN an event bus that rejects the second and third events
+        let bus = TestBus {
+            reject: vec![2, 1],
+            ..Default::default()
+        };
+        let event = ddb_event(&[record("1", "a"), record("2", "b"), record("3", "c")]);
+
+        // WHEN we parse the events
+        let res = parse_events(&bus, event, Context::default()).await?;
+
+        // THEN the first rejected record is reported
+        assert_eq!(failures(&res), vec!["2"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_events_stops_at_failed_chunk() -> Result<(), E> {
+        // GIVEN a batch of more records than are published at once, and a
+        // bus that rejects the second event it is sent
+        let bus = TestBus {
+            reject: vec![1],
+            ..Default::default()
+        };
+        let ids = (0..MAX_PUBLISH_BATCH_SIZE + 2)
+            .map(|i| format!("p{}", i))
+            .collect::<Vec<_>>();
+        let records = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| record(&(i + 1).to_string(), id))
+            .collect::<Vec<_>>();
+
+        // WHEN we parse the events
+        let res = parse_events(&bus, ddb_event(&records), Context::default()).await?;
+
+        // THEN the rejected record is reported, so that it is sent again with
+        // the records after it
+        assert_eq!(failures(&res), vec!["2"]);
+        // AND the events after the failing chunk were not published
+        let sent = bus.sent.lock().unwrap();
+        assert_eq!(sent.len(), MAX_PUBLISH_BATCH_SIZE - 1);
+        assert!(!sent.contains(&ids[MAX_PUBLISH_BATCH_SIZE]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_events_defers_past_limit() -> Result<(), E> {
+        // GIVEN a batch of more records than are published per invocation
+        let bus = TestBus::default();
+        let max_events = MAX_PUBLISH_CHUNKS * MAX_PUBLISH_BATCH_SIZE;
+        let records = (0..max_events + 5)
+            .map(|i| record(&(i + 1).to_string(), &format!("p{}", i)))
+            .collect::<Vec<_>>();
+
+        // WHEN we parse the events
+        let res = parse_events(&bus, ddb_event(&records), Context::default()).await?;
+
+        // THEN the first record past the limit is reported, so that Lambda
+        // sends it again with the records after it
+        let next = (max_events + 1).to_string();
+        assert_eq!(failures(&res), vec![next.as_str()]);
+        // AND only the events up to the limit were published
+        assert_eq!(bus.sent.lock().unwrap().len(), max_events);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_events_noop_update() -> Result<(), E> {
+        // GIVEN a batch with an update that only bumped the version, and a
+        // bus that rejects the second published event
+        let bus = TestBus {
+            reject: vec![1],
+            ..Default::default()
+        };
+        let event = ddb_event(&[
+            record("1", "a"),
+            modify_record("2", "a", "name", "name"),
+            modify_record("3", "a", "name", "new-name"),
+        ]);
+
+        // WHEN we parse the events
+        let res = parse_events(&bus, event, Context::default()).await?;
+
+        // THEN the no-op update is not published
+        assert_eq!(*bus.sent.lock().unwrap(), vec!["a"]);
+        // AND the rejected event is reported with its own record
+        assert_eq!(failures(&res), vec!["3"]);
+
+        Ok(())
+    }
 }
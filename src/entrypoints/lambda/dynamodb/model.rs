@@ -46,26 +46,72 @@ impl TryFrom<&DynamoDBRecord> for Event {
     type Error = Error;
 
     /// Try converting a DynamoDB record to an event.
+    ///
+    /// The event id is the id of the stream record, which stays the same when
+    /// Lambda retries a batch.
+    ///
+    /// With soft delete, deleting and restoring a product modify its item:
+    /// the `deleted_at` of the old and new images tells them apart from
+    /// updates. Putting a new product over a tombstone creates it, and the
+    /// removal of a tombstone, by time to live, purges it.
     fn try_from(value: &DynamoDBRecord) -> Result<Self, Self::Error> {
+        let event_id = value.event_id.clone();
         match value.event_name.as_str() {
             "INSERT" => {
                 let product = (&value.dynamodb.new_image).try_into()?;
-                Ok(Event::Created { product })
+                Ok(Event::Created { event_id, product })
             }
             "MODIFY" => {
-                let old = (&value.dynamodb.old_image).try_into()?;
-                let new = (&value.dynamodb.new_image).try_into()?;
-                Ok(Event::Updated { old, new })
+                let old: Product = (&value.dynamodb.old_image).try_into()?;
+                let new: Product = (&value.dynamodb.new_image).try_into()?;
+                Ok(match (old.is_deleted(), new.is_deleted()) {
+                    (false, true) => Event::Deleted {
+                        event_id,
+                        product: new,
+                    },
+                    (true, false) if old.created_at == new.created_at => Event::Restored {
+                        event_id,
+                        product: new,
+                    },
+                    (true, false) => Event::Created {
+                        event_id,
+                        product: new,
+                    },
+                    _ => Event::updated(event_id, old, new),
+                })
             }
             "REMOVE" => {
-                let product = (&value.dynamodb.old_image).try_into()?;
-                Ok(Event::Deleted { product })
+                let product: Product = (&value.dynamodb.old_image).try_into()?;
+                Ok(if product.is_deleted() {
+                    Event::Purged { event_id, product }
+                } else {
+                    Event::Deleted { event_id, product }
+                })
             }
             _ => Err(Error::InternalError("Unknown event type")),
         }
     }
 }
 
+/// Response of the DynamoDB Streams handler
+///
+/// When the event source mapping has `ReportBatchItemFailures` enabled,
+/// Lambda retries the batch starting from the lowest sequence number listed
+/// here, instead of retrying the whole batch.
+/// See https://docs.aws.amazon.com/lambda/latest/dg/with-ddb.html#services-ddb-batchfailurereporting
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct DynamoDBEventResponse {
+    #[serde(rename = "batchItemFailures")]
+    pub batch_item_failures: Vec<BatchItemFailure>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct BatchItemFailure {
+    /// Sequence number of the failed record
+    #[serde(rename = "itemIdentifier")]
+    pub item_identifier: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DynamoDBStreamRecord {
     #[serde(rename = "ApproximateCreationDateTime", default)]
@@ -93,51 +139,57 @@ pub struct DynamoDBStreamRecord {
 /// Attribute Value
 ///
 /// This is a copy of the `AttributeValue` struct from the AWS SDK for Rust,
-/// but without blob and `is_`-prefixed methods.
-/// See https://docs.rs/aws-sdk-dynamodb/0.0.22-alpha/aws_sdk_dynamodb/model/enum.AttributeValue.html
-#[derive(Deserialize, Serialize, Debug)]
+/// but without `is_`-prefixed methods. Binary values are base64-encoded in
+/// stream records and decoded into bytes.
+/// See https://docs.rs/aws-sdk-dynamodb/0.0.25-alpha/aws_sdk_dynamodb/model/enum.AttributeValue.html
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub enum AttributeValue {
-    // B(Blob),
+    B(#[serde(with = "base64_blob")] Vec<u8>),
+    #[serde(rename = "BOOL")]
     Bool(bool),
-    // Bs(Vec<Blob>),
+    #[serde(rename = "BS", with = "base64_blobs")]
+    Bs(Vec<Vec<u8>>),
     L(Vec<AttributeValue>),
     M(HashMap<String, AttributeValue>),
     N(String),
+    #[serde(rename = "NS")]
     Ns(Vec<String>),
+    #[serde(rename = "NULL")]
     Null(bool),
     S(String),
+    #[serde(rename = "SS")]
     Ss(Vec<String>),
 }
 
 impl AttributeValue {
-    pub fn as_bool(&self) -> Option<bool> {
+    pub fn as_b(&self) -> Option<&[u8]> {
         match self {
-            AttributeValue::Bool(b) => Some(*b),
+            AttributeValue::B(b) => Some(b),
             _ => None,
         }
     }
-    pub fn as_l(&self) -> Option<&Vec<AttributeValue>> {
+    pub fn as_bool(&self) -> Option<bool> {
         match self {
-            AttributeValue::L(l) => Some(l),
+            AttributeValue::Bool(b) => Some(*b),
             _ => None,
         }
     }
-    pub fn as_m(&self) -> Option<&HashMap<String, AttributeValue>> {
+    pub fn as_bs(&self) -> Vec<&[u8]> {
         match self {
-            AttributeValue::M(m) => Some(m),
-            _ => None,
+            AttributeValue::Bs(bs) => bs.iter().map(Vec::as_slice).collect(),
+            _ => Default::default(),
         }
     }
-    pub fn as_n(&self) -> Option<f64> {
+    pub fn as_l(&self) -> Option<&Vec<AttributeValue>> {
         match self {
-            AttributeValue::N(n) => n.parse::<f64>().ok(),
+            AttributeValue::L(l) => Some(l),
             _ => None,
         }
     }
-    pub fn as_ns(&self) -> Vec<f64> {
+    pub fn as_m(&self) -> Option<&HashMap<String, AttributeValue>> {
         match self {
-            AttributeValue::Ns(ns) => ns.iter().filter_map(|n| n.parse::<f64>().ok()).collect(),
-            _ => Default::default(),
+            AttributeValue::M(m) => Some(m),
+            _ => None,
         }
     }
     pub fn as_null(&self) -> Option<bool> {
@@ -160,38 +212,120 @@ impl AttributeValue {
     }
 }
 
+mod base64_blob {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        base64::decode(value).map_err(de::Error::custom)
+    }
+}
+
+mod base64_blobs {
+    use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(&base64::encode(value))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|value| base64::decode(value).map_err(de::Error::custom))
+            .collect()
+    }
+}
+
+impl From<AttributeValue> for aws_sdk_dynamodb::model::AttributeValue {
+    fn from(value: AttributeValue) -> Self {
+        use aws_sdk_dynamodb::model::AttributeValue as Sdk;
+        use aws_smithy_types::Blob;
+
+        match value {
+            AttributeValue::B(b) => Sdk::B(Blob::new(b)),
+            AttributeValue::Bool(b) => Sdk::Bool(b),
+            AttributeValue::Bs(bs) => Sdk::Bs(bs.into_iter().map(Blob::new).collect()),
+            AttributeValue::L(l) => Sdk::L(l.into_iter().map(Into::into).collect()),
+            AttributeValue::M(m) => Sdk::M(m.into_iter().map(|(k, v)| (k, v.into())).collect()),
+            AttributeValue::N(n) => Sdk::N(n),
+            AttributeValue::Ns(ns) => Sdk::Ns(ns),
+            AttributeValue::Null(null) => Sdk::Null(null),
+            AttributeValue::S(s) => Sdk::S(s),
+            AttributeValue::Ss(ss) => Sdk::Ss(ss),
+        }
+    }
+}
+
+impl TryFrom<aws_sdk_dynamodb::model::AttributeValue> for AttributeValue {
+    type Error = Error;
+
+    /// Convert an attribute value from the AWS SDK
+    ///
+    /// This fails for attribute types that are unknown to this version of
+    /// the SDK.
+    fn try_from(value: aws_sdk_dynamodb::model::AttributeValue) -> Result<Self, Self::Error> {
+        use aws_sdk_dynamodb::model::AttributeValue as Sdk;
+
+        Ok(match value {
+            Sdk::B(b) => AttributeValue::B(b.into_inner()),
+            Sdk::Bool(b) => AttributeValue::Bool(b),
+            Sdk::Bs(bs) => AttributeValue::Bs(bs.into_iter().map(|b| b.into_inner()).collect()),
+            Sdk::L(l) => AttributeValue::L(
+                l.into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Sdk::M(m) => AttributeValue::M(
+                m.into_iter()
+                    .map(|(k, v)| Ok((k, v.try_into()?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Sdk::N(n) => AttributeValue::N(n),
+            Sdk::Ns(ns) => AttributeValue::Ns(ns),
+            Sdk::Null(null) => AttributeValue::Null(null),
+            Sdk::S(s) => AttributeValue::S(s),
+            Sdk::Ss(ss) => AttributeValue::Ss(ss),
+            _ => return Err(Error::InternalError("Unsupported attribute type")),
+        })
+    }
+}
+
+fn to_sdk_item(
+    image: &HashMap<String, AttributeValue>,
+) -> HashMap<String, aws_sdk_dynamodb::model::AttributeValue> {
+    image
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone().into()))
+        .collect()
+}
+
 impl TryFrom<&HashMap<String, AttributeValue>> for Product {
     type Error = Error;
 
     /// Try to convert a DynamoDB item into a Product
     ///
-    /// This could fail as the DynamoDB item might be missing some fields.
+    /// Images have the same layout as the items written by the DynamoDB
+    /// store, so this goes through the same conversion.
     fn try_from(value: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        Ok(Product {
-            id: value
-                .get("id")
-                .ok_or(Error::InternalError("Missing id"))?
-                .as_s()
-                .ok_or(Error::InternalError("id is not a string"))?
-                .to_string(),
-            name: value
-                .get("name")
-                .ok_or(Error::InternalError("Missing name"))?
-                .as_s()
-                .ok_or(Error::InternalError("name is not a string"))?
-                .to_string(),
-            price: value
-                .get("price")
-                .ok_or(Error::InternalError("Missing price"))?
-                .as_n()
-                .ok_or(Error::InternalError("price is not a number"))?,
-        })
+        Product::try_from(to_sdk_item(value))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Money;
+    use rust_decimal::Decimal;
 
     fn get_ddb_event() -> DynamoDBEvent {
         let data = r#"
@@ -239,6 +373,9 @@ mod tests {
                     },
                     "price": {
                       "N": "20.5"
+                    },
+                    "version": {
+                      "N": "1"
                     }
                   },
                   "SequenceNumber": "222",
@@ -257,6 +394,12 @@ mod tests {
                     },
                     "price": {
                       "N": "30.5"
+                    },
+                    "currency": {
+                      "S": "EUR"
+                    },
+                    "version": {
+                      "N": "2"
                     }
                   },
                   "StreamViewType": "NEW_AND_OLD_IMAGES"
@@ -313,28 +456,86 @@ mod tests {
 
         assert_eq!(events.len(), 2);
         match &events[0] {
-            Event::Created { product } => {
+            Event::Created { event_id, product } => {
+                assert_eq!(event_id, "1");
                 assert_eq!(product.id, "101");
                 assert_eq!(product.name, "new-item");
-                assert_eq!(product.price, 10.5);
-            }
-            _ => {
-                assert!(false)
+                assert_eq!(product.price, Money::parse("10.5").unwrap());
+                assert_eq!(product.version, 0);
             }
+            _ => panic!("unexpected event type"),
         };
         match &events[1] {
-            Event::Updated { new, old } => {
+            Event::Updated { new, old, .. } => {
                 assert_eq!(new.id, "102");
                 assert_eq!(new.name, "new-item2");
-                assert_eq!(new.price, 30.5);
+                assert_eq!(new.price, Money::new(Decimal::new(305, 1), "EUR"));
+                assert_eq!(new.version, 2);
                 assert_eq!(old.id, "102");
                 assert_eq!(old.name, "new-item2");
-                assert_eq!(old.price, 20.5);
+                assert_eq!(old.price, Money::parse("20.5").unwrap());
+                assert_eq!(old.version, 1);
             }
-            _ => {
-                assert!(false)
+            _ => panic!("unexpected event type"),
+        };
+    }
+
+    #[test]
+    fn test_dynamodb_soft_delete_into_events() {
+        // GIVEN the records of a soft delete, a restore, a put over a
+        // tombstone and a purge
+        let image = |deleted: bool, created_at: &str| {
+            let mut image = serde_json::json!({
+                "id": {"S": "101"},
+                "name": {"S": "item"},
+                "price": {"N": "10.5"},
+                "created_at": {"S": created_at},
+            });
+            if deleted {
+                image["deleted_at"] = serde_json::json!({"S": "2024-02-01T00:00:00Z"});
             }
+            image
         };
+        let record = |event_name: &str, old: serde_json::Value, new: serde_json::Value| {
+            serde_json::from_value::<DynamoDBRecord>(serde_json::json!({
+                "awsRegion": "us-west-2",
+                "dynamodb": {
+                    "OldImage": old,
+                    "NewImage": new,
+                    "SequenceNumber": "111",
+                    "SizeBytes": 26,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES",
+                },
+                "eventID": "1",
+                "eventName": event_name,
+                "eventSource": "aws:dynamodb",
+                "eventSourceARN": "someARN",
+                "eventVersion": "1.1",
+            }))
+            .unwrap()
+        };
+        let created_at = "2024-01-01T00:00:00Z";
+        let records = [
+            record("MODIFY", image(false, created_at), image(true, created_at)),
+            record("MODIFY", image(true, created_at), image(false, created_at)),
+            record(
+                "MODIFY",
+                image(true, created_at),
+                image(false, "2024-03-01T00:00:00Z"),
+            ),
+            record("REMOVE", image(true, created_at), serde_json::json!({})),
+        ];
+
+        // WHEN converting them into events
+        let events = records
+            .iter()
+            .map(Event::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // THEN each record has its own event type
+        let types = events.iter().map(Event::event_type).collect::<Vec<_>>();
+        assert_eq!(types, vec!["Deleted", "Restored", "Created", "Purged"]);
     }
 
     #[test]
@@ -347,6 +548,69 @@ mod tests {
 
         assert_eq!(product.id, "101");
         assert_eq!(product.name, "new-item");
-        assert_eq!(product.price, 10.5);
+        assert_eq!(product.price, Money::parse("10.5").unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_binary() {
+        // GIVEN an image with binary attributes
+        let image: HashMap<String, AttributeValue> = serde_json::from_str(
+            r#"{
+                "id": {"S": "101"},
+                "name": {"S": "new-item"},
+                "price": {"N": "10.5"},
+                "thumbnail": {"B": "aGVsbG8="},
+                "chunks": {"BS": ["YQ==", "Yg=="]},
+                "tags": {"SS": ["a"]},
+                "active": {"BOOL": true}
+            }"#,
+        )
+        .unwrap();
+
+        // THEN binary values are decoded
+        assert_eq!(image["thumbnail"].as_b(), Some(&b"hello"[..]));
+        assert_eq!(image["chunks"].as_bs(), vec![&b"a"[..], &b"b"[..]]);
+        assert_eq!(image["tags"].as_ss(), vec!["a".to_string()]);
+        assert_eq!(image["active"].as_bool(), Some(true));
+        // AND the image is still a valid product
+        assert!(Product::try_from(&image).is_ok());
+
+        // AND binary values are encoded back to base64
+        assert_eq!(
+            serde_json::to_string(&image["chunks"]).unwrap(),
+            r#"{"BS":["YQ==","Yg=="]}"#
+        );
+    }
+
+    #[test]
+    fn test_deserialize_invalid_binary() {
+        assert!(serde_json::from_str::<AttributeValue>(r#"{"B": "not base64!"}"#).is_err());
+    }
+
+    #[test]
+    fn test_sdk_roundtrip() {
+        // GIVEN an attribute value with every type
+        let value = AttributeValue::M(HashMap::from([
+            ("b".to_string(), AttributeValue::B(vec![0, 1, 2])),
+            ("bool".to_string(), AttributeValue::Bool(false)),
+            ("bs".to_string(), AttributeValue::Bs(vec![vec![3], vec![4]])),
+            (
+                "l".to_string(),
+                AttributeValue::L(vec![AttributeValue::N("1".to_string())]),
+            ),
+            ("ns".to_string(), AttributeValue::Ns(vec!["2".to_string()])),
+            ("null".to_string(), AttributeValue::Null(true)),
+            ("s".to_string(), AttributeValue::S("s".to_string())),
+            ("ss".to_string(), AttributeValue::Ss(vec!["t".to_string()])),
+        ]));
+
+        // WHEN converting it to the SDK type and back
+        let sdk: aws_sdk_dynamodb::model::AttributeValue = value.clone().into();
+        let back = AttributeValue::try_from(sdk.clone()).unwrap();
+
+        // THEN nothing is lost
+        assert_eq!(back, value);
+        let sdk_b = sdk.as_m().unwrap()["b"].as_b().unwrap();
+        assert_eq!(sdk_b.as_ref(), &[0, 1, 2]);
     }
 }
@@ -1,2 +1,3 @@
 pub mod apigateway;
 pub mod dynamodb;
+pub mod outbox;
@@ -0,0 +1,39 @@
+use crate::{domain, event_bus::EventBus, store::StoreOutbox, Event};
+use lambda_runtime::Context;
+use tracing::{info, instrument};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Number of events read from the outbox at once
+const BATCH_SIZE: usize = 100;
+
+/// Maximum number of batches relayed in a single invocation
+///
+/// This keeps the invocation within the function timeout when the outbox is
+/// backed up. The remaining events are relayed by the next invocation.
+const MAX_BATCHES: usize = 20;
+
+/// Relay events from the outbox to the event bus
+///
+/// This is invoked on a schedule, so the payload of the invocation is
+/// ignored. If publishing fails, the invocation fails and the events that
+/// were not published stay in the outbox.
+#[instrument(skip(outbox, event_bus, _event))]
+pub async fn relay_outbox(
+    outbox: &dyn StoreOutbox,
+    event_bus: &dyn EventBus<E = Event>,
+    _event: serde_json::Value,
+    _: Context,
+) -> Result<(), E> {
+    let mut total = 0;
+    for _ in 0..MAX_BATCHES {
+        let count = domain::relay_outbox(outbox, event_bus, BATCH_SIZE).await?;
+        total += count;
+        if count < BATCH_SIZE {
+            break;
+        }
+    }
+    info!("Relayed {} events", total);
+
+    Ok(())
+}
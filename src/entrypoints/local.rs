@@ -0,0 +1,483 @@
+//! # Local HTTP server
+//!
+//! Serves the same API as the Lambda functions behind API Gateway, so that the
+//! service can be exercised on a developer machine. Requests are translated
+//! into `lambda_http` requests and handed to the API Gateway handlers, so both
+//! entrypoints return exactly the same responses.
+
+use super::lambda::apigateway;
+use crate::{
+    cursor,
+    domain::validation::ProductRules,
+    store::{Store, StoreIdempotency},
+    utils::strict_delete,
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Method, Server,
+};
+use lambda_http::{ext::RequestExt, http, lambda_runtime::Context, Body, IntoResponse};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use tracing::{error, info};
+
+type E = Box<dyn std::error::Error + Sync + Send + 'static>;
+
+/// Path of the bulk endpoint
+const BATCH_PATH: &str = "batch";
+
+/// Path of the soft-deleted products
+const TRASH_PATH: &str = "trash";
+
+/// Suffix of the path restoring a soft-deleted product
+const RESTORE_SUFFIX: &str = "/restore";
+
+/// Serve the API on `addr` until the process is stopped
+///
+/// Unlike the Lambda functions, this falls back to a default cursor secret if
+/// `CURSOR_SECRET` is not set.
+pub async fn serve<S>(store: S, rules: ProductRules, addr: SocketAddr) -> Result<(), E>
+where
+    S: Store + StoreIdempotency + Send + Sync + 'static,
+{
+    cursor::init_local();
+    let state = Arc::new((store, rules));
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move {
+                    let (store, rules) = &*state;
+                    Ok::<_, Infallible>(route(store, rules, req).await)
+                }
+            }))
+        }
+    });
+
+    info!("Listening on http://{}", addr);
+    Server::bind(&addr).serve(make_service).await?;
+    Ok(())
+}
+
+/// Dispatch a request to the matching API Gateway handler
+///
+/// The routes mirror the ones in `template.yaml`: `GET /` lists products,
+/// `GET`, `PUT` and `DELETE` on `/{id}` act on a single product, and
+/// `POST /batch` applies a bulk request. `GET /trash` lists soft-deleted
+/// products and `POST /{id}/restore` restores one.
+pub async fn route<S>(
+    store: &S,
+    rules: &ProductRules,
+    req: hyper::Request<hyper::Body>,
+) -> hyper::Response<hyper::Body>
+where
+    S: Store + StoreIdempotency,
+{
+    let (method, id) = (req.method().clone(), path_id(req.uri().path()));
+    info!("{} {}", method, req.uri());
+
+    // `/{id}/restore` acts on the product `{id}`
+    let (id, restore) = match id.as_deref().and_then(|id| id.strip_suffix(RESTORE_SUFFIX)) {
+        Some(id) => (Some(id.to_string()), true),
+        None => (id, false),
+    };
+
+    let event = match into_event(req).await {
+        Ok(event) => event,
+        Err(err) => {
+            error!("Failed to read request: {}", err);
+            return status(500, None);
+        }
+    };
+    let event = match &id {
+        Some(id) => {
+            event.with_path_parameters(HashMap::from([("id".to_string(), vec![id.clone()])]))
+        }
+        None => event,
+    };
+
+    let res = match (method, id) {
+        (Method::GET, None) => apigateway::get_products(store, event, Context::default())
+            .await
+            .map(IntoResponse::into_response),
+        (Method::POST, None) => apigateway::create_product(store, rules, event, Context::default())
+            .await
+            .map(IntoResponse::into_response),
+        (Method::POST, Some(_)) if restore => {
+            apigateway::restore_product(store, event, Context::default())
+                .await
+                .map(IntoResponse::into_response)
+        }
+        (_, Some(_)) if restore => return status(405, Some("POST")),
+        (Method::GET, Some(id)) if id == TRASH_PATH => {
+            apigateway::get_trash(store, event, Context::default())
+                .await
+                .map(IntoResponse::into_response)
+        }
+        (Method::GET, Some(_)) => apigateway::get_product(store, event, Context::default())
+            .await
+            .map(IntoResponse::into_response),
+        (Method::PUT, Some(_)) => apigateway::put_product(store, rules, event, Context::default())
+            .await
+            .map(IntoResponse::into_response),
+        (Method::PATCH, Some(_)) => {
+            apigateway::patch_product(store, rules, event, Context::default())
+                .await
+                .map(IntoResponse::into_response)
+        }
+        (Method::DELETE, Some(_)) => {
+            apigateway::delete_product(store, strict_delete(), event, Context::default())
+                .await
+                .map(IntoResponse::into_response)
+        }
+        (Method::POST, Some(id)) if id == BATCH_PATH => {
+            apigateway::batch_products(store, rules, strict_delete(), event, Context::default())
+                .await
+                .map(IntoResponse::into_response)
+        }
+        (_, None) => return status(405, Some("GET, POST")),
+        (_, Some(_)) => return status(405, Some("GET, PUT, PATCH, DELETE")),
+    };
+
+    match res {
+        Ok(res) => from_response(res),
+        Err(err) => {
+            error!("Handler failed: {}", err);
+            status(500, None)
+        }
+    }
+}
+
+/// Extract the product ID from a path
+///
+/// Returns `None` for the root path. Paths with more than one segment are
+/// kept whole, so they never match a product.
+fn path_id(path: &str) -> Option<String> {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return None;
+    }
+    Some(
+        percent_encoding::percent_decode_str(path)
+            .decode_utf8_lossy()
+            .into_owned(),
+    )
+}
+
+/// Convert a hyper request into a `lambda_http` request
+async fn into_event(req: hyper::Request<hyper::Body>) -> Result<lambda_http::Request, E> {
+    let (parts, body) = req.into_parts();
+    let bytes = hyper::body::to_bytes(body).await?;
+    let body = if bytes.is_empty() {
+        Body::Empty
+    } else {
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => Body::Text(text),
+            Err(err) => Body::Binary(err.into_bytes()),
+        }
+    };
+
+    // Query string parameters can be repeated, like in API Gateway
+    let mut query: HashMap<String, Vec<String>> = HashMap::new();
+    for (key, value) in form_urlencoded::parse(parts.uri.query().unwrap_or("").as_bytes()) {
+        query
+            .entry(key.into_owned())
+            .or_default()
+            .push(value.into_owned());
+    }
+
+    Ok(http::Request::from_parts(parts, body).with_query_string_parameters(query))
+}
+
+/// Convert a `lambda_http` response into a hyper response
+fn from_response(res: lambda_http::Response<Body>) -> hyper::Response<hyper::Body> {
+    let (parts, body) = res.into_parts();
+    let body = match body {
+        Body::Empty => hyper::Body::empty(),
+        Body::Text(text) => hyper::Body::from(text),
+        Body::Binary(bytes) => hyper::Body::from(bytes),
+    };
+    hyper::Response::from_parts(parts, body)
+}
+
+/// Empty response with a status code, for requests that never reach a handler
+fn status(status_code: u16, allow: Option<&str>) -> hyper::Response<hyper::Body> {
+    let mut builder = hyper::Response::builder().status(status_code);
+    if let Some(allow) = allow {
+        builder = builder.header("Allow", allow);
+    }
+    builder.body(hyper::Body::empty()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    async fn call(
+        store: &MemoryStore,
+        method: Method,
+        uri: &str,
+        body: &str,
+    ) -> (u16, serde_json::Value) {
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(hyper::Body::from(body.to_string()))
+            .unwrap();
+        let res = route(store, &ProductRules::default(), req).await;
+        let status = res.status().as_u16();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_flow() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let product = r#"{"id":"product-1","name":"test","price":"10.5"}"#;
+
+        // WHEN we put a product
+        let (status, _) = call(&store, Method::PUT, "/product-1", product).await;
+        // THEN it is created
+        assert_eq!(status, 201);
+
+        // WHEN we get the product
+        let (status, body) = call(&store, Method::GET, "/product-1", "").await;
+        // THEN it is returned
+        assert_eq!(status, 200);
+        assert_eq!(body["name"], "test");
+
+        // WHEN we list products with a limit
+        let (status, body) = call(&store, Method::GET, "/?limit=1", "").await;
+        // THEN the product is listed
+        assert_eq!(status, 200);
+        assert_eq!(body["products"][0]["id"], "product-1");
+
+        // WHEN we delete the product
+        let (status, body) = call(&store, Method::DELETE, "/product-1", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["product"]["id"], "product-1");
+        // THEN it is no longer found
+        let (status, _) = call(&store, Method::GET, "/product-1", "").await;
+        assert_eq!(status, 404);
+        // AND deleting it again fails
+        let (status, _) = call(&store, Method::DELETE, "/product-1", "").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_category() {
+        // GIVEN a store with a product in a category and one without
+        let store = MemoryStore::new();
+        let product = r#"{"id":"product-1","name":"test","category":"books","price":"10.5"}"#;
+        call(&store, Method::PUT, "/product-1", product).await;
+        let product = r#"{"id":"product-2","name":"test","price":"10.5"}"#;
+        call(&store, Method::PUT, "/product-2", product).await;
+
+        // WHEN we list the products of the category
+        let (status, body) = call(&store, Method::GET, "/?category=books&sort=price", "").await;
+        // THEN only the product in the category is listed
+        assert_eq!(status, 200);
+        assert_eq!(body["products"].as_array().unwrap().len(), 1);
+        assert_eq!(body["products"][0]["id"], "product-1");
+
+        // WHEN we sort without a category
+        let (status, _) = call(&store, Method::GET, "/?sort=price", "").await;
+        // THEN the request is rejected
+        assert_eq!(status, 400);
+
+        // WHEN we sort by an unknown key
+        let (status, _) = call(&store, Method::GET, "/?category=books&sort=id", "").await;
+        // THEN the request is rejected
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let batch =
+            r#"{"put":[{"id":"product-1","name":"test","price":"10.5"}],"get":["product-2"]}"#;
+
+        // WHEN we apply a batch
+        let (status, body) = call(&store, Method::POST, "/batch", batch).await;
+
+        // THEN each operation has its outcome
+        assert_eq!(status, 200);
+        assert_eq!(body["put"][0]["status"], 201);
+        assert_eq!(body["get"][0]["status"], 404);
+        // AND the product is stored
+        let (status, _) = call(&store, Method::GET, "/product-1", "").await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+
+        // WHEN we post a product without an id
+        let res = route(
+            &store,
+            &ProductRules::default(),
+            hyper::Request::builder()
+                .method(Method::POST)
+                .uri("/")
+                .body(hyper::Body::from(r#"{"name":"test","price":"10.5"}"#))
+                .unwrap(),
+        )
+        .await;
+
+        // THEN it is created at the location of its new id
+        assert_eq!(res.status().as_u16(), 201);
+        let location = res.headers()["Location"].to_str().unwrap().to_string();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(location, format!("/{}", body["id"].as_str().unwrap()));
+        let (status, body) = call(&store, Method::GET, &location, "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["name"], "test");
+
+        // WHEN we post a product with an id
+        let product = r#"{"id":"product-1","name":"test","price":"10.5"}"#;
+        let (status, _) = call(&store, Method::POST, "/", product).await;
+        // THEN it is rejected
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_patch() {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        let product = r#"{"id":"product-1","name":"test","price":"10.5"}"#;
+        call(&store, Method::PUT, "/product-1", product).await;
+        let patch = |content_type| {
+            hyper::Request::builder()
+                .method(Method::PATCH)
+                .uri("/product-1")
+                .header("Content-Type", content_type)
+                .body(hyper::Body::from(r#"{"name":"new"}"#))
+                .unwrap()
+        };
+
+        // WHEN we patch it with a merge patch
+        let res = route(
+            &store,
+            &ProductRules::default(),
+            patch("application/merge-patch+json"),
+        )
+        .await;
+        // THEN the updated product is returned with its new ETag
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(res.headers()["ETag"], "\"2\"");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["name"], "new");
+        assert_eq!(body["price"]["amount"], "10.5");
+
+        // WHEN we send the patch with another media type
+        let res = route(&store, &ProductRules::default(), patch("text/plain")).await;
+        // THEN it is rejected
+        assert_eq!(res.status().as_u16(), 415);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+        let put = |body: &str| {
+            hyper::Request::builder()
+                .method(Method::PUT)
+                .uri("/product-1")
+                .header("Idempotency-Key", "key-1")
+                .body(hyper::Body::from(body.to_string()))
+                .unwrap()
+        };
+        let product = r#"{"id":"product-1","name":"test","price":"10.5"}"#;
+
+        // WHEN we put a product with an idempotency key
+        let res = route(&store, &ProductRules::default(), put(product)).await;
+        // THEN it is created
+        assert_eq!(res.status().as_u16(), 201);
+        let etag = res.headers()["ETag"].clone();
+
+        // WHEN we retry the request
+        let res = route(&store, &ProductRules::default(), put(product)).await;
+        // THEN the first response is replayed
+        assert_eq!(res.status().as_u16(), 201);
+        assert_eq!(res.headers()["ETag"], etag);
+        assert_eq!(res.headers()["Idempotent-Replayed"], "true");
+        // AND the product was only written once
+        let (_, body) = call(&store, Method::GET, "/product-1", "").await;
+        assert_eq!(body["version"], 1);
+
+        // WHEN we reuse the key with another product
+        let product = r#"{"id":"product-1","name":"other","price":"10.5"}"#;
+        let res = route(&store, &ProductRules::default(), put(product)).await;
+        // THEN the request is rejected
+        assert_eq!(res.status().as_u16(), 422);
+
+        // WHEN we reuse the key with a precondition
+        let mut req = put(r#"{"id":"product-1","name":"test","price":"10.5"}"#);
+        req.headers_mut()
+            .insert("If-None-Match", http::HeaderValue::from_static("*"));
+        let res = route(&store, &ProductRules::default(), req).await;
+        // THEN the request is rejected
+        assert_eq!(res.status().as_u16(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete() {
+        // GIVEN a store in soft-delete mode with a product
+        let store = MemoryStore::new().with_soft_delete(chrono::Duration::days(30));
+        let product = r#"{"id":"product-1","name":"test","price":"10.5"}"#;
+        call(&store, Method::PUT, "/product-1", product).await;
+
+        // WHEN we delete the product
+        let (status, _) = call(&store, Method::DELETE, "/product-1", "").await;
+        assert_eq!(status, 200);
+        // THEN it is no longer found
+        let (status, _) = call(&store, Method::GET, "/product-1", "").await;
+        assert_eq!(status, 404);
+        // AND it is in the trash
+        let (status, body) = call(&store, Method::GET, "/trash", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["products"][0]["id"], "product-1");
+        assert!(body["products"][0]["deleted_at"].is_string());
+
+        // WHEN we restore it
+        let (status, body) = call(&store, Method::POST, "/product-1/restore", "").await;
+        // THEN it is back
+        assert_eq!(status, 200);
+        assert!(body.get("deleted_at").is_none());
+        let (status, _) = call(&store, Method::GET, "/product-1", "").await;
+        assert_eq!(status, 200);
+        // AND it cannot be restored again
+        let (status, _) = call(&store, Method::POST, "/product-1/restore", "").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        // GIVEN an empty store
+        let store = MemoryStore::new();
+
+        // WHEN we delete the collection
+        let res = route(
+            &store,
+            &ProductRules::default(),
+            hyper::Request::builder()
+                .method(Method::DELETE)
+                .uri("/")
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        // THEN the method is not allowed
+        assert_eq!(res.status().as_u16(), 405);
+        assert_eq!(res.headers()["Allow"], "GET, POST");
+    }
+}
@@ -1,2 +1,4 @@
 #[cfg(feature = "lambda")]
 pub mod lambda;
+#[cfg(feature = "local")]
+pub mod local;
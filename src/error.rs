@@ -1,5 +1,8 @@
+use crate::domain::validation::Violation;
+use crate::event_bus::FailedEvent;
 use aws_sdk_dynamodb::model::AttributeValue;
 use aws_smithy_http::result::SdkError;
+use aws_smithy_types::retry::{ErrorKind, ProvideErrorKind};
 use std::error;
 use std::fmt;
 
@@ -8,7 +11,72 @@ pub enum Error {
     InitError(&'static str),
     ClientError(&'static str),
     InternalError(&'static str),
+    NotFound(&'static str),
+    Conflict(&'static str),
+    PreconditionFailed(&'static str),
+    UnsupportedMediaType(&'static str),
+    UnprocessableEntity(&'static str),
+    Validation {
+        field: String,
+        reason: String,
+    },
+    Violations(Vec<Violation>),
+    Throttled(String),
+    Unavailable(String),
     SdkError(String),
+    /// A value could not be mapped to or from a DynamoDB item
+    ItemError(String),
+    /// Events that the event bus still rejected after retrying
+    PublishFailed(Vec<FailedEvent>),
+}
+
+impl Error {
+    /// HTTP status code matching this error
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Error::ClientError(_) | Error::Validation { .. } | Error::Violations(_) => 400,
+            Error::NotFound(_) => 404,
+            Error::Conflict(_) => 409,
+            Error::PreconditionFailed(_) => 412,
+            Error::UnsupportedMediaType(_) => 415,
+            Error::UnprocessableEntity(_) => 422,
+            Error::Throttled(_) => 429,
+            Error::Unavailable(_) => 503,
+            Error::InitError(_)
+            | Error::InternalError(_)
+            | Error::SdkError(_)
+            | Error::ItemError(_)
+            | Error::PublishFailed(_) => 500,
+        }
+    }
+
+    /// Message that can be safely returned to the client
+    ///
+    /// Server-side errors are reduced to a generic message, so that internal
+    /// details such as table names do not leak in responses.
+    pub fn detail(&self) -> String {
+        match self {
+            Error::ClientError(msg)
+            | Error::NotFound(msg)
+            | Error::Conflict(msg)
+            | Error::PreconditionFailed(msg)
+            | Error::UnsupportedMediaType(msg)
+            | Error::UnprocessableEntity(msg) => msg.to_string(),
+            Error::Validation { field, reason } => format!("{}: {}", field, reason),
+            Error::Violations(violations) => violations
+                .iter()
+                .map(|v| format!("{}: {}", v.field, v.reason))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Error::Throttled(_) => "Too many requests, please retry later".to_string(),
+            Error::Unavailable(_) => "Service temporarily unavailable".to_string(),
+            Error::InitError(_)
+            | Error::InternalError(_)
+            | Error::SdkError(_)
+            | Error::ItemError(_)
+            | Error::PublishFailed(_) => "Internal server error".to_string(),
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -17,7 +85,28 @@ impl fmt::Display for Error {
             Error::InitError(msg) => write!(f, "InitError: {}", msg),
             Error::ClientError(msg) => write!(f, "ClientError: {}", msg),
             Error::InternalError(msg) => write!(f, "InternalError: {}", msg),
+            Error::NotFound(msg) => write!(f, "NotFound: {}", msg),
+            Error::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            Error::PreconditionFailed(msg) => write!(f, "PreconditionFailed: {}", msg),
+            Error::UnsupportedMediaType(msg) => write!(f, "UnsupportedMediaType: {}", msg),
+            Error::UnprocessableEntity(msg) => write!(f, "UnprocessableEntity: {}", msg),
+            Error::Validation { field, reason } => write!(f, "Validation: {}: {}", field, reason),
+            Error::Violations(violations) => {
+                write!(f, "Violations: {} invalid field(s)", violations.len())
+            }
+            Error::Throttled(err) => write!(f, "Throttled: {}", err),
+            Error::Unavailable(err) => write!(f, "Unavailable: {}", err),
             Error::SdkError(err) => write!(f, "SdkError: {}", err),
+            Error::ItemError(err) => write!(f, "ItemError: {}", err),
+            Error::PublishFailed(events) => write!(
+                f,
+                "PublishFailed: {}",
+                events
+                    .iter()
+                    .map(|e| format!("{} ({}: {})", e.id, e.code, e.message))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
         }
     }
 }
@@ -36,11 +125,114 @@ impl From<&AttributeValue> for Error {
     }
 }
 
+/// Error codes returned by AWS services when a request is throttled
+static THROTTLING_CODES: &[&str] = &[
+    "ProvisionedThroughputExceededException",
+    "RequestLimitExceeded",
+    "ThrottlingException",
+    "LimitExceededException",
+];
+
+/// Error codes returned by AWS services when they are not available
+static UNAVAILABLE_CODES: &[&str] = &[
+    "InternalServerError",
+    "InternalException",
+    "ServiceUnavailable",
+];
+
+/// Error codes returned by AWS services when a condition was not met
+static CONFLICT_CODES: &[&str] = &[
+    "ConditionalCheckFailedException",
+    "TransactionConflictException",
+];
+
 impl<E> From<SdkError<E>> for Error
 where
-    E: error::Error,
+    E: error::Error + ProvideErrorKind,
 {
+    /// Classify an error returned by the AWS SDK
+    ///
+    /// The error codes are shared across operations, so this works for any
+    /// DynamoDB or EventBridge operation.
     fn from(value: SdkError<E>) -> Error {
-        Error::SdkError(format!("{}", value))
+        match &value {
+            SdkError::ServiceError { err, .. } => {
+                let code = err.code().unwrap_or_default();
+                if CONFLICT_CODES.contains(&code) {
+                    Error::Conflict("A condition on the request was not met")
+                } else if THROTTLING_CODES.contains(&code)
+                    || err.retryable_error_kind() == Some(ErrorKind::ThrottlingError)
+                {
+                    Error::Throttled(format!("{}", value))
+                } else if UNAVAILABLE_CODES.contains(&code)
+                    || matches!(
+                        err.retryable_error_kind(),
+                        Some(ErrorKind::ServerError) | Some(ErrorKind::TransientError)
+                    )
+                {
+                    Error::Unavailable(format!("{}", value))
+                } else {
+                    Error::SdkError(format!("{}", value))
+                }
+            }
+            // The request could not reach the service
+            SdkError::DispatchFailure(_) => Error::Unavailable(format!("{}", value)),
+            _ => Error::SdkError(format!("{}", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::error::PutItemError;
+    use aws_smithy_http::body::SdkBody;
+    use aws_smithy_http::operation;
+
+    fn service_error(code: &str) -> SdkError<PutItemError> {
+        SdkError::ServiceError {
+            err: PutItemError::generic(aws_smithy_types::Error::builder().code(code).build()),
+            raw: operation::Response::new(http::Response::new(SdkBody::empty())),
+        }
+    }
+
+    #[test]
+    fn test_from_sdk_error_conflict() {
+        let err: Error = service_error("ConditionalCheckFailedException").into();
+        assert!(matches!(err, Error::Conflict(_)));
+        assert_eq!(err.status_code(), 409);
+    }
+
+    #[test]
+    fn test_from_sdk_error_throttled() {
+        let err: Error = service_error("ProvisionedThroughputExceededException").into();
+        assert!(matches!(err, Error::Throttled(_)));
+        assert_eq!(err.status_code(), 429);
+    }
+
+    #[test]
+    fn test_from_sdk_error_unavailable() {
+        let err: Error = service_error("InternalServerError").into();
+        assert!(matches!(err, Error::Unavailable(_)));
+        assert_eq!(err.status_code(), 503);
+    }
+
+    #[test]
+    fn test_from_sdk_error_other() {
+        let err: Error = service_error("ValidationException").into();
+        assert!(matches!(err, Error::SdkError(_)));
+        assert_eq!(err.status_code(), 500);
+    }
+
+    #[test]
+    fn test_detail() {
+        let err = Error::Validation {
+            field: "price".to_string(),
+            reason: "must be positive".to_string(),
+        };
+        assert_eq!(err.detail(), "price: must be positive");
+
+        let err = Error::SdkError("table 'secret' not found".to_string());
+        assert_eq!(err.detail(), "Internal server error");
     }
 }
@@ -0,0 +1,223 @@
+//! CloudEvents encoding
+//!
+//! Encodes product events as [CloudEvents 1.0](https://github.com/cloudevents/spec),
+//! for consumers that do not read the EventBridge envelope. Both the
+//! structured JSON mode and the binary HTTP mode are supported.
+
+use crate::{schema, Event};
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+
+/// Version of the CloudEvents specification
+pub const SPEC_VERSION: &str = "1.0";
+
+/// Source of the events produced by this service
+pub const SOURCE: &str = "rust-products";
+
+/// Content type of an event in structured mode
+pub const STRUCTURED_CONTENT_TYPE: &str = "application/cloudevents+json";
+
+/// Envelope used to publish events
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Envelope {
+    /// Event JSON in the `detail` of an EventBridge entry
+    #[default]
+    EventBridge,
+    /// CloudEvent in structured mode in the `detail` of an EventBridge entry
+    CloudEvents,
+}
+
+impl std::str::FromStr for Envelope {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "eventbridge" => Ok(Envelope::EventBridge),
+            "cloudevents" => Ok(Envelope::CloudEvents),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Product event wrapped in a CloudEvent
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CloudEvent {
+    pub specversion: String,
+    /// Id of the event, stable across deliveries
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// Id of the product the event is about
+    pub subject: String,
+    /// RFC 3339 timestamp of the change, if it has one
+    ///
+    /// See `Event::time`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    pub datacontenttype: String,
+    pub dataschema: String,
+    pub data: Event,
+}
+
+/// CloudEvent in binary HTTP mode
+///
+/// The attributes are carried in `ce-` headers, and the body only contains
+/// the data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BinaryMessage {
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl CloudEvent {
+    /// Wrap an event
+    ///
+    /// The time comes from the event itself, so that every delivery of the
+    /// same event carries the same time.
+    pub fn new(event: &Event) -> Self {
+        let event_type = event.event_type();
+        Self {
+            specversion: SPEC_VERSION.to_string(),
+            id: event.event_id().to_string(),
+            source: SOURCE.to_string(),
+            ty: format!("{}.product.{}", SOURCE, event_type.to_lowercase()),
+            subject: event.id().to_string(),
+            time: event
+                .time()
+                .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            datacontenttype: "application/json".to_string(),
+            dataschema: schema::schema_id(event_type),
+            data: event.clone(),
+        }
+    }
+
+    /// Encode the event in structured JSON mode
+    pub fn to_structured(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Encode the event in binary HTTP mode
+    pub fn to_binary(&self) -> BinaryMessage {
+        let mut headers = vec![
+            ("ce-specversion", self.specversion.clone()),
+            ("ce-id", self.id.clone()),
+            ("ce-source", self.source.clone()),
+            ("ce-type", self.ty.clone()),
+            ("ce-subject", self.subject.clone()),
+        ];
+        if let Some(time) = &self.time {
+            headers.push(("ce-time", time.clone()));
+        }
+        headers.push(("ce-dataschema", self.dataschema.clone()));
+        headers.push(("content-type", self.datacontenttype.clone()));
+        BinaryMessage {
+            headers,
+            body: serde_json::to_string(&self.data).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Money, Product};
+    use chrono::{TimeZone, Utc};
+    use rust_decimal::Decimal;
+
+    /// Soft delete of a product
+    fn event() -> Event {
+        Event::Deleted {
+            event_id: "test-event".to_string(),
+            product: Product {
+                id: "123".to_string(),
+                name: "test".to_string(),
+                price: Money::new(Decimal::new(10, 0), "USD"),
+                deleted_at: Some(Utc.timestamp_millis_opt(1_600_000_000_000).unwrap()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_structured() {
+        // GIVEN a CloudEvent
+        let cloud_event = CloudEvent::new(&event());
+
+        // WHEN encoding it in structured mode
+        let json: serde_json::Value = serde_json::from_str(&cloud_event.to_structured()).unwrap();
+
+        // THEN it contains the required attributes and the event as data
+        assert_eq!(json["specversion"], "1.0");
+        assert_eq!(json["id"], "test-event");
+        assert_eq!(json["source"], SOURCE);
+        assert_eq!(json["type"], "rust-products.product.deleted");
+        assert_eq!(json["subject"], "123");
+        assert_eq!(json["time"], "2020-09-13T12:26:40.000Z");
+        assert_eq!(
+            json["dataschema"],
+            "urn:rust-products:schemas:product-deleted:v5"
+        );
+        assert_eq!(json["data"], serde_json::to_value(event()).unwrap());
+    }
+
+    #[test]
+    fn test_time_without_change_time() {
+        // GIVEN a hard delete, which has no time of its own
+        let event = match event() {
+            Event::Deleted { event_id, product } => Event::Deleted {
+                event_id,
+                product: Product {
+                    deleted_at: None,
+                    ..product
+                },
+            },
+            _ => unreachable!(),
+        };
+
+        // WHEN encoding it
+        let cloud_event = CloudEvent::new(&event);
+        let json: serde_json::Value = serde_json::from_str(&cloud_event.to_structured()).unwrap();
+
+        // THEN it has no time rather than the time of encoding
+        assert!(json.get("time").is_none());
+        assert!(cloud_event
+            .to_binary()
+            .headers
+            .iter()
+            .all(|(name, _)| *name != "ce-time"));
+    }
+
+    #[test]
+    fn test_structured_roundtrip() {
+        let cloud_event = CloudEvent::new(&event());
+
+        let decoded: CloudEvent = serde_json::from_str(&cloud_event.to_structured()).unwrap();
+        assert_eq!(decoded, cloud_event);
+    }
+
+    #[test]
+    fn test_binary() {
+        // GIVEN a CloudEvent
+        let cloud_event = CloudEvent::new(&event());
+
+        // WHEN encoding it in binary mode
+        let message = cloud_event.to_binary();
+
+        // THEN the attributes are in headers and the body is the event
+        assert!(message
+            .headers
+            .contains(&("ce-id", "test-event".to_string())));
+        assert!(message
+            .headers
+            .contains(&("content-type", "application/json".to_string())));
+        assert_eq!(message.body, serde_json::to_string(&event()).unwrap());
+    }
+
+    #[test]
+    fn test_envelope_from_str() {
+        assert_eq!("cloudevents".parse(), Ok(Envelope::CloudEvents));
+        assert_eq!("eventbridge".parse(), Ok(Envelope::EventBridge));
+        assert!("xml".parse::<Envelope>().is_err());
+    }
+}
@@ -1,43 +1,63 @@
+use crate::event_bus::cloudevents::{CloudEvent, Envelope, SOURCE};
 use crate::Event;
 use aws_sdk_eventbridge::model::PutEventsRequestEntry;
 
-static SOURCE: &str = "rust-products";
-
 pub trait EventExt {
-    fn to_eventbridge(&self, bus_name: &str) -> PutEventsRequestEntry;
+    fn to_eventbridge(&self, bus_name: &str, envelope: Envelope) -> PutEventsRequestEntry;
 }
 
 impl EventExt for Event {
-    fn to_eventbridge(&self, bus_name: &str) -> PutEventsRequestEntry {
-        PutEventsRequestEntry::builder()
+    fn to_eventbridge(&self, bus_name: &str, envelope: Envelope) -> PutEventsRequestEntry {
+        let builder = PutEventsRequestEntry::builder()
             .event_bus_name(bus_name)
             .source(SOURCE)
-            .detail_type(match self {
-                Event::Created { .. } => "ProductCreated",
-                Event::Updated { .. } => "ProductUpdated",
-                Event::Deleted { .. } => "ProductDeleted",
-            })
-            .resources(self.id())
-            .detail(serde_json::to_string(self).unwrap())
-            .build()
+            .resources(self.id());
+
+        match envelope {
+            Envelope::EventBridge => builder
+                .detail_type(match self {
+                    Event::Created { .. } => "ProductCreated",
+                    Event::Updated { .. } => "ProductUpdated",
+                    Event::Deleted { .. } => "ProductDeleted",
+                    Event::Restored { .. } => "ProductRestored",
+                    Event::Purged { .. } => "ProductPurged",
+                })
+                .detail(serde_json::to_string(self).unwrap()),
+            // Rules can match on the CloudEvent type through the detail type
+            Envelope::CloudEvents => {
+                let cloud_event = CloudEvent::new(self);
+                builder
+                    .detail_type(&cloud_event.ty)
+                    .detail(cloud_event.to_structured())
+            }
+        }
+        .build()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Product;
+    use crate::{Money, Product};
+    use rust_decimal::Decimal;
 
-    #[test]
-    fn test_to_eventbridge() {
-        let event = Event::Created {
+    fn event() -> Event {
+        Event::Created {
+            event_id: "test-event".to_string(),
             product: Product {
                 id: "123".to_string(),
                 name: "test".to_string(),
-                price: 10.0,
+                price: Money::new(Decimal::new(10, 0), "USD"),
+                version: 0,
+                ..Default::default()
             },
-        };
-        let entry = event.to_eventbridge("test-bus");
+        }
+    }
+
+    #[test]
+    fn test_to_eventbridge() {
+        let event = event();
+        let entry = event.to_eventbridge("test-bus", Envelope::EventBridge);
         assert_eq!(entry.event_bus_name.unwrap(), "test-bus");
         assert_eq!(entry.source.unwrap(), SOURCE);
         assert_eq!(entry.detail_type.unwrap(), "ProductCreated");
@@ -47,4 +67,16 @@ mod tests {
             serde_json::to_string(&event).unwrap()
         );
     }
+
+    #[test]
+    fn test_to_eventbridge_cloudevents() {
+        let event = event();
+        let entry = event.to_eventbridge("test-bus", Envelope::CloudEvents);
+        assert_eq!(entry.source.unwrap(), SOURCE);
+        assert_eq!(entry.detail_type.unwrap(), "rust-products.product.created");
+
+        let cloud_event: CloudEvent = serde_json::from_str(&entry.detail.unwrap()).unwrap();
+        assert_eq!(cloud_event.id, "test-event");
+        assert_eq!(cloud_event.data, event);
+    }
 }
@@ -2,16 +2,58 @@
 //!
 //! Bus implementation using the AWS SDK for EventBridge.
 
-use super::EventBus;
+use super::{Envelope, EventBus, FailedEvent};
 use crate::{Error, Event};
 use async_trait::async_trait;
 use aws_sdk_eventbridge::Client;
 use futures::future::join_all;
-use tracing::{info, instrument};
+use rand::Rng;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
 
 mod ext;
 use ext::EventExt;
 
+/// Maximum number of entries in a single `put_events()` request
+const MAX_BATCH_SIZE: usize = 10;
+
+/// Retry policy for events that EventBridge failed to ingest
+///
+/// Delays grow exponentially from `base_delay` up to `max_delay`, and the
+/// actual delay is picked at random below that bound ("full jitter"), so that
+/// concurrent senders do not retry in lockstep.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay to wait before the given retry, starting at 1
+    fn backoff(&self, retry: u32) -> Duration {
+        let bound = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        if bound.is_zero() {
+            return bound;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=bound)
+    }
+}
+
 /// EventBridge bus implementation.
 ///
 /// We have to pass a generic type parameter `C` for the underlying client,
@@ -20,6 +62,8 @@ use ext::EventExt;
 pub struct EventBridgeBus<C> {
     client: Client<C>,
     bus_name: String,
+    retry_policy: RetryPolicy,
+    envelope: Envelope,
 }
 
 impl<C> EventBridgeBus<C>
@@ -27,7 +71,106 @@ where
     C: aws_smithy_client::bounds::SmithyConnector,
 {
     pub fn new(client: Client<C>, bus_name: String) -> Self {
-        Self { client, bus_name }
+        Self {
+            client,
+            bus_name,
+            retry_policy: RetryPolicy::default(),
+            envelope: Envelope::default(),
+        }
+    }
+
+    /// Replace the envelope in which events are published
+    pub fn with_envelope(mut self, envelope: Envelope) -> Self {
+        self.envelope = envelope;
+        self
+    }
+
+    /// Replace the policy used to retry failed entries
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Publish a batch of at most 10 events
+    ///
+    /// `put_events()` can succeed while some of its entries were rejected,
+    /// so we check the result of every entry and only send the failed ones
+    /// again. Requests that fail as a whole are retried if the error is
+    /// transient.
+    ///
+    /// If some events are still failing once the retry budget is exhausted,
+    /// this returns an `Error::PublishFailed` listing them.
+    async fn put_batch(&self, events: &[(usize, &Event)]) -> Result<(), Error> {
+        let mut pending = events.to_vec();
+        let mut failed = Vec::new();
+
+        for attempt in 0..self.retry_policy.max_attempts {
+            if attempt > 0 {
+                let delay = self.retry_policy.backoff(attempt);
+                warn!(
+                    "Retrying {} event(s) in {:?} (attempt {})",
+                    pending.len(),
+                    delay,
+                    attempt + 1
+                );
+                tokio::time::sleep(delay).await;
+            }
+
+            let res = self
+                .client
+                .put_events()
+                .set_entries(Some(
+                    pending
+                        .iter()
+                        .map(|(_, e)| e.to_eventbridge(&self.bus_name, self.envelope))
+                        .collect(),
+                ))
+                .send()
+                .await;
+
+            let output = match res.map_err(Error::from) {
+                Ok(output) => output,
+                // The whole request failed for a transient reason
+                Err(err @ (Error::Throttled(_) | Error::Unavailable(_))) => {
+                    failed = pending
+                        .iter()
+                        .map(|(index, e)| FailedEvent {
+                            index: *index,
+                            id: e.id().to_string(),
+                            code: "RequestFailed".to_string(),
+                            message: err.to_string(),
+                        })
+                        .collect();
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if output.failed_entry_count == 0 {
+                return Ok(());
+            }
+
+            // Entries in the response are in the same order as in the request
+            let entries = output.entries.unwrap_or_default();
+            failed.clear();
+            let mut retry = Vec::new();
+            for (event, entry) in pending.iter().zip(entries) {
+                if let Some(code) = entry.error_code {
+                    failed.push(FailedEvent {
+                        index: event.0,
+                        id: event.1.id().to_string(),
+                        code,
+                        message: entry.error_message.unwrap_or_default(),
+                    });
+                    retry.push(*event);
+                }
+            }
+            if retry.is_empty() {
+                return Ok(());
+            }
+            pending = retry;
+        }
+
+        Err(Error::PublishFailed(failed))
     }
 }
 
@@ -42,13 +185,7 @@ where
     #[instrument(skip(self))]
     async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
         info!("Publishing event to EventBridge");
-        self.client
-            .put_events()
-            .entries(event.to_eventbridge(&self.bus_name))
-            .send()
-            .await?;
-
-        Ok(())
+        self.put_batch(&[(0, event)]).await
     }
 
     /// Publish a batch of events to the event bus.
@@ -58,27 +195,36 @@ where
         //
         // EventBridge has a limit of 10 events per `put_events()` request.
         //
-        // `send()` returns a Future, so we can use `join_all` to wait for all of the
-        // futures to complete. This means we can send all batches at the same time
-        // and not have to wait for each batch to complete before sending the next one.
+        // `put_batch()` returns a Future, so we can use `join_all` to wait for all
+        // of the futures to complete. This means we can send all batches at the
+        // same time and not have to wait for each batch to complete before sending
+        // the next one.
         info!("Publishing events to EventBridge");
-        let res = join_all(events.iter().collect::<Vec<_>>().chunks(10).map(|chunk| {
-            self.client
-                .put_events()
-                .set_entries(Some(
-                    chunk
-                        .iter()
-                        .map(|e| e.to_eventbridge(&self.bus_name))
-                        .collect::<Vec<_>>(),
-                ))
-                .send()
-        }))
+        let res = join_all(
+            events
+                .iter()
+                .enumerate()
+                .collect::<Vec<_>>()
+                .chunks(MAX_BATCH_SIZE)
+                .map(|chunk| self.put_batch(chunk)),
+        )
         .await;
 
         // Retrieve errors from the response vector
         //
-        // If any of the responses contained an error, we'll return an error.
-        res.into_iter().collect::<Result<Vec<_>, _>>()?;
+        // Failed events from all batches are merged into a single error, so
+        // that the caller knows about every event that was not published.
+        let mut failed = Vec::new();
+        for res in res {
+            match res {
+                Ok(()) => (),
+                Err(Error::PublishFailed(events)) => failed.extend(events),
+                Err(err) => return Err(err),
+            }
+        }
+        if !failed.is_empty() {
+            return Err(Error::PublishFailed(failed));
+        }
 
         Ok(())
     }
@@ -87,10 +233,11 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Event, Product};
+    use crate::{Event, Money, Product};
     use aws_sdk_eventbridge::{Client, Config, Credentials, Region};
     use aws_smithy_client::test_connection::TestConnection;
     use aws_smithy_http::body::SdkBody;
+    use rust_decimal::Decimal;
 
     // Config for mocking EventBridge
     async fn get_mock_config() -> Config {
@@ -118,7 +265,7 @@ mod tests {
             get_request_builder()
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
-                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id\",\"name\":\"test-name\",\"price\":10.0}}","EventBusName":"test-bus"}]}"#,
+                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id\",\"name\":\"test-name\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"10\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
             http::Response::builder()
@@ -131,17 +278,20 @@ mod tests {
 
         // WHEN we send an event
         let event = Event::Created {
+            event_id: "test-event".to_string(),
             product: Product {
                 id: "test-id".to_string(),
                 name: "test-name".to_string(),
-                price: 10.0,
+                price: Money::new(Decimal::new(10, 0), "USD"),
+                version: 0,
+                ..Default::default()
             },
         };
         event_bus.send_event(&event).await?;
 
         // THEN the request should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 1);
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
@@ -153,7 +303,7 @@ mod tests {
             get_request_builder()
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
-                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id\",\"name\":\"test-name\",\"price\":10.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-2"],"DetailType":"ProductDeleted","Detail":"{\"type\":\"Deleted\",\"product\":{\"id\":\"test-id-2\",\"name\":\"test-name-2\",\"price\":20.0}}","EventBusName":"test-bus"}]}"#,
+                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id\",\"name\":\"test-name\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"10\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-2"],"DetailType":"ProductDeleted","Detail":"{\"type\":\"Deleted\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-2\",\"name\":\"test-name-2\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"20\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
             http::Response::builder()
@@ -167,17 +317,23 @@ mod tests {
         // WHEN we send a batch of events
         let events = vec![
             Event::Created {
+                event_id: "test-event".to_string(),
                 product: Product {
                     id: "test-id".to_string(),
                     name: "test-name".to_string(),
-                    price: 10.0,
+                    price: Money::new(Decimal::new(10, 0), "USD"),
+                    version: 0,
+                    ..Default::default()
                 },
             },
             Event::Deleted {
+                event_id: "test-event".to_string(),
                 product: Product {
                     id: "test-id-2".to_string(),
                     name: "test-name-2".to_string(),
-                    price: 20.0,
+                    price: Money::new(Decimal::new(20, 0), "USD"),
+                    version: 0,
+                    ..Default::default()
                 },
             },
         ];
@@ -185,7 +341,7 @@ mod tests {
 
         // THEN the request should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 1);
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
@@ -198,11 +354,11 @@ mod tests {
         let event_bus = EventBridgeBus::new(client, "test-bus".to_string());
 
         // WHEN we send zero events
-        event_bus.send_events(&vec![]).await?;
+        event_bus.send_events(&[]).await?;
 
         // THEN no request should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 0);
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
@@ -214,7 +370,7 @@ mod tests {
             get_request_builder()
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
-                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-0"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-0\",\"name\":\"test-name-0\",\"price\":10.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-1"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-1\",\"name\":\"test-name-1\",\"price\":11.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-2"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-2\",\"name\":\"test-name-2\",\"price\":12.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-3"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-3\",\"name\":\"test-name-3\",\"price\":13.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-4"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-4\",\"name\":\"test-name-4\",\"price\":14.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-5"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-5\",\"name\":\"test-name-5\",\"price\":15.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-6"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-6\",\"name\":\"test-name-6\",\"price\":16.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-7"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-7\",\"name\":\"test-name-7\",\"price\":17.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-8"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-8\",\"name\":\"test-name-8\",\"price\":18.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-9"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-9\",\"name\":\"test-name-9\",\"price\":19.0}}","EventBusName":"test-bus"}]}"#,
+                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-0"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-0\",\"name\":\"test-name-0\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"10\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-1"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-1\",\"name\":\"test-name-1\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"11\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-2"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-2\",\"name\":\"test-name-2\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"12\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-3"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-3\",\"name\":\"test-name-3\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"13\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-4"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-4\",\"name\":\"test-name-4\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"14\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-5"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-5\",\"name\":\"test-name-5\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"15\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-6"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-6\",\"name\":\"test-name-6\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"16\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-7"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-7\",\"name\":\"test-name-7\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"17\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-8"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-8\",\"name\":\"test-name-8\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"18\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-9"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-9\",\"name\":\"test-name-9\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"19\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
             http::Response::builder()
@@ -225,7 +381,7 @@ mod tests {
             get_request_builder()
                 .header("x-amz-target", "AWSEvents.PutEvents")
                 .body(SdkBody::from(
-                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-10"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-10\",\"name\":\"test-name-10\",\"price\":20.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-11"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-11\",\"name\":\"test-name-11\",\"price\":21.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-12"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-12\",\"name\":\"test-name-12\",\"price\":22.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-13"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-13\",\"name\":\"test-name-13\",\"price\":23.0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-14"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"product\":{\"id\":\"test-id-14\",\"name\":\"test-name-14\",\"price\":24.0}}","EventBusName":"test-bus"}]}"#,
+                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-10"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-10\",\"name\":\"test-name-10\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"20\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-11"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-11\",\"name\":\"test-name-11\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"21\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-12"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-12\",\"name\":\"test-name-12\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"22\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-13"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-13\",\"name\":\"test-name-13\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"23\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-14"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-14\",\"name\":\"test-name-14\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"24\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
                 ))
                 .unwrap(),
             http::Response::builder()
@@ -239,10 +395,13 @@ mod tests {
         // WHEN we send 15 events
         let events = (0..15)
             .map(|i| Event::Created {
+                event_id: "test-event".to_string(),
                 product: Product {
                     id: format!("test-id-{}", i),
                     name: format!("test-name-{}", i),
-                    price: 10.0 + i as f64,
+                    price: Money::new(Decimal::from(10 + i), "USD"),
+                    version: 0,
+                    ..Default::default()
                 },
             })
             .collect::<Vec<_>>();
@@ -250,8 +409,135 @@ mod tests {
 
         // THEN two requests should have been sent to EventBridge
         assert_eq!(conn.requests().len(), 2);
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
+
+    fn product_event(i: i64) -> Event {
+        Event::Created {
+            event_id: "test-event".to_string(),
+            product: Product {
+                id: format!("test-id-{}", i),
+                name: format!("test-name-{}", i),
+                price: Money::new(Decimal::from(10 + i), "USD"),
+                version: 0,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn no_delay() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_events_partial_failure() -> Result<(), Error> {
+        // GIVEN a mock EventBridge client that rejects the second entry once
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "AWSEvents.PutEvents")
+                .body(SdkBody::from(
+                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-0"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-0\",\"name\":\"test-name-0\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"10\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"},{"Source":"rust-products","Resources":["test-id-1"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-1\",\"name\":\"test-name-1\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"11\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"FailedEntryCount": 1, "Entries": [{"EventId": "event-0"}, {"ErrorCode": "InternalFailure", "ErrorMessage": "Internal failure"}]}"#,
+                ))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "AWSEvents.PutEvents")
+                .body(SdkBody::from(
+                    r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-1"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-1\",\"name\":\"test-name-1\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"11\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"FailedEntryCount": 0, "Entries": [{"EventId": "event-1"}]}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let event_bus =
+            EventBridgeBus::new(client, "test-bus".to_string()).with_retry_policy(no_delay());
+
+        // WHEN we send two events
+        let events = (0..2).map(product_event).collect::<Vec<_>>();
+        event_bus.send_events(&events).await?;
+
+        // THEN only the failed event should have been sent again
+        assert_eq!(conn.requests().len(), 2);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_retries_exhausted() -> Result<(), Error> {
+        // GIVEN a mock EventBridge client that always rejects the entry
+        let failure = || {
+            (
+                get_request_builder()
+                    .header("x-amz-target", "AWSEvents.PutEvents")
+                    .body(SdkBody::from(
+                        r#"{"Entries":[{"Source":"rust-products","Resources":["test-id-0"],"DetailType":"ProductCreated","Detail":"{\"type\":\"Created\",\"schema_version\":5,\"event_id\":\"test-event\",\"product\":{\"id\":\"test-id-0\",\"name\":\"test-name-0\",\"tags\":[],\"image_urls\":[],\"attributes\":{},\"price\":{\"amount\":\"10\",\"currency\":\"USD\"},\"status\":\"active\",\"version\":0}}","EventBusName":"test-bus"}]}"#,
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(
+                        r#"{"FailedEntryCount": 1, "Entries": [{"ErrorCode": "ThrottlingException", "ErrorMessage": "Rate exceeded"}]}"#,
+                    ))
+                    .unwrap(),
+            )
+        };
+        let conn = TestConnection::new(vec![failure(), failure(), failure()]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let event_bus =
+            EventBridgeBus::new(client, "test-bus".to_string()).with_retry_policy(no_delay());
+
+        // WHEN we send an event
+        let res = event_bus.send_events(&[product_event(0)]).await;
+
+        // THEN the event should have been sent once per attempt
+        assert_eq!(conn.requests().len(), 3);
+        conn.assert_requests_match(&[]);
+        // AND the error lists the failed event
+        match res {
+            Err(Error::PublishFailed(failed)) => assert_eq!(
+                failed,
+                vec![FailedEvent {
+                    index: 0,
+                    id: "test-id-0".to_string(),
+                    code: "ThrottlingException".to_string(),
+                    message: "Rate exceeded".to_string(),
+                }]
+            ),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(250),
+        };
+
+        assert!(policy.backoff(1) <= Duration::from_millis(100));
+        assert!(policy.backoff(2) <= Duration::from_millis(200));
+        assert!(policy.backoff(3) <= Duration::from_millis(250));
+        assert!(policy.backoff(10) <= Duration::from_millis(250));
+    }
 }
@@ -0,0 +1,239 @@
+//! # In-memory event bus implementation
+//!
+//! This bus keeps published events in memory instead of sending them
+//! anywhere. It is meant for tests: events can be inspected once published, or
+//! received as they are published by subscribing to the bus.
+
+use super::EventBus;
+use crate::{Error, Event};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+/// Number of events a subscriber can lag behind before missing events
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// In-memory event bus
+pub struct MemoryBus {
+    events: Mutex<Vec<Event>>,
+    notify: Notify,
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self {
+            events: Default::default(),
+            notify: Notify::new(),
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Receive events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Events published so far, in order
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Remove and return the events published so far
+    pub fn drain(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+
+    /// Events published so far that match a predicate
+    pub fn filter<F>(&self, predicate: F) -> Vec<Event>
+    where
+        F: Fn(&Event) -> bool,
+    {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| predicate(event))
+            .cloned()
+            .collect()
+    }
+
+    /// `Created` events published so far
+    pub fn created(&self) -> Vec<Event> {
+        self.filter(|event| matches!(event, Event::Created { .. }))
+    }
+
+    /// `Updated` events published so far
+    pub fn updated(&self) -> Vec<Event> {
+        self.filter(|event| matches!(event, Event::Updated { .. }))
+    }
+
+    /// `Deleted` events published so far
+    pub fn deleted(&self) -> Vec<Event> {
+        self.filter(|event| matches!(event, Event::Deleted { .. }))
+    }
+
+    /// Wait until at least `n` events are stored, and return them
+    ///
+    /// This returns an error if fewer than `n` events were published before
+    /// the timeout.
+    pub async fn wait_for(&self, n: usize, timeout: Duration) -> Result<Vec<Event>, Error> {
+        let wait = async {
+            loop {
+                // Register for notifications before checking, so that events
+                // published in between are not missed.
+                let notified = self.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+
+                let events = self.events();
+                if events.len() >= n {
+                    return events;
+                }
+                notified.await;
+            }
+        };
+
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| Error::InternalError("Timed out waiting for events"))
+    }
+
+    fn publish(&self, events: &[Event]) {
+        self.events.lock().unwrap().extend_from_slice(events);
+        for event in events {
+            // Sending only fails if there are no subscribers
+            let _ = self.sender.send(event.clone());
+        }
+        self.notify.notify_waiters();
+    }
+}
+
+#[async_trait]
+impl EventBus for MemoryBus {
+    type E = Event;
+
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        self.publish(std::slice::from_ref(event));
+        Ok(())
+    }
+
+    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
+        self.publish(events);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Money, Product};
+    use rust_decimal::Decimal;
+    use std::sync::Arc;
+
+    fn product(id: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            name: "test".to_string(),
+            price: Money::new(Decimal::new(10, 0), "USD"),
+            version: 0,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), Error> {
+        // GIVEN an empty bus
+        let bus = MemoryBus::new();
+
+        // WHEN we send events
+        bus.send_event(&Event::Created {
+            event_id: "test-event".to_string(),
+            product: product("1"),
+        })
+        .await?;
+        bus.send_events(&[
+            Event::Deleted {
+                event_id: "test-event".to_string(),
+                product: product("1"),
+            },
+            Event::Created {
+                event_id: "test-event".to_string(),
+                product: product("2"),
+            },
+        ])
+        .await?;
+
+        // THEN the events are stored in order
+        let ids = bus
+            .events()
+            .iter()
+            .map(|e| e.id().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["1", "1", "2"]);
+        // AND can be filtered by type
+        assert_eq!(bus.created().len(), 2);
+        assert_eq!(bus.deleted().len(), 1);
+        assert_eq!(bus.updated().len(), 0);
+        // AND drained
+        assert_eq!(bus.drain().len(), 3);
+        assert!(bus.events().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() -> Result<(), Error> {
+        // GIVEN a bus with a subscriber
+        let bus = MemoryBus::new();
+        let mut receiver = bus.subscribe();
+
+        // WHEN we send an event
+        let event = Event::Created {
+            event_id: "test-event".to_string(),
+            product: product("1"),
+        };
+        bus.send_event(&event).await?;
+
+        // THEN the subscriber receives it
+        assert_eq!(receiver.recv().await.unwrap(), event);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for() -> Result<(), Error> {
+        // GIVEN a bus that receives events from another task
+        let bus = Arc::new(MemoryBus::new());
+        let sender = bus.clone();
+        tokio::spawn(async move {
+            for id in ["1", "2"] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                sender
+                    .send_event(&Event::Created {
+                        event_id: "test-event".to_string(),
+                        product: product(id),
+                    })
+                    .await
+                    .unwrap();
+            }
+        });
+
+        // WHEN we wait for two events
+        let events = bus.wait_for(2, Duration::from_secs(5)).await?;
+
+        // THEN both events are returned
+        assert_eq!(events.len(), 2);
+        // AND waiting for more times out
+        assert!(bus.wait_for(3, Duration::from_millis(10)).await.is_err());
+
+        Ok(())
+    }
+}
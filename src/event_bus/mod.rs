@@ -1,10 +1,15 @@
 use crate::Error;
 use async_trait::async_trait;
+use serde::Serialize;
 
+pub mod cloudevents;
 mod eventbridge;
+mod memory;
 mod void;
 
-pub use eventbridge::EventBridgeBus;
+pub use cloudevents::{CloudEvent, Envelope};
+pub use eventbridge::{EventBridgeBus, RetryPolicy};
+pub use memory::MemoryBus;
 pub use void::VoidBus;
 
 #[async_trait]
@@ -14,3 +19,14 @@ pub trait EventBus {
     async fn send_event(&self, event: &Self::E) -> Result<(), Error>;
     async fn send_events(&self, events: &[Self::E]) -> Result<(), Error>;
 }
+
+/// An event that the bus did not accept
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FailedEvent {
+    /// Position of the event in the slice passed to the bus
+    pub index: usize,
+    /// Id of the product the event is about
+    pub id: String,
+    pub code: String,
+    pub message: String,
+}
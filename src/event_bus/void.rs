@@ -27,16 +27,20 @@ impl EventBus for VoidBus {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Product;
+    use crate::{Money, Product};
+    use rust_decimal::Decimal;
 
     #[tokio::test]
     async fn test_send_event() {
         let bus = VoidBus;
         let event = Event::Created {
+            event_id: "test-event".to_string(),
             product: Product {
                 id: "123".to_string(),
                 name: "test".to_string(),
-                price: 10.0,
+                price: Money::new(Decimal::new(10, 0), "USD"),
+                version: 0,
+                ..Default::default()
             },
         };
         let result = bus.send_event(&event).await;
@@ -47,10 +51,13 @@ mod tests {
     async fn test_send_events() {
         let bus = VoidBus;
         let event = Event::Created {
+            event_id: "test-event".to_string(),
             product: Product {
                 id: "123".to_string(),
                 name: "test".to_string(),
-                price: 10.0,
+                price: Money::new(Decimal::new(10, 0), "USD"),
+                version: 0,
+                ..Default::default()
             },
         };
         let result = bus.send_events(&[event]).await;
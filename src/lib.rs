@@ -1,16 +1,21 @@
 //! # Domain logic for the service
 
+mod cursor;
 pub mod domain;
 pub mod entrypoints;
 mod error;
 pub mod event_bus;
 mod model;
+pub mod schema;
 pub mod store;
 pub mod utils;
 
 pub use error::Error;
 use event_bus::EventBus;
-pub use model::{Event, Product, ProductRange};
+pub use model::{
+    BatchRequest, BatchResponse, BatchResult, Change, ChangeOp, Event, IdempotencyRecord,
+    MergePatch, Money, Product, ProductRange, ProductStatus, PutOutcome, RecordedResponse,
+};
 
 /// Event Service
 ///
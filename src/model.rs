@@ -2,13 +2,189 @@
 //!
 //! This module contains the representations of the products.
 
-use serde::{Deserialize, Serialize};
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// Product in the catalog
+///
+/// Only `id`, `name` and `price` are required: the other fields were added
+/// later, and products stored before that read with their default values.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct Product {
     pub id: String,
     pub name: String,
-    pub price: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Stock keeping unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub image_urls: Vec<String>,
+    /// Free-form attributes, such as a color or a size
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    pub price: Money,
+    #[serde(default)]
+    pub status: ProductStatus,
+    /// Version of the product, incremented by the store on every write
+    ///
+    /// Products written before versioning was introduced are at version 0.
+    #[serde(default)]
+    pub version: u64,
+    /// When the product was first stored, set by the domain layer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the product was last stored, set by the domain layer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// When the product was soft-deleted, set by the store
+    ///
+    /// Soft-deleted products are hidden until they are restored or purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Product {
+    /// Whether the product was soft-deleted
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+/// Fields of a product that change on every write
+///
+/// They are left out when comparing products, as they do not tell anything
+/// about what changed.
+pub(crate) const WRITE_METADATA: [&str; 2] = ["version", "updated_at"];
+
+/// Lifecycle of a product
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductStatus {
+    /// Being prepared, not visible in the storefront yet
+    Draft,
+    /// Visible in the storefront
+    ///
+    /// Products stored before statuses were introduced are active.
+    #[default]
+    Active,
+    /// No longer sold
+    Archived,
+}
+
+/// Currency of prices that were stored without one
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// Exact amount of money in a given currency
+///
+/// The amount is a decimal number, so that values such as `0.1 + 0.2` are
+/// represented exactly. In JSON, the amount is serialized as a string to
+/// avoid clients parsing it as a float:
+///
+/// ```json
+/// { "amount": "10.50", "currency": "USD" }
+/// ```
+///
+/// For backwards compatibility, a bare number or string is also accepted and
+/// read in the default currency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Money {
+    pub amount: Decimal,
+    /// ISO 4217 currency code
+    pub currency: String,
+}
+
+impl Default for Money {
+    /// Zero in the default currency
+    fn default() -> Self {
+        Self::new(Decimal::ZERO, DEFAULT_CURRENCY)
+    }
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: &str) -> Self {
+        Self {
+            amount,
+            currency: currency.to_owned(),
+        }
+    }
+
+    /// Parse an amount in the default currency
+    ///
+    /// This accepts the string representation of a DynamoDB number, as well as
+    /// numbers in scientific notation written by older versions of the
+    /// service.
+    pub fn parse(amount: &str) -> Option<Self> {
+        let amount = Decimal::from_str(amount)
+            .or_else(|_| Decimal::from_scientific(amount))
+            .ok()?;
+        Some(Self::new(amount, DEFAULT_CURRENCY))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Repr<'a> {
+            amount: String,
+            currency: &'a str,
+        }
+
+        Repr {
+            amount: self.amount.to_string(),
+            currency: &self.currency,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Amount {
+            String(String),
+            Number(serde_json::Number),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Money { amount: Amount, currency: String },
+            Legacy(Amount),
+        }
+
+        let (amount, currency) = match Repr::deserialize(deserializer)? {
+            Repr::Money { amount, currency } => (amount, currency),
+            Repr::Legacy(amount) => (amount, DEFAULT_CURRENCY.to_owned()),
+        };
+        // Integers are kept as they are, while floats from the legacy JSON
+        // format are converted through their shortest representation, so
+        // that `10.5` becomes exactly `10.5`.
+        let amount = match amount {
+            Amount::String(amount) => amount,
+            Amount::Number(amount) => amount.to_string(),
+        };
+        let money = Money::parse(&amount)
+            .ok_or_else(|| de::Error::custom(format!("invalid amount: {}", amount)))?;
+        Ok(Money { currency, ..money })
+    }
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -18,20 +194,690 @@ pub struct ProductRange {
     pub next: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(tag = "type")]
+/// JSON Merge Patch (RFC 7386) to apply to a product
+///
+/// Fields set to `null` are removed, objects are merged recursively, and any
+/// other value replaces the current one.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MergePatch(pub serde_json::Map<String, serde_json::Value>);
+
+impl MergePatch {
+    /// Apply the patch to a product
+    ///
+    /// This returns a `ClientError` if the patched product is not a valid
+    /// product, e.g. because the patch removed its name.
+    pub fn apply(&self, product: &Product) -> Result<Product, Error> {
+        let mut value = serde_json::to_value(product).unwrap();
+        merge(&mut value, &serde_json::Value::Object(self.0.clone()));
+        serde_json::from_value(value)
+            .map_err(|_| Error::ClientError("Patch does not result in a valid product"))
+    }
+}
+
+fn merge(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    use serde_json::Value;
+
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Outcome of storing a product
+#[derive(Clone, Debug, PartialEq)]
+pub struct PutOutcome {
+    /// Product as stored
+    pub product: Product,
+    /// Product that was replaced, if there was one
+    pub previous: Option<Product>,
+}
+
+impl PutOutcome {
+    /// Whether the put created a new product rather than replacing one
+    pub fn is_created(&self) -> bool {
+        self.previous.is_none()
+    }
+}
+
+/// Request recorded under an idempotency key
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdempotencyRecord {
+    /// Hash of the request that first used the key
+    pub fingerprint: String,
+    /// Response to replay, or `None` while the request is still in progress
+    pub response: Option<RecordedResponse>,
+    /// When the key can be used again for another request
+    pub expires_at: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// HTTP response replayed for a repeated idempotency key
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    /// Headers to replay, such as `ETag` or `Location`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+/// Operations of a bulk request
+///
+/// Every id can only appear once across `put` and `delete`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BatchRequest {
+    /// Ids of the products to retrieve
+    #[serde(default)]
+    pub get: Vec<String>,
+    /// Products to create or replace
+    #[serde(default)]
+    pub put: Vec<Product>,
+    /// Ids of the products to delete
+    #[serde(default)]
+    pub delete: Vec<String>,
+}
+
+/// Outcome of a bulk request, with one result per operation, in the order
+/// of the request
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BatchResponse {
+    pub get: Vec<BatchResult>,
+    pub put: Vec<BatchResult>,
+    pub delete: Vec<BatchResult>,
+}
+
+/// Outcome of a single operation of a bulk request
+///
+/// `status` is the HTTP status code the operation would have returned on its
+/// own.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct BatchResult {
+    pub id: String,
+    pub status: u16,
+    /// Product as retrieved or stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<Product>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    pub fn ok(id: &str, status: u16, product: Option<Product>) -> Self {
+        Self {
+            id: id.to_string(),
+            status,
+            product,
+            error: None,
+        }
+    }
+
+    pub fn error(id: &str, err: &Error) -> Self {
+        Self {
+            id: id.to_string(),
+            status: err.status_code(),
+            product: None,
+            error: Some(err.detail()),
+        }
+    }
+}
+
+/// Change to a product
+///
+/// Every event carries an `event_id` that stays the same when the event is
+/// delivered more than once, so that consumers can discard duplicates.
+///
+/// Events are serialized with their `type` and `schema_version`, and payloads
+/// written with an older schema version are upcasted when deserialized. See
+/// the `schema` module.
+// Updates carry two products, but events are short-lived and not worth boxing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Event {
-    Created { product: Product },
-    Updated { old: Product, new: Product },
-    Deleted { product: Product },
+    Created {
+        event_id: String,
+        product: Product,
+    },
+    Updated {
+        event_id: String,
+        old: Product,
+        new: Product,
+        /// Fields that changed from `old` to `new`
+        changes: Vec<Change>,
+    },
+    /// The product was deleted, or soft-deleted if it has a `deleted_at`
+    Deleted {
+        event_id: String,
+        product: Product,
+    },
+    /// A soft-deleted product was restored
+    Restored {
+        event_id: String,
+        product: Product,
+    },
+    /// A soft-deleted product was permanently removed
+    Purged {
+        event_id: String,
+        product: Product,
+    },
 }
 
 impl Event {
+    /// Create an `Updated` event, with the changes between the products
+    pub fn updated(event_id: String, old: Product, new: Product) -> Self {
+        let changes = Change::diff(&old, &new);
+        Event::Updated {
+            event_id,
+            old,
+            new,
+            changes,
+        }
+    }
+
+    /// Whether the event is an update that did not change any field
+    pub fn is_noop(&self) -> bool {
+        matches!(self, Event::Updated { changes, .. } if changes.is_empty())
+    }
+
+    /// Type of the event, as written in the `type` field
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Event::Created { .. } => "Created",
+            Event::Updated { .. } => "Updated",
+            Event::Deleted { .. } => "Deleted",
+            Event::Restored { .. } => "Restored",
+            Event::Purged { .. } => "Purged",
+        }
+    }
+
+    /// Id of the product the event is about
     pub fn id(&self) -> &str {
         match self {
-            Event::Created { product } => product.id.as_str(),
+            Event::Created { product, .. } => product.id.as_str(),
             Event::Updated { new, .. } => new.id.as_str(),
-            Event::Deleted { product } => product.id.as_str(),
+            Event::Deleted { product, .. }
+            | Event::Restored { product, .. }
+            | Event::Purged { product, .. } => product.id.as_str(),
+        }
+    }
+
+    /// Time of the change, from the timestamps of the product
+    ///
+    /// Hard deletes and purges remove the product without writing it, so
+    /// they have no time of their own.
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Event::Created { product, .. } | Event::Restored { product, .. } => product.updated_at,
+            Event::Updated { new, .. } => new.updated_at,
+            Event::Deleted { product, .. } => product.deleted_at,
+            Event::Purged { .. } => None,
+        }
+    }
+
+    /// Id of the event itself
+    pub fn event_id(&self) -> &str {
+        match self {
+            Event::Created { event_id, .. }
+            | Event::Updated { event_id, .. }
+            | Event::Deleted { event_id, .. }
+            | Event::Restored { event_id, .. }
+            | Event::Purged { event_id, .. } => event_id.as_str(),
+        }
+    }
+}
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = if let Event::Updated { .. } = self {
+            6
+        } else {
+            4
+        };
+        let mut state = serializer.serialize_struct("Event", len)?;
+        state.serialize_field("type", self.event_type())?;
+        state.serialize_field("schema_version", &SCHEMA_VERSION)?;
+        state.serialize_field("event_id", self.event_id())?;
+        match self {
+            Event::Created { product, .. }
+            | Event::Deleted { product, .. }
+            | Event::Restored { product, .. }
+            | Event::Purged { product, .. } => {
+                state.serialize_field("product", product)?;
+            }
+            Event::Updated {
+                old, new, changes, ..
+            } => {
+                state.serialize_field("old", old)?;
+                state.serialize_field("new", new)?;
+                state.serialize_field("changes", changes)?;
+            }
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// Event at the current schema version
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Deserialize)]
+        #[serde(tag = "type")]
+        enum Repr {
+            Created {
+                event_id: String,
+                product: Product,
+            },
+            Updated {
+                event_id: String,
+                old: Product,
+                new: Product,
+                changes: Vec<Change>,
+            },
+            Deleted {
+                event_id: String,
+                product: Product,
+            },
+            Restored {
+                event_id: String,
+                product: Product,
+            },
+            Purged {
+                event_id: String,
+                product: Product,
+            },
+        }
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let value = schema::upcasters()
+            .upcast(value)
+            .map_err(de::Error::custom)?;
+        Ok(match Repr::deserialize(value).map_err(de::Error::custom)? {
+            Repr::Created { event_id, product } => Event::Created { event_id, product },
+            Repr::Updated {
+                event_id,
+                old,
+                new,
+                changes,
+            } => Event::Updated {
+                event_id,
+                old,
+                new,
+                changes,
+            },
+            Repr::Deleted { event_id, product } => Event::Deleted { event_id, product },
+            Repr::Restored { event_id, product } => Event::Restored { event_id, product },
+            Repr::Purged { event_id, product } => Event::Purged { event_id, product },
+        })
+    }
+}
+
+/// Change to a field of a product, as a JSON Patch (RFC 6902) operation
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change {
+    pub op: ChangeOp,
+    /// JSON Pointer to the field
+    pub path: String,
+    /// New value of the field, absent when it was removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Add,
+    Remove,
+    Replace,
+}
+
+impl Change {
+    /// Compute the changes that turn `old` into `new`
+    ///
+    /// The version and update time are not compared, as they change on every
+    /// write even when nothing else changed.
+    pub fn diff(old: &Product, new: &Product) -> Vec<Change> {
+        let to_value = |product: &Product| {
+            let mut value = serde_json::to_value(product).unwrap();
+            for field in WRITE_METADATA {
+                value.as_object_mut().unwrap().remove(field);
+            }
+            value
+        };
+        Self::diff_values(&to_value(old), &to_value(new))
+    }
+
+    /// Compute the changes between two JSON values
+    ///
+    /// Objects are compared field by field, and any other value is replaced
+    /// as a whole.
+    pub(crate) fn diff_values(old: &serde_json::Value, new: &serde_json::Value) -> Vec<Change> {
+        let mut changes = Vec::new();
+        diff_into(&mut changes, String::new(), old, new);
+        changes
+    }
+}
+
+fn diff_into(
+    changes: &mut Vec<Change>,
+    path: String,
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+) {
+    use serde_json::Value;
+
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            // Keys are escaped as JSON Pointer reference tokens (RFC 6901)
+            let child =
+                |key: &str| format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+            for (key, old_value) in old {
+                match new.get(key) {
+                    Some(new_value) => diff_into(changes, child(key), old_value, new_value),
+                    None => changes.push(Change {
+                        op: ChangeOp::Remove,
+                        path: child(key),
+                        value: None,
+                    }),
+                }
+            }
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    changes.push(Change {
+                        op: ChangeOp::Add,
+                        path: child(key),
+                        value: Some(new_value.clone()),
+                    });
+                }
+            }
         }
+        (old, new) if old != new => changes.push(Change {
+            op: ChangeOp::Replace,
+            path,
+            value: Some(new.clone()),
+        }),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_money_serialize() {
+        let money = Money::new(Decimal::new(1050, 2), "EUR");
+
+        assert_eq!(
+            serde_json::to_string(&money).unwrap(),
+            r#"{"amount":"10.50","currency":"EUR"}"#
+        );
+    }
+
+    #[test]
+    fn test_money_deserialize() {
+        let money: Money = serde_json::from_str(r#"{"amount":"0.3","currency":"EUR"}"#).unwrap();
+
+        assert_eq!(money, Money::new(Decimal::new(3, 1), "EUR"));
+    }
+
+    #[test]
+    fn test_money_deserialize_number() {
+        let money: Money = serde_json::from_str(r#"{"amount":10.5,"currency":"EUR"}"#).unwrap();
+
+        assert_eq!(money, Money::new(Decimal::new(105, 1), "EUR"));
+    }
+
+    #[test]
+    fn test_money_deserialize_large_integer() {
+        let money: Money =
+            serde_json::from_str(r#"{"amount":9007199254740993,"currency":"EUR"}"#).unwrap();
+
+        assert_eq!(
+            money,
+            Money::new(Decimal::from(9_007_199_254_740_993u64), "EUR")
+        );
+    }
+
+    #[test]
+    fn test_money_deserialize_legacy() {
+        let money: Money = serde_json::from_str("10.5").unwrap();
+
+        assert_eq!(money, Money::new(Decimal::new(105, 1), DEFAULT_CURRENCY));
+    }
+
+    #[test]
+    fn test_money_deserialize_invalid() {
+        assert!(serde_json::from_str::<Money>(r#"{"amount":"ten","currency":"EUR"}"#).is_err());
+    }
+
+    #[test]
+    fn test_money_roundtrip() {
+        let money = Money::new(Decimal::from_str("0.30000000000000004").unwrap(), "USD");
+
+        let json = serde_json::to_string(&money).unwrap();
+        assert_eq!(serde_json::from_str::<Money>(&json).unwrap(), money);
+    }
+
+    #[test]
+    fn test_money_parse_scientific() {
+        assert_eq!(
+            Money::parse("1e-7"),
+            Some(Money::new(Decimal::new(1, 7), DEFAULT_CURRENCY))
+        );
+    }
+
+    fn product() -> Product {
+        Product {
+            id: "product-1".to_string(),
+            name: "test".to_string(),
+            price: Money::new(Decimal::new(1050, 2), "EUR"),
+            version: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // GIVEN a product with attributes
+        let mut product = product();
+        product
+            .attributes
+            .insert("color".to_string(), "red".to_string());
+        product
+            .attributes
+            .insert("size".to_string(), "M".to_string());
+
+        // WHEN applying a patch that changes, removes and merges fields
+        let patch: MergePatch = serde_json::from_str(
+            r#"{"name":"new","sku":"SKU-1","attributes":{"size":null},"price":{"amount":"12"}}"#,
+        )
+        .unwrap();
+        let patched = patch.apply(&product).unwrap();
+
+        // THEN only the patched fields change
+        assert_eq!(patched.name, "new");
+        assert_eq!(patched.sku.as_deref(), Some("SKU-1"));
+        assert_eq!(
+            patched.attributes,
+            BTreeMap::from([("color".to_string(), "red".to_string())])
+        );
+        assert_eq!(patched.price, Money::new(Decimal::new(12, 0), "EUR"));
+        assert_eq!(patched.version, product.version);
+    }
+
+    #[test]
+    fn test_merge_patch_invalid() {
+        let patch: MergePatch = serde_json::from_str(r#"{"name":null}"#).unwrap();
+
+        assert!(matches!(
+            patch.apply(&product()),
+            Err(Error::ClientError(_))
+        ));
+    }
+
+    #[test]
+    fn test_event_wire_format() {
+        let event = Event::updated(
+            "e1".to_string(),
+            product(),
+            Product {
+                name: "new".to_string(),
+                version: 4,
+                ..product()
+            },
+        );
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            concat!(
+                r#"{"type":"Updated","schema_version":5,"event_id":"e1","#,
+                r#""old":{"id":"product-1","name":"test","tags":[],"image_urls":[],"attributes":{},"#,
+                r#""price":{"amount":"10.50","currency":"EUR"},"status":"active","version":3},"#,
+                r#""new":{"id":"product-1","name":"new","tags":[],"image_urls":[],"attributes":{},"#,
+                r#""price":{"amount":"10.50","currency":"EUR"},"status":"active","version":4},"#,
+                r#""changes":[{"op":"replace","path":"/name","value":"new"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_event_roundtrip() {
+        let event = Event::Created {
+            event_id: "e1".to_string(),
+            product: product(),
+        };
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_event_deserialize_v1() {
+        let event: Event = serde_json::from_str(
+            r#"{"type":"Deleted","product":{"id":"product-1","name":"test","price":10.5}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            event,
+            Event::Deleted {
+                event_id: "".to_string(),
+                product: Product {
+                    price: Money::new(Decimal::new(105, 1), DEFAULT_CURRENCY),
+                    version: 0,
+                    ..product()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_event_matches_schema() {
+        // Every field written on the wire must be declared in the schema
+        let event = Event::Deleted {
+            event_id: "e1".to_string(),
+            product: product(),
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        let schema = schema::json_schema(event.event_type()).unwrap();
+
+        for (key, field) in value.as_object().unwrap() {
+            assert!(schema["properties"].get(key).is_some(), "{}", key);
+            if key == "product" {
+                for key in field.as_object().unwrap().keys() {
+                    assert!(
+                        schema["properties"]["product"]["properties"]
+                            .get(key)
+                            .is_some(),
+                        "product.{}",
+                        key
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            schema["properties"]["schema_version"]["const"],
+            value["schema_version"]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        // GIVEN a product with a new name and price
+        let new = Product {
+            name: "new".to_string(),
+            price: Money::new(Decimal::new(1200, 2), "EUR"),
+            version: 4,
+            ..product()
+        };
+
+        // WHEN computing the changes
+        let changes = Change::diff(&product(), &new);
+
+        // THEN only the changed fields are listed
+        assert_eq!(
+            serde_json::to_value(changes).unwrap(),
+            serde_json::json!([
+                { "op": "replace", "path": "/name", "value": "new" },
+                { "op": "replace", "path": "/price/amount", "value": "12.00" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_diff_add_remove() {
+        let changes = Change::diff_values(
+            &serde_json::json!({ "a": 1, "b/c": 2 }),
+            &serde_json::json!({ "a": 1, "d": 3 }),
+        );
+
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    op: ChangeOp::Remove,
+                    path: "/b~1c".to_string(),
+                    value: None,
+                },
+                Change {
+                    op: ChangeOp::Add,
+                    path: "/d".to_string(),
+                    value: Some(serde_json::json!(3)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_noop() {
+        // Only the version changed
+        let event = Event::updated(
+            "e1".to_string(),
+            product(),
+            Product {
+                version: 4,
+                ..product()
+            },
+        );
+
+        assert!(event.is_noop());
     }
 }
@@ -0,0 +1,387 @@
+//! # Event schemas
+//!
+//! Every event is serialized with a `schema_version`, which is bumped whenever
+//! the shape of the payload changes. Consumers can check the version against
+//! the JSON Schema of the event type, and this service reads payloads written
+//! with older versions by upcasting them to the current shape. Only the
+//! versions that changed the payload of an event type have an upcaster, the
+//! others are skipped.
+//!
+//! Payloads without a `schema_version` predate versioning and are read as
+//! version 1.
+
+use crate::{
+    model::{Money, WRITE_METADATA},
+    Change,
+};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+/// Current version of the event schemas
+pub const SCHEMA_VERSION: u32 = 5;
+
+/// Event types, as written in the `type` field
+pub const EVENT_TYPES: [&str; 5] = ["Created", "Updated", "Deleted", "Restored", "Purged"];
+
+/// Transform an event payload from one schema version to the next
+pub type Upcaster = fn(Value) -> Result<Value, String>;
+
+/// Registry of upcasters, by event type and version they read
+///
+/// Versions without an upcaster for an event type did not change its
+/// payload, e.g. because they only added fields with defaults.
+pub struct Upcasters {
+    steps: BTreeMap<(String, u32), Upcaster>,
+}
+
+impl Default for Upcasters {
+    /// Registry with the upcasters for all past versions of the events
+    ///
+    /// `Restored` and `Purged` were added in version 5, so they have none.
+    fn default() -> Self {
+        Self::new()
+            .register("Created", 1, v1_to_v2)
+            .register("Updated", 1, v1_to_v2)
+            .register("Deleted", 1, v1_to_v2)
+            .register("Updated", 2, v2_to_v3)
+    }
+}
+
+impl Upcasters {
+    /// Empty registry
+    pub fn new() -> Self {
+        Self {
+            steps: BTreeMap::new(),
+        }
+    }
+
+    /// Register an upcaster reading version `from` of an event type and
+    /// returning version `from + 1`
+    pub fn register(mut self, event_type: &str, from: u32, upcaster: Upcaster) -> Self {
+        self.steps.insert((event_type.to_string(), from), upcaster);
+        self
+    }
+
+    /// Upcast a payload to the current schema version
+    ///
+    /// The returned payload has its `schema_version` set to `SCHEMA_VERSION`.
+    pub fn upcast(&self, mut value: Value) -> Result<Value, String> {
+        let event_type = value
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or("missing event type")?
+            .to_string();
+        let version = match value.get("schema_version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or("invalid schema version")?,
+        };
+        if version > SCHEMA_VERSION {
+            return Err(format!("unsupported schema version {}", version));
+        }
+
+        let steps = self
+            .steps
+            .range((event_type.clone(), version)..(event_type, SCHEMA_VERSION));
+        for (_, upcaster) in steps {
+            value = upcaster(value)?;
+        }
+
+        value
+            .as_object_mut()
+            .ok_or("event is not an object")?
+            .insert("schema_version".to_string(), json!(SCHEMA_VERSION));
+        Ok(value)
+    }
+}
+
+/// Upcasters shared by all the events read by this service
+pub fn upcasters() -> &'static Upcasters {
+    static UPCASTERS: OnceLock<Upcasters> = OnceLock::new();
+    UPCASTERS.get_or_init(Upcasters::default)
+}
+
+/// Version 1 to 2: prices became money objects, and products and events
+/// gained a version and an id
+///
+/// Payloads that already use money objects were written between those
+/// changes and before schema versions, so they are left untouched.
+fn v1_to_v2(mut value: Value) -> Result<Value, String> {
+    let event = value.as_object_mut().ok_or("event is not an object")?;
+    event
+        .entry("event_id")
+        .or_insert_with(|| Value::String(String::new()));
+    for key in ["product", "old", "new"] {
+        if let Some(product) = event.get_mut(key) {
+            let product = product
+                .as_object_mut()
+                .ok_or_else(|| format!("{} is not an object", key))?;
+            product.entry("version").or_insert_with(|| json!(0));
+            if let Some(price) = product.get_mut("price") {
+                // Numbers may be written with an exponent, e.g. `1e-7`, so
+                // amounts are normalized to plain decimals
+                let amount = match price {
+                    Value::Number(amount) => amount.to_string(),
+                    Value::String(amount) => amount.clone(),
+                    _ => continue,
+                };
+                let amount = Money::parse(&amount)
+                    .ok_or_else(|| format!("invalid price: {}", amount))?
+                    .amount
+                    .to_string();
+                *price = json!({ "amount": amount, "currency": crate::model::DEFAULT_CURRENCY });
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// Version 2 to 3: updates gained the list of fields that changed
+fn v2_to_v3(mut value: Value) -> Result<Value, String> {
+    let event = value.as_object_mut().ok_or("event is not an object")?;
+    if let (Some(old), Some(new)) = (event.get("old"), event.get("new")) {
+        let without_metadata = |product: &Value| {
+            let mut product = product.clone();
+            if let Some(product) = product.as_object_mut() {
+                for field in WRITE_METADATA {
+                    product.remove(field);
+                }
+            }
+            product
+        };
+        let changes = Change::diff_values(&without_metadata(old), &without_metadata(new));
+        event.insert(
+            "changes".to_string(),
+            serde_json::to_value(changes).map_err(|err| err.to_string())?,
+        );
+    }
+    Ok(value)
+}
+
+/// Identifier of the schema of an event type at the current version
+///
+/// This is also used as the `dataschema` of CloudEvents.
+pub fn schema_id(event_type: &str) -> String {
+    format!(
+        "urn:rust-products:schemas:product-{}:v{}",
+        event_type.to_lowercase(),
+        SCHEMA_VERSION
+    )
+}
+
+/// JSON Schema of an event type at the current version
+///
+/// Returns `None` if the event type is unknown.
+pub fn json_schema(event_type: &str) -> Option<Value> {
+    let payload = match event_type {
+        "Created" | "Deleted" | "Restored" | "Purged" => vec![("product", product_schema())],
+        "Updated" => vec![
+            ("old", product_schema()),
+            ("new", product_schema()),
+            ("changes", changes_schema()),
+        ],
+        _ => return None,
+    };
+
+    let mut properties = Map::new();
+    properties.insert("type".to_string(), json!({ "const": event_type }));
+    properties.insert(
+        "schema_version".to_string(),
+        json!({ "const": SCHEMA_VERSION }),
+    );
+    properties.insert("event_id".to_string(), json!({ "type": "string" }));
+    let mut required = vec!["type", "schema_version", "event_id"];
+    for (key, schema) in payload {
+        properties.insert(key.to_string(), schema);
+        required.push(key);
+    }
+
+    Some(json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": schema_id(event_type),
+        "title": format!("Product{}", event_type),
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    }))
+}
+
+fn changes_schema() -> Value {
+    json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "op": { "enum": ["add", "remove", "replace"] },
+                "path": { "type": "string" },
+                "value": {},
+            },
+            "required": ["op", "path"],
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn product_schema() -> Value {
+    let string = json!({ "type": "string" });
+    let strings = json!({ "type": "array", "items": { "type": "string" } });
+    let timestamp = json!({ "type": "string", "format": "date-time" });
+    json!({
+        "type": "object",
+        "properties": {
+            "id": string,
+            "name": string,
+            "description": string,
+            "sku": string,
+            "category": string,
+            "tags": strings,
+            "image_urls": strings,
+            "attributes": { "type": "object", "additionalProperties": string },
+            "price": {
+                "type": "object",
+                "properties": {
+                    "amount": { "type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+)?$" },
+                    "currency": { "type": "string", "pattern": "^[A-Z]{3}$" },
+                },
+                "required": ["amount", "currency"],
+                "additionalProperties": false,
+            },
+            "status": { "enum": ["draft", "active", "archived"] },
+            "version": { "type": "integer", "minimum": 0 },
+            "created_at": timestamp,
+            "updated_at": timestamp,
+            "deleted_at": timestamp,
+        },
+        "required": ["id", "name", "tags", "image_urls", "attributes", "price", "status", "version"],
+        "additionalProperties": false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upcast_v1() {
+        // GIVEN an event written before schema versions
+        let value = json!({
+            "type": "Updated",
+            "old": { "id": "1", "name": "old", "price": 10.5 },
+            "new": { "id": "1", "name": "new", "price": 12 },
+        });
+
+        // WHEN upcasting it
+        let value = upcasters().upcast(value).unwrap();
+
+        // THEN it has the current shape
+        assert_eq!(
+            value,
+            json!({
+                "type": "Updated",
+                "schema_version": 5,
+                "event_id": "",
+                "old": {
+                    "id": "1",
+                    "name": "old",
+                    "price": { "amount": "10.5", "currency": "USD" },
+                    "version": 0,
+                },
+                "new": {
+                    "id": "1",
+                    "name": "new",
+                    "price": { "amount": "12", "currency": "USD" },
+                    "version": 0,
+                },
+                "changes": [
+                    { "op": "replace", "path": "/name", "value": "new" },
+                    { "op": "replace", "path": "/price/amount", "value": "12" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_upcast_current() {
+        let value = json!({
+            "type": "Deleted",
+            "schema_version": 5,
+            "event_id": "e1",
+            "product": {
+                "id": "1",
+                "name": "test",
+                "price": { "amount": "1", "currency": "EUR" },
+                "version": 3,
+            },
+        });
+
+        assert_eq!(upcasters().upcast(value.clone()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_upcast_future_version() {
+        let value = json!({ "type": "Created", "schema_version": 6 });
+
+        assert!(upcasters().upcast(value).is_err());
+    }
+
+    #[test]
+    fn test_upcast_without_steps() {
+        // GIVEN a payload from a version that only added fields with defaults
+        let value = json!({
+            "type": "Created",
+            "schema_version": 3,
+            "event_id": "e1",
+            "product": { "id": "1", "name": "test", "price": { "amount": "1", "currency": "EUR" } },
+        });
+
+        // WHEN upcasting it
+        let upcasted = upcasters().upcast(value.clone()).unwrap();
+
+        // THEN only its schema version changes
+        let mut expected = value;
+        expected["schema_version"] = json!(5);
+        assert_eq!(upcasted, expected);
+    }
+
+    #[test]
+    fn test_upcast_v1_exponent() {
+        let value = json!({ "type": "Created", "product": { "id": "1", "price": 1e-7 } });
+
+        let value = upcasters().upcast(value).unwrap();
+
+        assert_eq!(value["product"]["price"]["amount"], "0.0000001");
+    }
+
+    #[test]
+    fn test_upcast_v1_invalid_price() {
+        let value = json!({ "type": "Created", "product": { "id": "1", "price": "ten" } });
+
+        assert!(upcasters().upcast(value).is_err());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = json_schema("Updated").unwrap();
+
+        assert_eq!(
+            schema["$id"],
+            "urn:rust-products:schemas:product-updated:v5"
+        );
+        assert_eq!(
+            schema["required"],
+            json!([
+                "type",
+                "schema_version",
+                "event_id",
+                "old",
+                "new",
+                "changes"
+            ])
+        );
+        assert!(json_schema("Renamed").is_none());
+    }
+}
@@ -0,0 +1,384 @@
+//! # Batch operations for `DynamoDBStore`
+//!
+//! Reads go through `BatchGetItem` and puts through `TransactWriteItems`, in
+//! chunks of the maximum size DynamoDB accepts. Items that DynamoDB did not
+//! process, usually because of throttling, are retried with a backoff.
+//!
+//! Deletes go through `DeleteItem`, a few at a time, as `BatchWriteItem`
+//! cannot tell which items existed.
+
+use super::{ConditionExpression, DynamoDBStore, MAX_PUT_ATTEMPTS};
+use crate::{
+    store::{Condition, StoreBatchDelete, StoreBatchGet, StoreBatchPut, StoreDelete, StorePut},
+    Error, Product, PutOutcome,
+};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    error::{TransactWriteItemsError, TransactWriteItemsErrorKind},
+    model::{AttributeValue, KeysAndAttributes, Put, TransactWriteItem},
+};
+use aws_smithy_http::result::SdkError;
+use futures::future::{join_all, try_join_all};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+/// Maximum number of keys in a single `BatchGetItem` request
+const MAX_GET_BATCH_SIZE: usize = 100;
+
+/// Maximum number of `DeleteItem` requests in flight at once
+const MAX_CONCURRENT_DELETES: usize = 25;
+
+/// Maximum number of items in a single `TransactWriteItems` request
+const MAX_TRANSACT_SIZE: usize = 100;
+
+/// Total number of attempts for the unprocessed items of a batch
+const MAX_BATCH_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled on every attempt
+const BASE_DELAY: Duration = Duration::from_millis(50);
+
+type Item = HashMap<String, AttributeValue>;
+
+fn key(id: &str) -> Item {
+    HashMap::from([("id".to_owned(), AttributeValue::S(id.to_owned()))])
+}
+
+impl<C> DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Read up to `MAX_GET_BATCH_SIZE` items, retrying unprocessed keys
+    async fn batch_get_chunk(&self, ids: &[String], consistent: bool) -> Result<Vec<Item>, Error> {
+        let mut keys = ids.iter().map(|id| key(id)).collect::<Vec<_>>();
+        let mut items = Vec::new();
+        for attempt in 0..MAX_BATCH_ATTEMPTS {
+            if attempt > 0 {
+                warn!("Retrying {} unprocessed key(s)", keys.len());
+                tokio::time::sleep(BASE_DELAY * 2u32.pow(attempt - 1)).await;
+            }
+            let res = self
+                .client
+                .batch_get_item()
+                .request_items(
+                    &self.table_name,
+                    KeysAndAttributes::builder()
+                        .set_keys(Some(keys))
+                        .consistent_read(consistent)
+                        .build(),
+                )
+                .send()
+                .await?;
+
+            if let Some(mut responses) = res.responses {
+                items.extend(responses.remove(&self.table_name).unwrap_or_default());
+            }
+            keys = res
+                .unprocessed_keys
+                .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                .and_then(|unprocessed| unprocessed.keys)
+                .unwrap_or_default();
+            if keys.is_empty() {
+                return Ok(items);
+            }
+        }
+
+        Err(Error::Throttled(format!(
+            "{} key(s) were not processed",
+            keys.len()
+        )))
+    }
+
+    /// Read items by id, in chunks
+    ///
+    /// Items that do not exist are left out. `BatchGetItem` rejects duplicate
+    /// keys, so every item is read once.
+    async fn batch_get(&self, ids: &[String], consistent: bool) -> Result<Vec<Product>, Error> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        let chunks = try_join_all(
+            ids.chunks(MAX_GET_BATCH_SIZE)
+                .map(|ids| self.batch_get_chunk(ids, consistent)),
+        )
+        .await?;
+        chunks
+            .into_iter()
+            .flatten()
+            .map(Product::try_from)
+            .collect()
+    }
+
+    /// Put up to `MAX_TRANSACT_SIZE` items in a transaction, each on its
+    /// condition
+    ///
+    /// A canceled transaction writes nothing. Items that were fine or
+    /// throttled are written again with a backoff, and the others fail at
+    /// once, see `cancellation_error`. Returns the result for each item, by
+    /// id, which is `Ok(false)` if its condition failed.
+    async fn transact_put_chunk(
+        &self,
+        mut writes: Vec<(PutOutcome, Condition)>,
+    ) -> Result<HashMap<String, Result<bool, Error>>, Error> {
+        let mut results = HashMap::new();
+        for attempt in 0..MAX_BATCH_ATTEMPTS {
+            if attempt > 0 {
+                warn!("Retrying {} canceled item(s)", writes.len());
+                tokio::time::sleep(BASE_DELAY * 2u32.pow(attempt - 1)).await;
+            }
+            let items = writes
+                .iter()
+                .map(|(outcome, condition)| {
+                    let expr = ConditionExpression::from(*condition);
+                    TransactWriteItem::builder()
+                        .put(
+                            Put::builder()
+                                .table_name(&self.table_name)
+                                .set_item(Some((&outcome.product).into()))
+                                .set_condition_expression(expr.expression)
+                                .set_expression_attribute_names(expr.names)
+                                .set_expression_attribute_values(expr.values)
+                                .build(),
+                        )
+                        .build()
+                })
+                .collect();
+            let res = self
+                .client
+                .transact_write_items()
+                .set_transact_items(Some(items))
+                .send()
+                .await;
+
+            let mut reasons = match res {
+                Ok(_) => {
+                    results.extend(
+                        writes
+                            .drain(..)
+                            .map(|(outcome, _)| (outcome.product.id, Ok(true))),
+                    );
+                    break;
+                }
+                Err(SdkError::ServiceError {
+                    err:
+                        TransactWriteItemsError {
+                            kind:
+                                TransactWriteItemsErrorKind::TransactionCanceledException(canceled),
+                            ..
+                        },
+                    ..
+                }) => canceled
+                    .cancellation_reasons
+                    .unwrap_or_default()
+                    .into_iter(),
+                Err(err) => return Err(err.into()),
+            };
+            // The reasons are in the same order as the items
+            writes.retain(|(outcome, _)| {
+                match reasons.next().and_then(|reason| reason.code).as_deref() {
+                    None | Some("None" | "ThrottlingError" | "ProvisionedThroughputExceeded") => {
+                        true
+                    }
+                    Some("ConditionalCheckFailed") => {
+                        results.insert(outcome.product.id.clone(), Ok(false));
+                        false
+                    }
+                    Some(code) => {
+                        warn!(
+                            "Item with id '{}' canceled the transaction: {}",
+                            outcome.product.id, code
+                        );
+                        results.insert(outcome.product.id.clone(), Err(cancellation_error(code)));
+                        false
+                    }
+                }
+            });
+            if writes.is_empty() {
+                break;
+            }
+        }
+
+        results.extend(
+            writes
+                .into_iter()
+                .map(|(outcome, _)| (outcome.product.id, Err(unprocessed_error()))),
+        );
+        Ok(results)
+    }
+}
+
+fn unprocessed_error() -> Error {
+    Error::Throttled("Item was not processed".to_string())
+}
+
+/// Error for an item that canceled a transaction for a reason that retrying
+/// would not fix
+fn cancellation_error(code: &str) -> Error {
+    match code {
+        "TransactionConflict" => Error::Conflict("Product was modified concurrently"),
+        "ValidationError" => Error::UnprocessableEntity("Product cannot be stored"),
+        "ItemCollectionSizeLimitExceeded" => {
+            Error::InternalError("Item collection size limit exceeded")
+        }
+        _ => Error::InternalError("Transaction was canceled"),
+    }
+}
+
+#[async_trait]
+impl<C> StoreBatchGet for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Get items
+    ///
+    /// Soft-deleted items are left out, as if they did not exist.
+    #[instrument(skip(self))]
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        info!("Getting {} item(s) from DynamoDB table", ids.len());
+        let mut products = self.batch_get(ids, false).await?;
+        products.retain(|p| !p.is_deleted());
+        Ok(products)
+    }
+}
+
+#[async_trait]
+impl<C> StoreBatchPut for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Create or update items
+    ///
+    /// The current items are read first, to bump their versions and keep
+    /// their creation times. Like `put`, each item is only written if it is
+    /// still at the version that was read, and the items written concurrently
+    /// are read and written again.
+    ///
+    /// In outbox mode, every write must be in a transaction with its event,
+    /// so the products are put one at a time instead.
+    #[instrument(skip(self, products))]
+    async fn put_many(
+        &self,
+        products: &[Product],
+    ) -> Result<Vec<Result<PutOutcome, Error>>, Error> {
+        info!("Putting {} item(s) into DynamoDB table", products.len());
+        if self.outbox_table_name.is_some() {
+            let mut results = Vec::with_capacity(products.len());
+            for product in products {
+                results.push(self.put(product, Condition::Any).await);
+            }
+            return Ok(results);
+        }
+
+        let mut results = HashMap::new();
+        let mut pending = products.iter().collect::<Vec<_>>();
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            let ids = pending.iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+            let mut current = self
+                .batch_get(&ids, true)
+                .await?
+                .into_iter()
+                .map(|p| (p.id.clone(), p))
+                .collect::<HashMap<_, _>>();
+
+            let writes = pending
+                .iter()
+                .map(|product| {
+                    // A tombstone is replaced as if there was no product
+                    let stored = current.remove(&product.id);
+                    let version = stored.as_ref().map(|p| p.version);
+                    let previous = stored.filter(|p| !p.is_deleted());
+                    let product = Product {
+                        version: version.unwrap_or(0) + 1,
+                        created_at: previous
+                            .as_ref()
+                            .and_then(|p| p.created_at)
+                            .or(product.created_at),
+                        ..(*product).clone()
+                    };
+                    let expected = match version {
+                        Some(version) => Condition::Version(version),
+                        None => Condition::NotExists,
+                    };
+                    (PutOutcome { product, previous }, expected)
+                })
+                .collect::<Vec<_>>();
+
+            let chunks = writes
+                .chunks(MAX_TRANSACT_SIZE)
+                .map(<[_]>::to_vec)
+                .collect::<Vec<_>>();
+            let mut written = try_join_all(
+                chunks
+                    .into_iter()
+                    .map(|writes| self.transact_put_chunk(writes)),
+            )
+            .await?
+            .into_iter()
+            .flatten()
+            .collect::<HashMap<_, _>>();
+
+            // The items that changed since we read them are read again
+            let mut changed = HashSet::new();
+            for (outcome, _) in writes {
+                let id = outcome.product.id.clone();
+                match written.remove(&id) {
+                    Some(Ok(true)) => {
+                        results.insert(id, Ok(outcome));
+                    }
+                    Some(Ok(false)) => {
+                        warn!("Item with id '{}' changed concurrently", id);
+                        changed.insert(id);
+                    }
+                    Some(Err(err)) => {
+                        results.insert(id, Err(err));
+                    }
+                    None => {
+                        results.insert(id, Err(Error::InternalError("Missing result")));
+                    }
+                }
+            }
+            pending.retain(|p| changed.contains(&p.id));
+            if pending.is_empty() {
+                break;
+            }
+        }
+        for product in pending {
+            results.insert(
+                product.id.clone(),
+                Err(Error::Conflict("Product was modified concurrently")),
+            );
+        }
+
+        Ok(products
+            .iter()
+            .map(|product| {
+                results
+                    .remove(&product.id)
+                    .unwrap_or(Err(Error::InternalError("Missing result")))
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl<C> StoreBatchDelete for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Delete items
+    ///
+    /// Each item is deleted as with `delete`, which returns the deleted item,
+    /// in chunks of `MAX_CONCURRENT_DELETES` concurrent deletes.
+    #[instrument(skip(self))]
+    async fn delete_many(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<Result<Option<Product>, Error>>, Error> {
+        info!("Deleting {} item(s) from DynamoDB table", ids.len());
+        let mut results = Vec::with_capacity(ids.len());
+        for ids in ids.chunks(MAX_CONCURRENT_DELETES) {
+            results.extend(join_all(ids.iter().map(|id| self.delete(id, Condition::Any))).await);
+        }
+        Ok(results)
+    }
+}
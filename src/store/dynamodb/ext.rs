@@ -10,7 +10,6 @@ use std::collections::HashMap;
 /// to extract those values.
 pub trait AttributeValuesExt {
     fn get_s(&self, key: &str) -> Option<String>;
-    fn get_n(&self, key: &str) -> Option<f64>;
 }
 
 impl AttributeValuesExt for HashMap<String, AttributeValue> {
@@ -29,22 +28,6 @@ impl AttributeValuesExt for HashMap<String, AttributeValue> {
     fn get_s(&self, key: &str) -> Option<String> {
         Some(self.get(key)?.as_s().ok()?.to_owned())
     }
-
-    /// Return a number from an key
-    ///
-    /// E.g. if you run `get_n("price")` on a DynamoDB item structured like this,
-    /// you will retrieve the value `10.0`.
-    ///
-    /// ```json
-    /// {
-    ///  "price": {
-    ///   "N": "10.0"
-    ///   }
-    /// }
-    /// ```
-    fn get_n(&self, key: &str) -> Option<f64> {
-        self.get(key)?.as_n().ok()?.parse::<f64>().ok()
-    }
 }
 
 #[cfg(test)]
@@ -66,20 +49,4 @@ mod tests {
 
         assert_eq!(item.get_s("foo"), None);
     }
-
-    #[test]
-    fn attributevalue_get_n() {
-        let mut item = HashMap::new();
-        item.insert("price".to_owned(), AttributeValue::N("10.0".to_owned()));
-
-        assert_eq!(item.get_n("price"), Some(10.0));
-    }
-
-    #[test]
-    fn attributevalue_get_n_missing() {
-        let mut item = HashMap::new();
-        item.insert("price".to_owned(), AttributeValue::N("10.0".to_owned()));
-
-        assert_eq!(item.get_n("foo"), None);
-    }
 }
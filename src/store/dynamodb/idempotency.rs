@@ -0,0 +1,185 @@
+//! # Idempotency records for `DynamoDBStore`
+//!
+//! Records live in their own table, one item per key. Expired items are not
+//! deleted right away by DynamoDB, so every read and conditional write also
+//! compares `expires_at` with the current time.
+
+use super::{ext::AttributeValuesExt, DynamoDBStore, MAX_PUT_ATTEMPTS};
+use crate::{store::StoreIdempotency, Error, IdempotencyRecord, RecordedResponse};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::model::AttributeValue;
+use aws_smithy_http::result::SdkError;
+use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use tracing::{instrument, warn};
+
+type Item = HashMap<String, AttributeValue>;
+
+/// Convert a record into a DynamoDB item
+///
+/// `expires_at` is stored in seconds since the epoch, as expected by the
+/// time to live of DynamoDB.
+fn to_item(key: &str, record: &IdempotencyRecord) -> Result<Item, Error> {
+    let mut item = HashMap::from([
+        ("key".to_owned(), AttributeValue::S(key.to_owned())),
+        (
+            "fingerprint".to_owned(),
+            AttributeValue::S(record.fingerprint.clone()),
+        ),
+        (
+            "expires_at".to_owned(),
+            AttributeValue::N(record.expires_at.timestamp().to_string()),
+        ),
+    ]);
+    if let Some(response) = &record.response {
+        item.insert(
+            "response".to_owned(),
+            AttributeValue::S(
+                serde_json::to_string(response)
+                    .map_err(|_| Error::InternalError("Failed to serialize response"))?,
+            ),
+        );
+    }
+    Ok(item)
+}
+
+/// Convert a DynamoDB item into a record
+fn from_item(item: &Item) -> Result<IdempotencyRecord, Error> {
+    let fingerprint = item
+        .get_s("fingerprint")
+        .ok_or(Error::InternalError("Missing fingerprint"))?;
+    let expires_at = match item.get("expires_at") {
+        Some(AttributeValue::N(seconds)) => seconds
+            .parse()
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+            .ok_or(Error::InternalError("Invalid expires_at"))?,
+        _ => return Err(Error::InternalError("Missing expires_at")),
+    };
+    let response = match item.get_s("response") {
+        Some(response) => Some(
+            serde_json::from_str::<RecordedResponse>(&response)
+                .map_err(|_| Error::InternalError("Invalid recorded response"))?,
+        ),
+        None => None,
+    };
+    Ok(IdempotencyRecord {
+        fingerprint,
+        response,
+        expires_at,
+    })
+}
+
+#[async_trait]
+impl<C> StoreIdempotency for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Claim a key with a conditional write
+    ///
+    /// If the key is taken, the current record is read back. It may have
+    /// expired or been released in between, in which case we try again.
+    #[instrument(skip(self, record))]
+    async fn claim(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let table_name = match &self.idempotency_table_name {
+            Some(table_name) => table_name,
+            None => return Ok(None),
+        };
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            let now = Utc::now();
+            let res = self
+                .client
+                .put_item()
+                .table_name(table_name)
+                .set_item(Some(to_item(key, record)?))
+                .condition_expression("attribute_not_exists(#key) OR expires_at <= :now")
+                .expression_attribute_names("#key", "key")
+                .expression_attribute_values(":now", AttributeValue::N(now.timestamp().to_string()))
+                .send()
+                .await;
+            match res {
+                Ok(_) => return Ok(None),
+                Err(SdkError::ServiceError { err, .. })
+                    if err.is_conditional_check_failed_exception() => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            let res = self
+                .client
+                .get_item()
+                .table_name(table_name)
+                .key("key", AttributeValue::S(key.to_owned()))
+                .consistent_read(true)
+                .send()
+                .await?;
+            if let Some(item) = res.item {
+                let current = from_item(&item)?;
+                if !current.is_expired(now) {
+                    return Ok(Some(current));
+                }
+            }
+            warn!("Idempotency key '{}' changed concurrently", key);
+        }
+
+        Err(Error::Conflict("Idempotency key was modified concurrently"))
+    }
+
+    /// Store the response of a claimed key
+    ///
+    /// The write is conditional on the key still being in progress with the
+    /// same fingerprint, so that it does not overwrite the record of a request
+    /// that claimed the key after it expired.
+    #[instrument(skip(self, record))]
+    async fn complete(&self, key: &str, record: &IdempotencyRecord) -> Result<(), Error> {
+        let table_name = match &self.idempotency_table_name {
+            Some(table_name) => table_name,
+            None => return Ok(()),
+        };
+        let res = self
+            .client
+            .put_item()
+            .table_name(table_name)
+            .set_item(Some(to_item(key, record)?))
+            .condition_expression("fingerprint = :fingerprint AND attribute_not_exists(#response)")
+            .expression_attribute_names("#response", "response")
+            .expression_attribute_values(
+                ":fingerprint",
+                AttributeValue::S(record.fingerprint.clone()),
+            )
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                warn!(
+                    "Idempotency key '{}' was claimed again before completion",
+                    key
+                );
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Delete the record of a claimed key
+    #[instrument(skip(self))]
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        let table_name = match &self.idempotency_table_name {
+            Some(table_name) => table_name,
+            None => return Ok(()),
+        };
+        self.client
+            .delete_item()
+            .table_name(table_name)
+            .key("key", AttributeValue::S(key.to_owned()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,882 @@
+//! # Serde mapping for DynamoDB items
+//!
+//! Converts any type implementing `Serialize` into a DynamoDB item, and items
+//! back into any type implementing `Deserialize`, so that new fields are
+//! stored without writing the conversion by hand.
+//!
+//! Values are mapped as follows:
+//!
+//! * strings and chars are `S`, booleans are `BOOL`, and bytes are `B`
+//! * integers and floats are `N`
+//! * `None` and unit values are `NULL`
+//! * sequences and tuples are `L`, maps and structs are `M`
+//! * enums are externally tagged, like in JSON: unit variants are `S`, and
+//!   other variants are an `M` with the variant name as the only key
+//!
+//! Sets are serialized as lists, unless the field uses one of the
+//! `string_set`, `number_set` or `binary_set` helpers. When deserializing,
+//! `SS`, `NS` and `BS` are read as sequences.
+//!
+//! Numbers are parsed into the type that is asked for. When the type is not
+//! known in advance, such as in untagged enums, integers are read as integers
+//! and other numbers as strings, so that decimals are not rounded through a
+//! float.
+
+use crate::Error;
+use aws_sdk_dynamodb::model::AttributeValue;
+use aws_smithy_types::Blob;
+use serde::{
+    de::{self, DeserializeOwned, IntoDeserializer, Visitor},
+    ser::{self, Serialize},
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Serialize a value into a DynamoDB item
+///
+/// The value must serialize to a map, such as a struct.
+pub fn to_item<T>(value: &T) -> Result<HashMap<String, AttributeValue>, Error>
+where
+    T: Serialize + ?Sized,
+{
+    match to_attribute_value(value)? {
+        AttributeValue::M(item) => Ok(item),
+        _ => Err(Error::ItemError("item is not a map".to_string())),
+    }
+}
+
+/// Deserialize a value from a DynamoDB item
+pub fn from_item<T>(item: HashMap<String, AttributeValue>) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    from_attribute_value(AttributeValue::M(item))
+}
+
+/// Serialize a value into a DynamoDB attribute value
+pub fn to_attribute_value<T>(value: &T) -> Result<AttributeValue, Error>
+where
+    T: Serialize + ?Sized,
+{
+    value
+        .serialize(Serializer)
+        .map_err(|err| Error::ItemError(err.0))
+}
+
+/// Deserialize a value from a DynamoDB attribute value
+pub fn from_attribute_value<T>(value: AttributeValue) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(Deserializer(value)).map_err(|err| Error::ItemError(err.0))
+}
+
+/// Error raised while mapping a value
+#[derive(Debug)]
+struct ItemError(String);
+
+impl fmt::Display for ItemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ItemError {}
+
+impl ser::Error for ItemError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ItemError(msg.to_string())
+    }
+}
+
+impl de::Error for ItemError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ItemError(msg.to_string())
+    }
+}
+
+/// Names of the newtype structs used to mark sets
+const STRING_SET: &str = "$dynamodb::StringSet";
+const NUMBER_SET: &str = "$dynamodb::NumberSet";
+const BINARY_SET: &str = "$dynamodb::BinarySet";
+
+macro_rules! set_helper {
+    ($module:ident, $marker:ident, $doc:literal) => {
+        #[doc = $doc]
+        ///
+        /// Use with `#[serde(with = "...")]`. DynamoDB does not accept empty
+        /// sets, so empty collections should be skipped with
+        /// `skip_serializing_if`.
+        pub mod $module {
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+            pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                T: Serialize,
+                S: Serializer,
+            {
+                serializer.serialize_newtype_struct(super::$marker, value)
+            }
+
+            pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+            where
+                T: Deserialize<'de>,
+                D: Deserializer<'de>,
+            {
+                T::deserialize(deserializer)
+            }
+        }
+    };
+}
+
+set_helper!(
+    string_set,
+    STRING_SET,
+    "Store a collection of strings as `SS`"
+);
+set_helper!(
+    number_set,
+    NUMBER_SET,
+    "Store a collection of numbers as `NS`"
+);
+set_helper!(
+    binary_set,
+    BINARY_SET,
+    "Store a collection of byte buffers as `BS`"
+);
+
+struct Serializer;
+
+type SerResult = Result<AttributeValue, ItemError>;
+
+impl ser::Serializer for Serializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> SerResult {
+        Ok(AttributeValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_i16(self, v: i16) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_i32(self, v: i32) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_i64(self, v: i64) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_i128(self, v: i128) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_u8(self, v: u8) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_u16(self, v: u16) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_u32(self, v: u32) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_u64(self, v: u64) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_u128(self, v: u128) -> SerResult {
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_f32(self, v: f32) -> SerResult {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> SerResult {
+        if !v.is_finite() {
+            return Err(ser::Error::custom("numbers must be finite"));
+        }
+        Ok(AttributeValue::N(v.to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> SerResult {
+        Ok(AttributeValue::S(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> SerResult {
+        Ok(AttributeValue::S(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> SerResult {
+        Ok(AttributeValue::B(Blob::new(v)))
+    }
+
+    fn serialize_none(self) -> SerResult {
+        Ok(AttributeValue::Null(true))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerResult {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> SerResult {
+        Ok(AttributeValue::Null(true))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> SerResult {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> SerResult {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> SerResult {
+        let set = match name {
+            STRING_SET | NUMBER_SET | BINARY_SET => match value.serialize(self)? {
+                AttributeValue::L(values) => values,
+                _ => return Err(ser::Error::custom("sets must be sequences")),
+            },
+            _ => return value.serialize(self),
+        };
+        if set.is_empty() {
+            return Err(ser::Error::custom("sets cannot be empty"));
+        }
+
+        let invalid = || ser::Error::custom(format!("invalid element in {}", name));
+        Ok(match name {
+            STRING_SET => AttributeValue::Ss(
+                set.into_iter()
+                    .map(|value| match value {
+                        AttributeValue::S(s) => Ok(s),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            NUMBER_SET => AttributeValue::Ns(
+                set.into_iter()
+                    .map(|value| match value {
+                        AttributeValue::N(n) => Ok(n),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => AttributeValue::Bs(
+                set.into_iter()
+                    .map(|value| match value {
+                        AttributeValue::B(b) => Ok(b),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> SerResult {
+        Ok(AttributeValue::M(HashMap::from([(
+            variant.to_owned(),
+            value.serialize(self)?,
+        )])))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, ItemError> {
+        Ok(SerializeVec(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, ItemError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, ItemError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, ItemError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: SerializeVec(Vec::with_capacity(len)),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, ItemError> {
+        Ok(SerializeMap {
+            map: HashMap::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, ItemError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, ItemError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: SerializeMap {
+                map: HashMap::with_capacity(len),
+                key: None,
+            },
+        })
+    }
+}
+
+struct SerializeVec(Vec<AttributeValue>);
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        self.0.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> SerResult {
+        Ok(AttributeValue::L(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> SerResult {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> SerResult {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeMap {
+    map: HashMap<String, AttributeValue>,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ItemError> {
+        // Map keys are attribute names, so they have to be strings
+        self.key = Some(match key.serialize(Serializer)? {
+            AttributeValue::S(key) => key,
+            AttributeValue::N(key) => key,
+            _ => return Err(ser::Error::custom("map keys must be strings")),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ser::Error::custom("map value without a key"))?;
+        self.map.insert(key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> SerResult {
+        Ok(AttributeValue::M(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ItemError> {
+        self.map
+            .insert(key.to_owned(), value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> SerResult {
+        Ok(AttributeValue::M(self.map))
+    }
+}
+
+/// Tuple or struct variant, wrapped in a map keyed by the variant name
+struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> SerializeVariant<S> {
+    fn wrap(variant: &str, value: AttributeValue) -> SerResult {
+        Ok(AttributeValue::M(HashMap::from([(
+            variant.to_owned(),
+            value,
+        )])))
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec> {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> SerResult {
+        Self::wrap(self.variant, ser::SerializeSeq::end(self.inner)?)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ItemError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> SerResult {
+        Self::wrap(self.variant, ser::SerializeStruct::end(self.inner)?)
+    }
+}
+
+struct Deserializer(AttributeValue);
+
+impl Deserializer {
+    fn unexpected(&self) -> de::Unexpected<'_> {
+        match &self.0 {
+            AttributeValue::S(s) => de::Unexpected::Str(s),
+            AttributeValue::N(_) => de::Unexpected::Other("number"),
+            AttributeValue::Bool(b) => de::Unexpected::Bool(*b),
+            AttributeValue::Null(_) => de::Unexpected::Unit,
+            AttributeValue::B(_) | AttributeValue::Bs(_) => de::Unexpected::Other("binary"),
+            AttributeValue::M(_) => de::Unexpected::Map,
+            _ => de::Unexpected::Seq,
+        }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ItemError> for Deserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Parse a number into the type requested by the visitor, falling back to
+/// `deserialize_any` for other attribute types
+macro_rules! deserialize_number {
+    ($($method:ident => $visit:ident: $ty:ty),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ItemError> {
+                match &self.0 {
+                    AttributeValue::N(n) => match n.parse::<$ty>() {
+                        Ok(n) => visitor.$visit(n),
+                        Err(_) => Err(de::Error::invalid_value(
+                            de::Unexpected::Str(n),
+                            &stringify!($ty),
+                        )),
+                    },
+                    _ => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = ItemError;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ItemError> {
+        match self.0 {
+            AttributeValue::S(s) => visitor.visit_string(s),
+            AttributeValue::N(n) => {
+                if let Ok(n) = n.parse::<u64>() {
+                    visitor.visit_u64(n)
+                } else if let Ok(n) = n.parse::<i64>() {
+                    visitor.visit_i64(n)
+                } else {
+                    visitor.visit_string(n)
+                }
+            }
+            AttributeValue::Bool(b) => visitor.visit_bool(b),
+            AttributeValue::Null(_) => visitor.visit_unit(),
+            AttributeValue::B(b) => visitor.visit_byte_buf(b.into_inner()),
+            AttributeValue::L(l) => visit_seq(l.into_iter(), visitor),
+            AttributeValue::Ss(ss) => visit_seq(ss.into_iter().map(AttributeValue::S), visitor),
+            AttributeValue::Ns(ns) => visit_seq(ns.into_iter().map(AttributeValue::N), visitor),
+            AttributeValue::Bs(bs) => visit_seq(bs.into_iter().map(AttributeValue::B), visitor),
+            AttributeValue::M(m) => {
+                let mut map = de::value::MapDeserializer::new(
+                    m.into_iter().map(|(key, value)| (key, Deserializer(value))),
+                );
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            _ => Err(de::Error::custom("unsupported attribute type")),
+        }
+    }
+
+    deserialize_number! {
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_i128 => visit_i128: i128,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_u128 => visit_u128: u128,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ItemError> {
+        match self.0 {
+            AttributeValue::Null(_) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ItemError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ItemError> {
+        match self.0 {
+            AttributeValue::S(variant) => visitor.visit_enum(variant.into_deserializer()),
+            AttributeValue::M(m) if m.len() == 1 => {
+                let (variant, value) = m.into_iter().next().unwrap();
+                visitor.visit_enum(Enum { variant, value })
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool char str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+fn visit_seq<'de, I, V>(values: I, visitor: V) -> Result<V::Value, ItemError>
+where
+    I: Iterator<Item = AttributeValue>,
+    V: Visitor<'de>,
+{
+    let mut seq = de::value::SeqDeserializer::new(values.map(Deserializer));
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+}
+
+/// Enum variant with data, read from a map with a single key
+struct Enum {
+    variant: String,
+    value: AttributeValue,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = ItemError;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer), ItemError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, Deserializer(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = ItemError;
+
+    fn unit_variant(self) -> Result<(), ItemError> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, ItemError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ItemError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ItemError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Record {
+        id: String,
+        count: u64,
+        delta: i32,
+        ratio: f64,
+        active: bool,
+        note: Option<String>,
+        missing: Option<String>,
+        #[serde(with = "serde_bytes_like")]
+        data: Vec<u8>,
+        list: Vec<u32>,
+        nested: BTreeMap<String, Vec<String>>,
+        #[serde(with = "string_set")]
+        tags: BTreeSet<String>,
+        #[serde(with = "number_set")]
+        sizes: Vec<u16>,
+        shapes: Vec<Shape>,
+    }
+
+    /// Serialize a byte vector as bytes rather than a list of numbers
+    mod serde_bytes_like {
+        use serde::{Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(value)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+
+        struct BytesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+        }
+    }
+
+    fn record() -> Record {
+        Record {
+            id: "r1".to_string(),
+            count: u64::MAX,
+            delta: -3,
+            ratio: 0.5,
+            active: true,
+            note: Some("note".to_string()),
+            missing: None,
+            data: vec![1, 2, 3],
+            list: vec![1, 2],
+            nested: BTreeMap::from([("a".to_string(), vec!["b".to_string()])]),
+            tags: BTreeSet::from(["x".to_string(), "y".to_string()]),
+            sizes: vec![10, 20],
+            shapes: vec![Shape::Point, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+        }
+    }
+
+    #[test]
+    fn test_to_item() {
+        let item = to_item(&record()).unwrap();
+
+        assert_eq!(item["id"], AttributeValue::S("r1".to_string()));
+        assert_eq!(item["count"], AttributeValue::N(u64::MAX.to_string()));
+        assert_eq!(item["delta"], AttributeValue::N("-3".to_string()));
+        assert_eq!(item["active"], AttributeValue::Bool(true));
+        assert_eq!(item["missing"], AttributeValue::Null(true));
+        assert_eq!(item["data"], AttributeValue::B(Blob::new(vec![1, 2, 3])));
+        assert_eq!(
+            item["tags"],
+            AttributeValue::Ss(vec!["x".to_string(), "y".to_string()])
+        );
+        assert_eq!(
+            item["sizes"],
+            AttributeValue::Ns(vec!["10".to_string(), "20".to_string()])
+        );
+        assert_eq!(
+            item["nested"],
+            AttributeValue::M(HashMap::from([(
+                "a".to_string(),
+                AttributeValue::L(vec![AttributeValue::S("b".to_string())])
+            )]))
+        );
+        assert_eq!(
+            item["shapes"].as_l().unwrap()[0],
+            AttributeValue::S("Point".to_string())
+        );
+        assert_eq!(
+            item["shapes"].as_l().unwrap()[1],
+            AttributeValue::M(HashMap::from([(
+                "Circle".to_string(),
+                AttributeValue::N("1.5".to_string())
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let item = to_item(&record()).unwrap();
+
+        assert_eq!(from_item::<Record>(item).unwrap(), record());
+    }
+
+    #[test]
+    fn test_from_item_missing_field() {
+        let mut item = to_item(&record()).unwrap();
+        item.remove("id");
+
+        assert!(matches!(
+            from_item::<Record>(item),
+            Err(Error::ItemError(_))
+        ));
+    }
+
+    #[test]
+    fn test_to_item_not_a_map() {
+        assert!(to_item(&vec![1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_empty_set() {
+        let mut record = record();
+        record.tags.clear();
+
+        assert!(to_item(&record).is_err());
+    }
+
+    #[test]
+    fn test_number_types() {
+        // Numbers are parsed into the requested type
+        assert_eq!(
+            from_attribute_value::<i8>(AttributeValue::N("-8".to_string())).unwrap(),
+            -8
+        );
+        assert_eq!(
+            from_attribute_value::<f32>(AttributeValue::N("1e3".to_string())).unwrap(),
+            1000.0
+        );
+        assert!(from_attribute_value::<u8>(AttributeValue::N("256".to_string())).is_err());
+        // Unknown types get decimals as strings
+        assert_eq!(
+            from_attribute_value::<serde_json::Value>(AttributeValue::N("0.1".to_string()))
+                .unwrap(),
+            serde_json::json!("0.1")
+        );
+    }
+}
@@ -2,14 +2,30 @@
 //!
 //! Store implementation using the AWS SDK for DynamoDB.
 
-use super::{Store, StoreDelete, StoreGet, StoreGetAll, StorePut};
-use crate::{Error, Product, ProductRange};
+use super::{
+    decode_query_key, encode_query_key, new_event_id, Condition, PatchCheck, SortKey, Store,
+    StoreDelete, StoreExport, StoreGet, StoreGetAll, StoreOutbox, StorePatch, StorePut, StoreQuery,
+    StoreTrash,
+};
+use crate::{model::DEFAULT_CURRENCY, Error, Event, MergePatch, Product, ProductRange, PutOutcome};
 use async_trait::async_trait;
-use aws_sdk_dynamodb::{model::AttributeValue, Client};
-use std::collections::HashMap;
-use tracing::{info, instrument};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, Delete, Put, ReturnValue, TransactWriteItem, Update},
+    Client,
+};
+use aws_smithy_http::result::SdkError;
+use chrono::{Duration, Utc};
+use futures::{
+    future::join_all,
+    stream::{self, BoxStream, StreamExt, TryStreamExt},
+};
+use std::collections::{BTreeSet, HashMap};
+use tracing::{info, instrument, warn};
 
+mod batch;
 mod ext;
+mod idempotency;
+pub mod item;
 use ext::AttributeValuesExt;
 
 /// DynamoDB store implementation.
@@ -20,14 +36,394 @@ use ext::AttributeValuesExt;
 pub struct DynamoDBStore<C> {
     client: Client<C>,
     table_name: String,
+    /// Table receiving events, if the store is in outbox mode
+    outbox_table_name: Option<String>,
+    /// Table recording idempotency keys, if any
+    idempotency_table_name: Option<String>,
+    /// How long soft-deleted items are kept, if the store is in soft-delete
+    /// mode
+    soft_delete: Option<Duration>,
 }
 
+/// Partition key of all the events in the outbox table
+///
+/// Keeping all events in a single partition, sorted by id, lets the relay
+/// query them oldest first.
+const OUTBOX_PARTITION: &str = "outbox";
+
+/// Global secondary index with `category` as partition key and `name` as
+/// sort key
+const CATEGORY_NAME_INDEX: &str = "category-name";
+
+/// Global secondary index with `category` as partition key and `price` as
+/// sort key
+const CATEGORY_PRICE_INDEX: &str = "category-price";
+
+/// Attribute holding the time, in seconds since the epoch, after which a
+/// soft-deleted item is purged
+///
+/// The table should have time to live enabled on this attribute.
+const PURGE_AT: &str = "purge_at";
+
+/// Filter hiding soft-deleted items from scans and queries
+const LIVE_FILTER: &str = "attribute_not_exists(deleted_at)";
+
+/// Filter keeping only soft-deleted items
+const TRASH_FILTER: &str = "attribute_exists(deleted_at)";
+
+/// Maximum number of attempts for a put that has to read the current version
+///
+/// If the product is modified between the read and the conditional write, the
+/// put is retried with the new version.
+const MAX_PUT_ATTEMPTS: usize = 3;
+
 impl<C> DynamoDBStore<C>
 where
     C: aws_smithy_client::bounds::SmithyConnector,
 {
     pub fn new(client: Client<C>, table_name: String) -> DynamoDBStore<C> {
-        DynamoDBStore { client, table_name }
+        DynamoDBStore {
+            client,
+            table_name,
+            outbox_table_name: None,
+            idempotency_table_name: None,
+            soft_delete: None,
+        }
+    }
+
+    /// Switch the store to outbox mode
+    ///
+    /// Every put and delete also writes an event into `outbox_table_name`, in
+    /// the same transaction. The table must have a `pk` partition key and an
+    /// `id` sort key, both strings.
+    pub fn with_outbox(mut self, outbox_table_name: String) -> Self {
+        self.outbox_table_name = Some(outbox_table_name);
+        self
+    }
+
+    /// Record idempotency keys in `idempotency_table_name`
+    ///
+    /// The table must have a `key` partition key, as a string, and should
+    /// have time to live enabled on the `expires_at` attribute so that
+    /// expired records are eventually removed.
+    pub fn with_idempotency(mut self, idempotency_table_name: String) -> Self {
+        self.idempotency_table_name = Some(idempotency_table_name);
+        self
+    }
+
+    /// Switch the store to soft-delete mode
+    ///
+    /// Deletes set `deleted_at` on the item instead of removing it, along
+    /// with a `purge_at` attribute `retention` later, on which the table
+    /// should have time to live enabled.
+    pub fn with_soft_delete(mut self, retention: Duration) -> Self {
+        self.soft_delete = Some(retention);
+        self
+    }
+
+    /// Filter for scans and queries of live items, in soft-delete mode
+    fn live_filter(&self) -> Option<String> {
+        self.soft_delete.map(|_| LIVE_FILTER.to_owned())
+    }
+
+    /// Get the current state of an item with a strongly consistent read
+    ///
+    /// This returns the item as stored, including tombstones.
+    async fn get_consistent(&self, id: &str) -> Result<Option<Product>, Error> {
+        match self.get_item_consistent(id).await? {
+            Some(item) => Ok(Some(item.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the raw attributes of an item with a strongly consistent read
+    async fn get_item_consistent(
+        &self,
+        id: &str,
+    ) -> Result<Option<HashMap<String, AttributeValue>>, Error> {
+        let res = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_owned()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        Ok(res.item)
+    }
+
+    /// Turn an item into another, on the condition that it is still at
+    /// `version`
+    ///
+    /// In outbox mode, the update is written in a transaction with `event`.
+    /// Returns `Ok(false)` if the condition failed.
+    async fn update_with_event(
+        &self,
+        id: &str,
+        old: &HashMap<String, AttributeValue>,
+        new: &HashMap<String, AttributeValue>,
+        version: u64,
+        event: impl FnOnce() -> Event,
+    ) -> Result<bool, Error> {
+        let update = UpdateExpression::diff(old, new);
+        let expr = ConditionExpression::from(Condition::Version(version));
+        let mut names = update.names;
+        names.extend(expr.names.unwrap_or_default());
+        let mut values = update.values;
+        values.extend(expr.values.unwrap_or_default());
+
+        match &self.outbox_table_name {
+            Some(outbox_table_name) => {
+                let change = TransactWriteItem::builder()
+                    .update(
+                        Update::builder()
+                            .table_name(&self.table_name)
+                            .key("id", AttributeValue::S(id.to_owned()))
+                            .update_expression(update.expression)
+                            .set_condition_expression(expr.expression)
+                            .set_expression_attribute_names(Some(names))
+                            .set_expression_attribute_values(Some(values))
+                            .build(),
+                    )
+                    .build();
+                self.transact_with_event(outbox_table_name, change, &event())
+                    .await
+            }
+            None => {
+                let res = self
+                    .client
+                    .update_item()
+                    .table_name(&self.table_name)
+                    .key("id", AttributeValue::S(id.to_owned()))
+                    .update_expression(update.expression)
+                    .set_condition_expression(expr.expression)
+                    .set_expression_attribute_names(Some(names))
+                    .set_expression_attribute_values(Some(values))
+                    .send()
+                    .await;
+                match res {
+                    Ok(_) => Ok(true),
+                    Err(SdkError::ServiceError { err, .. })
+                        if err.is_conditional_check_failed_exception() =>
+                    {
+                        Ok(false)
+                    }
+                    Err(err) => Err(err.into()),
+                }
+            }
+        }
+    }
+
+    /// Write an event along with a change to a product
+    ///
+    /// Either both writes happen or none does. A failed condition on the
+    /// product cancels the transaction, which is reported as `Ok(false)`.
+    async fn transact_with_event(
+        &self,
+        outbox_table_name: &str,
+        change: TransactWriteItem,
+        event: &Event,
+    ) -> Result<bool, Error> {
+        let mut item = HashMap::new();
+        item.insert(
+            "pk".to_owned(),
+            AttributeValue::S(OUTBOX_PARTITION.to_owned()),
+        );
+        item.insert(
+            "id".to_owned(),
+            AttributeValue::S(event.event_id().to_owned()),
+        );
+        item.insert(
+            "event".to_owned(),
+            AttributeValue::S(
+                serde_json::to_string(event)
+                    .map_err(|_| Error::InternalError("Failed to serialize event"))?,
+            ),
+        );
+        let event = TransactWriteItem::builder()
+            .put(
+                Put::builder()
+                    .table_name(outbox_table_name)
+                    .set_item(Some(item))
+                    .build(),
+            )
+            .build();
+
+        let res = self
+            .client
+            .transact_write_items()
+            .transact_items(change)
+            .transact_items(event)
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError { err, .. }) if err.is_transaction_canceled_exception() => {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Put an item and its event in a single transaction
+    ///
+    /// The event contains the product being replaced, so we always read it
+    /// first and make the write conditional on it not having changed.
+    async fn put_with_outbox(
+        &self,
+        product: &Product,
+        condition: Condition,
+        outbox_table_name: &str,
+    ) -> Result<PutOutcome, Error> {
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            // A tombstone is replaced as if there was no product
+            let stored = self.get_consistent(&product.id).await?;
+            let stored_version = stored.as_ref().map(|p| p.version);
+            let previous = stored.filter(|p| !p.is_deleted());
+            if !condition.matches(previous.as_ref().map(|p| p.version)) {
+                return Err(Error::PreconditionFailed("Product version does not match"));
+            }
+
+            let product = Product {
+                version: stored_version.unwrap_or(0) + 1,
+                created_at: previous
+                    .as_ref()
+                    .and_then(|p| p.created_at)
+                    .or(product.created_at),
+                ..product.clone()
+            };
+            let event = match &previous {
+                Some(old) => Event::updated(new_event_id(), old.clone(), product.clone()),
+                None => Event::Created {
+                    event_id: new_event_id(),
+                    product: product.clone(),
+                },
+            };
+            let expr = ConditionExpression::from(match stored_version {
+                Some(version) => Condition::Version(version),
+                None => Condition::NotExists,
+            });
+            let put = Put::builder()
+                .table_name(&self.table_name)
+                .set_item(Some((&product).into()))
+                .set_condition_expression(expr.expression)
+                .set_expression_attribute_names(expr.names)
+                .set_expression_attribute_values(expr.values)
+                .build();
+
+            let change = TransactWriteItem::builder().put(put).build();
+            if self
+                .transact_with_event(outbox_table_name, change, &event)
+                .await?
+            {
+                return Ok(PutOutcome { product, previous });
+            }
+            warn!("Item with id '{}' changed concurrently", product.id);
+        }
+
+        Err(Error::Conflict("Product was modified concurrently"))
+    }
+
+    /// Delete an item and write its event in a single transaction
+    async fn delete_with_outbox(
+        &self,
+        id: &str,
+        condition: Condition,
+        outbox_table_name: &str,
+    ) -> Result<Option<Product>, Error> {
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            let current = self.get_consistent(id).await?;
+            if !condition.matches(current.as_ref().map(|p| p.version)) {
+                return Err(Error::PreconditionFailed("Product version does not match"));
+            }
+            // Nothing to delete, so nothing happened
+            let product = match current {
+                Some(product) => product,
+                None => return Ok(None),
+            };
+
+            let expr = ConditionExpression::from(Condition::Version(product.version));
+            let delete = Delete::builder()
+                .table_name(&self.table_name)
+                .key("id", AttributeValue::S(id.to_owned()))
+                .set_condition_expression(expr.expression)
+                .set_expression_attribute_names(expr.names)
+                .set_expression_attribute_values(expr.values)
+                .build();
+            let event = Event::Deleted {
+                event_id: new_event_id(),
+                product: product.clone(),
+            };
+
+            let change = TransactWriteItem::builder().delete(delete).build();
+            if self
+                .transact_with_event(outbox_table_name, change, &event)
+                .await?
+            {
+                return Ok(Some(product));
+            }
+            warn!("Item with id '{}' changed concurrently", id);
+        }
+
+        Err(Error::Conflict("Product was modified concurrently"))
+    }
+}
+
+impl<C> DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Turn an item into a tombstone, and write its event in outbox mode
+    async fn soft_delete(
+        &self,
+        id: &str,
+        condition: Condition,
+        retention: Duration,
+    ) -> Result<Option<Product>, Error> {
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            let current = self.get_consistent(id).await?.filter(|p| !p.is_deleted());
+            if !condition.matches(current.as_ref().map(|p| p.version)) {
+                return Err(Error::PreconditionFailed("Product version does not match"));
+            }
+            // Nothing to delete, so nothing happened
+            let previous = match current {
+                Some(product) => product,
+                None => return Ok(None),
+            };
+
+            let now = Utc::now();
+            let product = Product {
+                version: previous.version + 1,
+                deleted_at: Some(now),
+                ..previous.clone()
+            };
+            let mut item = HashMap::from(&product);
+            item.insert(
+                PURGE_AT.to_owned(),
+                AttributeValue::N((now + retention).timestamp().to_string()),
+            );
+            let event = || Event::Deleted {
+                event_id: new_event_id(),
+                product: product.clone(),
+            };
+            if self
+                .update_with_event(id, &(&previous).into(), &item, previous.version, event)
+                .await?
+            {
+                return Ok(Some(product));
+            }
+
+            // The item changed since we read it: only retry if the caller did
+            // not pin the version.
+            if let Condition::Version(_) = condition {
+                return Err(Error::PreconditionFailed("Product version does not match"));
+            }
+            warn!("Item with id '{}' changed concurrently", id);
+        }
+
+        Err(Error::Conflict("Product was modified concurrently"))
     }
 }
 
@@ -40,10 +436,33 @@ where
 {
     /// Get all items
     #[instrument(skip(self))]
-    async fn all(&self, next: Option<&str>) -> Result<ProductRange, Error> {
-        // Scan DynamoDB table
+    async fn all(&self, next: Option<&str>, limit: usize) -> Result<ProductRange, Error> {
         info!("Scanning DynamoDB table");
-        let mut req = self.client.scan().table_name(&self.table_name).limit(20);
+        self.scan_page(next, limit, self.live_filter()).await
+    }
+}
+
+impl<C> DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Scan a page of items matching `filter`
+    ///
+    /// DynamoDB applies the filter after reading `limit` items, so a page can
+    /// have fewer products than `limit` even if there are more to fetch.
+    async fn scan_page(
+        &self,
+        next: Option<&str>,
+        limit: usize,
+        filter: Option<String>,
+    ) -> Result<ProductRange, Error> {
+        let limit = i32::try_from(limit).map_err(|_| Error::ClientError("Limit is too large"))?;
+        let mut req = self
+            .client
+            .scan()
+            .table_name(&self.table_name)
+            .limit(limit)
+            .set_filter_expression(filter);
         req = if let Some(next) = next {
             req.exclusive_start_key("id", AttributeValue::S(next.to_owned()))
         } else {
@@ -64,12 +483,68 @@ where
     }
 }
 
+impl<C> DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Scan one segment of the table, page by page
+    fn scan_segment(&self, segment: u32, total: u32) -> BoxStream<'_, Result<Product, Error>> {
+        // The state is the start key of the next page, or `None` once the
+        // last page was read.
+        stream::try_unfold(Some(None), move |start| async move {
+            let start = match start {
+                Some(start) => start,
+                None => return Ok(None),
+            };
+            let res = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .segment(segment as i32)
+                .total_segments(total as i32)
+                .set_filter_expression(self.live_filter())
+                .set_exclusive_start_key(start)
+                .send()
+                .await?;
+
+            let products = res
+                .items
+                .unwrap_or_default()
+                .into_iter()
+                .map(Product::try_from)
+                .collect::<Vec<_>>();
+            let next = res.last_evaluated_key.map(Some);
+            Ok::<_, Error>(Some((stream::iter(products), next)))
+        })
+        .try_flatten()
+        .boxed()
+    }
+}
+
+impl<C> StoreExport for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Scan all items with a parallel scan
+    ///
+    /// Every segment is scanned concurrently, and products are yielded as soon
+    /// as their page is read.
+    #[instrument(skip(self))]
+    fn export(&self, segments: u32) -> BoxStream<'_, Result<Product, Error>> {
+        info!("Scanning DynamoDB table in {} segment(s)", segments);
+        stream::select_all((0..segments).map(|segment| self.scan_segment(segment, segments)))
+            .boxed()
+    }
+}
+
 #[async_trait]
 impl<C> StoreGet for DynamoDBStore<C>
 where
     C: aws_smithy_client::bounds::SmithyConnector,
 {
     /// Get item
+    ///
+    /// Soft-deleted items are not returned.
     #[instrument(skip(self))]
     async fn get(&self, id: &str) -> Result<Option<Product>, Error> {
         info!("Getting item with id '{}' from DynamoDB table", id);
@@ -82,12 +557,85 @@ where
             .await?;
 
         Ok(match res.item {
-            Some(item) => Some(item.try_into()?),
+            Some(item) => Some(Product::try_from(item)?).filter(|p| !p.is_deleted()),
             None => None,
         })
     }
 }
 
+#[async_trait]
+impl<C> StoreQuery for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Query the index of the sort key
+    ///
+    /// Products without a category are not in the indexes, so they are never
+    /// returned.
+    #[instrument(skip(self))]
+    async fn by_category(
+        &self,
+        category: &str,
+        sort: SortKey,
+        next: Option<&str>,
+        limit: usize,
+    ) -> Result<ProductRange, Error> {
+        info!("Querying products in category '{}'", category);
+        let (index_name, sort_attribute) = match sort {
+            SortKey::Name => (CATEGORY_NAME_INDEX, "name"),
+            SortKey::Price => (CATEGORY_PRICE_INDEX, "price"),
+        };
+        let limit = i32::try_from(limit).map_err(|_| Error::ClientError("Limit is too large"))?;
+        let mut req = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .index_name(index_name)
+            .key_condition_expression("category = :category")
+            .expression_attribute_values(":category", AttributeValue::S(category.to_owned()))
+            .set_filter_expression(self.live_filter())
+            .limit(limit);
+
+        // The start key of an index query holds both the keys of the index
+        // and the key of the table.
+        if let Some(next) = next {
+            let (value, id) = decode_query_key(next)?;
+            let value = match sort {
+                SortKey::Name => AttributeValue::S(value),
+                SortKey::Price => AttributeValue::N(value),
+            };
+            req = req
+                .exclusive_start_key("category", AttributeValue::S(category.to_owned()))
+                .exclusive_start_key(sort_attribute, value)
+                .exclusive_start_key("id", AttributeValue::S(id));
+        }
+        let res = req.send().await?;
+
+        // Build response
+        let products = match res.items {
+            Some(items) => items
+                .into_iter()
+                .map(|v| v.try_into())
+                .collect::<Result<Vec<Product>, Error>>()?,
+            None => Vec::default(),
+        };
+        let next = match res.last_evaluated_key {
+            Some(key) => {
+                let value = match key.get(sort_attribute) {
+                    Some(AttributeValue::S(value)) | Some(AttributeValue::N(value)) => value,
+                    _ => return Err(Error::InternalError("Missing sort key in last key")),
+                };
+                let id = key
+                    .get_s("id")
+                    .ok_or(Error::InternalError("Missing id in last key"))?;
+                Some(encode_query_key(value, &id))
+            }
+            None => None,
+        };
+        Ok(ProductRange { products, next })
+    }
+}
+
 #[async_trait]
 impl<C> StorePut for DynamoDBStore<C>
 where
@@ -95,16 +643,148 @@ where
 {
     /// Create or update an item
     #[instrument(skip(self))]
-    async fn put(&self, product: &Product) -> Result<(), Error> {
+    async fn put(&self, product: &Product, condition: Condition) -> Result<PutOutcome, Error> {
         info!("Putting item with id '{}' into DynamoDB table", product.id);
-        self.client
-            .put_item()
-            .table_name(&self.table_name)
-            .set_item(Some(product.into()))
-            .send()
-            .await?;
+        if let Some(outbox_table_name) = &self.outbox_table_name {
+            return self
+                .put_with_outbox(product, condition, outbox_table_name)
+                .await;
+        }
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            // Retrieve the item we are replacing, to know its version and
+            // when it was created
+            //
+            // If the caller told us that the item should not exist, there is
+            // no need to read it first, unless it could be a tombstone, which
+            // is replaced as if there was no product.
+            let stored = match condition {
+                Condition::NotExists if self.soft_delete.is_none() => None,
+                _ => {
+                    let stored = self.get_consistent(&product.id).await?;
+                    let live = stored.as_ref().filter(|p| !p.is_deleted());
+                    if !condition.matches(live.map(|p| p.version)) {
+                        return Err(Error::PreconditionFailed("Product version does not match"));
+                    }
+                    stored
+                }
+            };
+            let current = stored.as_ref().map(|p| p.version);
 
-        Ok(())
+            // Write the new version, on the condition that the item is still
+            // at the version we read, and retrieve the item it replaced.
+            let product = Product {
+                version: current.unwrap_or(0) + 1,
+                created_at: stored
+                    .as_ref()
+                    .filter(|p| !p.is_deleted())
+                    .and_then(|p| p.created_at)
+                    .or(product.created_at),
+                ..product.clone()
+            };
+            let expected = match current {
+                Some(version) => Condition::Version(version),
+                None => Condition::NotExists,
+            };
+            let expr = ConditionExpression::from(expected);
+            let res = self
+                .client
+                .put_item()
+                .table_name(&self.table_name)
+                .set_item(Some((&product).into()))
+                .set_condition_expression(expr.expression)
+                .set_expression_attribute_names(expr.names)
+                .set_expression_attribute_values(expr.values)
+                .return_values(ReturnValue::AllOld)
+                .send()
+                .await;
+
+            match res {
+                Ok(res) => {
+                    let previous = match res.attributes {
+                        Some(item) => Some(Product::try_from(item)?),
+                        None => None,
+                    };
+                    let previous = previous.filter(|p| !p.is_deleted());
+                    return Ok(PutOutcome { product, previous });
+                }
+                // The item changed since we read it: only retry if the caller
+                // did not pin the version.
+                Err(SdkError::ServiceError { err, .. })
+                    if err.is_conditional_check_failed_exception() =>
+                {
+                    if let Condition::Version(_) | Condition::NotExists = condition {
+                        return Err(Error::PreconditionFailed("Product version does not match"));
+                    }
+                    warn!("Item with id '{}' changed concurrently", product.id);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Err(Error::Conflict("Product was modified concurrently"))
+    }
+}
+
+#[async_trait]
+impl<C> StorePatch for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Partially update an item
+    ///
+    /// The patch is applied to a consistent read of the item, and only the
+    /// attributes that changed are written, on the condition that the item is
+    /// still at the version we read.
+    #[instrument(skip(self, check))]
+    async fn patch(
+        &self,
+        id: &str,
+        patch: &MergePatch,
+        check: PatchCheck<'_>,
+        condition: Condition,
+    ) -> Result<PutOutcome, Error> {
+        info!("Patching item with id '{}' in DynamoDB table", id);
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            let previous = self
+                .get_consistent(id)
+                .await?
+                .filter(|p| !p.is_deleted())
+                .ok_or(Error::NotFound("Product not found"))?;
+            if !condition.matches(Some(previous.version)) {
+                return Err(Error::PreconditionFailed("Product version does not match"));
+            }
+
+            let product = Product {
+                version: previous.version + 1,
+                ..patch.apply(&previous)?
+            };
+            check(&product)?;
+
+            let updated = self
+                .update_with_event(
+                    id,
+                    &(&previous).into(),
+                    &(&product).into(),
+                    previous.version,
+                    || Event::updated(new_event_id(), previous.clone(), product.clone()),
+                )
+                .await?;
+            if updated {
+                return Ok(PutOutcome {
+                    product,
+                    previous: Some(previous),
+                });
+            }
+
+            // The item changed since we read it: only retry if the caller did
+            // not pin the version.
+            if let Condition::Version(_) = condition {
+                return Err(Error::PreconditionFailed("Product version does not match"));
+            }
+            warn!("Item with id '{}' changed concurrently", id);
+        }
+
+        Err(Error::Conflict("Product was modified concurrently"))
     }
 }
 
@@ -114,62 +794,326 @@ where
     C: aws_smithy_client::bounds::SmithyConnector,
 {
     /// Delete item
+    ///
+    /// The deleted item is returned by DynamoDB along with the delete, so
+    /// there is no need to read it first.
     #[instrument(skip(self))]
-    async fn delete(&self, id: &str) -> Result<(), Error> {
+    async fn delete(&self, id: &str, condition: Condition) -> Result<Option<Product>, Error> {
         info!("Deleting item with id '{}' from DynamoDB table", id);
-        self.client
+        if let Some(retention) = self.soft_delete {
+            return self.soft_delete(id, condition, retention).await;
+        }
+        if let Some(outbox_table_name) = &self.outbox_table_name {
+            return self
+                .delete_with_outbox(id, condition, outbox_table_name)
+                .await;
+        }
+        let expr = ConditionExpression::from(condition);
+        let res = self
+            .client
             .delete_item()
             .table_name(&self.table_name)
             .key("id", AttributeValue::S(id.to_owned()))
+            .set_condition_expression(expr.expression)
+            .set_expression_attribute_names(expr.names)
+            .set_expression_attribute_values(expr.values)
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await;
+
+        match res {
+            Ok(res) => match res.attributes {
+                Some(item) => Ok(Some(item.try_into()?)),
+                None => Ok(None),
+            },
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Err(Error::PreconditionFailed("Product version does not match"))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl<C> StoreTrash for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Scan soft-deleted items
+    ///
+    /// Items past their `purge_at` time are listed until DynamoDB removes
+    /// them, which can take a few days.
+    #[instrument(skip(self))]
+    async fn trash(&self, next: Option<&str>, limit: usize) -> Result<ProductRange, Error> {
+        info!("Scanning DynamoDB table for soft-deleted items");
+        self.scan_page(next, limit, Some(TRASH_FILTER.to_owned()))
+            .await
+    }
+
+    /// Remove the tombstone of an item
+    ///
+    /// The update is based on the item as stored, so that `purge_at` is
+    /// removed along with `deleted_at`.
+    #[instrument(skip(self))]
+    async fn restore(&self, id: &str) -> Result<Product, Error> {
+        info!("Restoring item with id '{}' in DynamoDB table", id);
+        for _ in 0..MAX_PUT_ATTEMPTS {
+            let item = self
+                .get_item_consistent(id)
+                .await?
+                .ok_or(Error::NotFound("Product not found in trash"))?;
+            let deleted = Product::try_from(item.clone())?;
+            if !deleted.is_deleted() {
+                return Err(Error::NotFound("Product not found in trash"));
+            }
+
+            let product = Product {
+                version: deleted.version + 1,
+                updated_at: Some(Utc::now()),
+                deleted_at: None,
+                ..deleted.clone()
+            };
+            let event = || Event::Restored {
+                event_id: new_event_id(),
+                product: product.clone(),
+            };
+            if self
+                .update_with_event(id, &item, &(&product).into(), deleted.version, event)
+                .await?
+            {
+                return Ok(product);
+            }
+            warn!("Item with id '{}' changed concurrently", id);
+        }
+
+        Err(Error::Conflict("Product was modified concurrently"))
+    }
+}
+
+#[async_trait]
+impl<C> StoreOutbox for DynamoDBStore<C>
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    /// Get the oldest events from the outbox table
+    #[instrument(skip(self))]
+    async fn pending(&self, limit: usize) -> Result<Vec<Event>, Error> {
+        let outbox_table_name = match &self.outbox_table_name {
+            Some(outbox_table_name) => outbox_table_name,
+            None => return Ok(Vec::new()),
+        };
+        let limit = i32::try_from(limit).map_err(|_| Error::ClientError("Limit is too large"))?;
+        let res = self
+            .client
+            .query()
+            .table_name(outbox_table_name)
+            .key_condition_expression("pk = :pk")
+            .expression_attribute_values(":pk", AttributeValue::S(OUTBOX_PARTITION.to_owned()))
+            .consistent_read(true)
+            .limit(limit)
             .send()
             .await?;
 
+        res.items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| {
+                let event = item
+                    .get_s("event")
+                    .ok_or(Error::InternalError("Missing event"))?;
+                serde_json::from_str(&event)
+                    .map_err(|_| Error::InternalError("Invalid event in outbox"))
+            })
+            .collect()
+    }
+
+    /// Delete events from the outbox table
+    #[instrument(skip(self))]
+    async fn acknowledge(&self, event_ids: &[String]) -> Result<(), Error> {
+        let outbox_table_name = match &self.outbox_table_name {
+            Some(outbox_table_name) => outbox_table_name,
+            None => return Ok(()),
+        };
+        let res = join_all(event_ids.iter().map(|event_id| {
+            self.client
+                .delete_item()
+                .table_name(outbox_table_name)
+                .key("pk", AttributeValue::S(OUTBOX_PARTITION.to_owned()))
+                .key("id", AttributeValue::S(event_id.to_owned()))
+                .send()
+        }))
+        .await;
+
+        res.into_iter().collect::<Result<Vec<_>, _>>()?;
         Ok(())
     }
 }
 
+/// DynamoDB representation of a `Condition`
+///
+/// Items written before versioning was introduced do not have a `version`
+/// attribute, which is treated as version 0.
+struct ConditionExpression {
+    expression: Option<String>,
+    names: Option<HashMap<String, String>>,
+    values: Option<HashMap<String, AttributeValue>>,
+}
+
+impl From<Condition> for ConditionExpression {
+    fn from(condition: Condition) -> Self {
+        let (expression, version) = match condition {
+            Condition::Any => {
+                return ConditionExpression {
+                    expression: None,
+                    names: None,
+                    values: None,
+                }
+            }
+            Condition::Exists => ("attribute_exists(id)", None),
+            Condition::NotExists => ("attribute_not_exists(id)", None),
+            Condition::Version(0) => (
+                "attribute_exists(id) AND attribute_not_exists(#version)",
+                None,
+            ),
+            Condition::Version(version) => ("#version = :version", Some(version)),
+            Condition::NotVersion(0) => (
+                "attribute_not_exists(id) OR attribute_exists(#version)",
+                None,
+            ),
+            Condition::NotVersion(version) => (
+                "attribute_not_exists(id) OR #version <> :version",
+                Some(version),
+            ),
+        };
+
+        ConditionExpression {
+            expression: Some(expression.to_owned()),
+            names: expression
+                .contains("#version")
+                .then(|| HashMap::from([("#version".to_owned(), "version".to_owned())])),
+            values: version.map(|version| {
+                HashMap::from([(
+                    ":version".to_owned(),
+                    AttributeValue::N(version.to_string()),
+                )])
+            }),
+        }
+    }
+}
+
+/// DynamoDB update turning an item into another
+///
+/// Attributes that are new or changed are set, attributes that are gone are
+/// removed, and the others are left out of the expression.
+struct UpdateExpression {
+    expression: String,
+    names: HashMap<String, String>,
+    values: HashMap<String, AttributeValue>,
+}
+
+impl UpdateExpression {
+    fn diff(
+        old: &HashMap<String, AttributeValue>,
+        new: &HashMap<String, AttributeValue>,
+    ) -> UpdateExpression {
+        let mut set = Vec::new();
+        let mut remove = Vec::new();
+        let mut names = HashMap::new();
+        let mut values = HashMap::new();
+
+        // Sort attributes so that the expression is stable
+        let attributes = old
+            .keys()
+            .chain(new.keys())
+            .filter(|name| *name != "id")
+            .collect::<BTreeSet<_>>();
+        for (i, name) in attributes.into_iter().enumerate() {
+            let placeholder = format!("#a{}", i);
+            match (old.get(name), new.get(name)) {
+                (old, Some(value)) if old != Some(value) => {
+                    set.push(format!("{} = :a{}", placeholder, i));
+                    values.insert(format!(":a{}", i), value.clone());
+                }
+                (Some(_), None) => remove.push(placeholder.clone()),
+                _ => continue,
+            }
+            names.insert(placeholder, name.clone());
+        }
+
+        let mut clauses = Vec::new();
+        if !set.is_empty() {
+            clauses.push(format!("SET {}", set.join(", ")));
+        }
+        if !remove.is_empty() {
+            clauses.push(format!("REMOVE {}", remove.join(", ")));
+        }
+        UpdateExpression {
+            expression: clauses.join(" "),
+            names,
+            values,
+        }
+    }
+}
+
 impl From<&Product> for HashMap<String, AttributeValue> {
     /// Convert a &Product into a DynamoDB item
+    ///
+    /// Fields are mapped with `item::to_item`, except for the price: its
+    /// amount is stored as a number in `price` and its currency in
+    /// `currency`, so that the layout of existing items does not change.
     fn from(value: &Product) -> HashMap<String, AttributeValue> {
-        let mut retval = HashMap::new();
-        retval.insert("id".to_owned(), AttributeValue::S(value.id.clone()));
-        retval.insert("name".to_owned(), AttributeValue::S(value.name.clone()));
-        retval.insert(
-            "price".to_owned(),
-            AttributeValue::N(format!("{:}", value.price)),
-        );
+        let mut retval = item::to_item(value).expect("products can be mapped to items");
+        if let Some(AttributeValue::M(mut price)) = retval.remove("price") {
+            if let Some(AttributeValue::S(amount)) = price.remove("amount") {
+                retval.insert("price".to_owned(), AttributeValue::N(amount));
+            }
+            if let Some(currency) = price.remove("currency") {
+                retval.insert("currency".to_owned(), currency);
+            }
+        }
 
         retval
     }
 }
+
 impl TryFrom<HashMap<String, AttributeValue>> for Product {
     type Error = Error;
 
     /// Try to convert a DynamoDB item into a Product
     ///
     /// This could fail as the DynamoDB item might be missing some fields.
-    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        Ok(Product {
-            id: value
-                .get_s("id")
-                .ok_or(Error::InternalError("Missing id"))?,
-            name: value
-                .get_s("name")
-                .ok_or(Error::InternalError("Missing name"))?,
-            price: value
-                .get_n("price")
-                .ok_or(Error::InternalError("Missing price"))?,
-        })
+    fn try_from(mut value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        // Put the price back together, in the default currency for items
+        // written before currencies were introduced
+        if let Some(amount) = value.remove("price") {
+            let currency = value
+                .remove("currency")
+                .unwrap_or_else(|| AttributeValue::S(DEFAULT_CURRENCY.to_owned()));
+            value.insert(
+                "price".to_owned(),
+                AttributeValue::M(HashMap::from([
+                    ("amount".to_owned(), amount),
+                    ("currency".to_owned(), currency),
+                ])),
+            );
+        }
+
+        item::from_item(value)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Error;
+    use crate::store::{StoreBatchDelete, StoreBatchPut, StoreIdempotency};
+    use crate::{Error, IdempotencyRecord, Money, RecordedResponse};
     use aws_sdk_dynamodb::{Client, Config, Credentials, Region};
     use aws_smithy_client::test_connection::TestConnection;
     use aws_smithy_http::body::SdkBody;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal::Decimal;
 
     /// Config for mocking DynamoDB
     async fn get_mock_config() -> Config {
@@ -207,12 +1151,12 @@ mod tests {
         let store = DynamoDBStore::new(client, "test".to_string());
 
         // WHEN getting all items
-        let res = store.all(None).await?;
+        let res = store.all(None, 20).await?;
 
         // THEN the response is empty
         assert_eq!(res.products.len(), 0);
         // AND the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
@@ -233,7 +1177,7 @@ mod tests {
         let store = DynamoDBStore::new(client, "test".to_string());
 
         // WHEN getting all items
-        let res = store.all(None).await?;
+        let res = store.all(None, 20).await?;
 
         // THEN the response has one item
         assert_eq!(res.products.len(), 1);
@@ -242,65 +1186,247 @@ mod tests {
         // AND the item has the correct name
         assert_eq!(res.products[0].name, "test1");
         // AND the item has the correct price
-        assert_eq!(res.products[0].price, 1.0);
+        assert_eq!(res.products[0].price, Money::parse("1.0").unwrap());
         // AND the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_all_next() -> Result<(), Error> {
-        // GIVEN a DynamoDBStore with a last evaluated key
+    async fn test_by_category() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with two items in a category, one page apart
         let conn = TestConnection::new(vec![(
             get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.Scan")
-                .body(SdkBody::from(r#"{"TableName":"test","Limit":20}"#))
+                .header("x-amz-target", "DynamoDB_20120810.Query")
+                .body(SdkBody::from(r#"{"TableName":"test","IndexName":"category-price","Limit":1,"KeyConditionExpression":"category = :category","ExpressionAttributeValues":{":category":{"S":"books"}}}"#))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(
-                    r#"{"Items": [], "LastEvaluatedKey": {"id": {"S": "1"}}}"#,
-                ))
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "category": {"S": "books"}, "price": {"N": "1.0"}}], "LastEvaluatedKey": {"id": {"S": "1"}, "category": {"S": "books"}, "price": {"N": "1.0"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Query")
+                .body(SdkBody::from(r#"{"TableName":"test","IndexName":"category-price","Limit":1,"ExclusiveStartKey":{"id":{"S":"1"},"category":{"S":"books"},"price":{"N":"1.0"}},"KeyConditionExpression":"category = :category","ExpressionAttributeValues":{":category":{"S":"books"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "2"}, "name": {"S": "test2"}, "category": {"S": "books"}, "price": {"N": "2.0"}}]}"#))
                 .unwrap(),
         )]);
         let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
         let store = DynamoDBStore::new(client, "test".to_string());
 
-        // WHEN getting all items
-        let res = store.all(None).await?;
+        // WHEN querying the category by price, one item at a time
+        let first = store.by_category("books", SortKey::Price, None, 1).await?;
+        let second = store
+            .by_category("books", SortKey::Price, first.next.as_deref(), 1)
+            .await?;
 
-        // THEN the response has a next key
-        assert_eq!(res.next, Some("1".to_string()));
-        // AND the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        // THEN each page has one item
+        assert_eq!(first.products[0].id, "1");
+        assert_eq!(second.products[0].id, "2");
+        // AND only the first page has a cursor
+        assert!(first.next.is_some());
+        assert!(second.next.is_none());
+        // AND the requests match the expected requests
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_delete() -> Result<(), Error> {
-        // GIVEN a DynamoDBStore
+    async fn test_export_pages() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with two pages of items
         let conn = TestConnection::new(vec![(
             get_request_builder()
-                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
-                .body(SdkBody::from(
-                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#,
-                ))
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","Segment":0,"TotalSegments":1}"#))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from("{}"))
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}], "LastEvaluatedKey": {"id": {"S": "1"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","ExclusiveStartKey":{"id":{"S":"1"}},"Segment":0,"TotalSegments":1}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "2"}, "name": {"S": "test2"}, "price": {"N": "2.0"}}]}"#))
                 .unwrap(),
         )]);
         let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
         let store = DynamoDBStore::new(client, "test".to_string());
 
-        // WHEN deleting an item
-        store.delete("1").await?;
+        // WHEN exporting the table in a single segment
+        let products = store.export(1).try_collect::<Vec<_>>().await?;
+
+        // THEN the products of both pages are returned
+        let ids = products.iter().map(|p| p.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["1", "2"]);
+        // AND the requests match the expected requests
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_segments() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item in each of two segments
+        let response = |id: &str| {
+            (
+                get_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.Scan")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(format!(
+                        r#"{{"Items": [{{"id": {{"S": "{}"}}, "name": {{"S": "test"}}, "price": {{"N": "1.0"}}}}]}}"#,
+                        id
+                    )))
+                    .unwrap(),
+            )
+        };
+        let conn = TestConnection::new(vec![response("1"), response("2")]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN exporting the table in two segments
+        let mut ids = store
+            .export(2)
+            .map_ok(|p| p.id)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // THEN both items are returned
+        ids.sort();
+        assert_eq!(ids, vec!["1", "2"]);
+        // AND each segment was scanned once
+        let mut segments = (0..2)
+            .map(|i| request_body(&conn, i)["Segment"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        segments.sort();
+        assert_eq!(segments, vec![0, 1]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_next() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a last evaluated key
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","Limit":20}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(
+                    r#"{"Items": [], "LastEvaluatedKey": {"id": {"S": "1"}}}"#,
+                ))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting all items
+        let res = store.all(None, 20).await?;
+
+        // THEN the response has a next key
+        assert_eq!(res.next, Some("1".to_string()));
+        // AND the request matches the expected request
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_limit_next() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(
+                    r#"{"TableName":"test","Limit":5,"ExclusiveStartKey":{"id":{"S":"1"}}}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": []}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting a page of items after a key
+        let res = store.all(Some("1"), 5).await?;
+
+        // THEN the response has no next key
+        assert_eq!(res.next, None);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}, "ReturnValues": "ALL_OLD"}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "version": {"N": "2"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN deleting an item
+        let deleted = store.delete("1", Condition::Any).await?;
+
+        // THEN the deleted item is returned
+        let deleted = deleted.expect("Expected deleted product to be Some");
+        assert_eq!(deleted.name, "test1");
+        assert_eq!(deleted.version, 2);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_version() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
+                .body(SdkBody::from(
+                    r##"{"TableName": "test", "Key": {"id": {"S": "1"}}, "ConditionExpression": "#version = :version", "ExpressionAttributeNames": {"#version": "version"}, "ExpressionAttributeValues": {":version": {"N": "2"}}, "ReturnValues": "ALL_OLD"}"##,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN deleting an item at version 2
+        store.delete("1", Condition::Version(2)).await?;
 
         // THEN the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
@@ -328,12 +1454,12 @@ mod tests {
         if let Some(product) = res {
             assert_eq!(product.id, "1");
             assert_eq!(product.name, "test1");
-            assert_eq!(product.price, 1.0);
+            assert_eq!(product.price, Money::parse("1.0").unwrap());
         } else {
             panic!("Expected product to be Some");
         }
         // AND the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
@@ -342,13 +1468,22 @@ mod tests {
     async fn test_put() -> Result<(), Error> {
         // GIVEN an empty DynamoDBStore and a product
         let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        ), (
             get_request_builder()
                 .header("x-amz-target", "DynamoDB_20120810.PutItem")
-                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"price":{"N":"1.5"}}}"#))
+                .body(SdkBody::from(r#"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"tags":{"L":[]},"image_urls":{"L":[]},"attributes":{"M":{}},"price":{"N":"1.5"},"currency":{"S":"USD"},"status":{"S":"active"},"version":{"N":"1"}},"ConditionExpression":"attribute_not_exists(id)","ReturnValues":"ALL_OLD"}"#))
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.5"}}}"#))
+                .body(SdkBody::from("{}"))
                 .unwrap(),
         )]);
         let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
@@ -356,44 +1491,990 @@ mod tests {
         let product = Product {
             id: "1".to_string(),
             name: "test1".to_string(),
-            price: 1.5,
+            price: Money::new(Decimal::new(15, 1), "USD"),
+            version: 0,
+            ..Default::default()
         };
 
         // WHEN putting an item
-        store.put(&product).await?;
+        let res = store.put(&product, Condition::Any).await?;
 
-        // THEN the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        // THEN the product is at version 1
+        assert_eq!(res.product.version, 1);
+        // AND the product is created
+        assert!(res.is_created());
+        // AND the requests match the expected requests
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
 
-    #[test]
-    fn product_from_dynamodb() {
-        let mut value = HashMap::new();
-        value.insert("id".to_owned(), AttributeValue::S("id".to_owned()));
-        value.insert("name".to_owned(), AttributeValue::S("name".to_owned()));
-        value.insert("price".to_owned(), AttributeValue::N("1.0".to_owned()));
+    #[tokio::test]
+    async fn test_put_version() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item at version 2
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}, "version": {"N": "2"}, "created_at": {"S": "2020-09-13T12:26:40Z"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from(r##"{"TableName":"test","Item":{"id":{"S":"1"},"name":{"S":"test1"},"tags":{"L":[]},"image_urls":{"L":[]},"attributes":{"M":{}},"price":{"N":"1.5"},"currency":{"S":"USD"},"created_at":{"S":"2020-09-13T12:26:40Z"},"status":{"S":"active"},"version":{"N":"3"}},"ConditionExpression":"#version = :version","ExpressionAttributeNames":{"#version":"version"},"ExpressionAttributeValues":{":version":{"N":"2"}},"ReturnValues":"ALL_OLD"}"##))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}, "version": {"N": "2"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+        let product = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: Money::new(Decimal::new(15, 1), "USD"),
+            version: 0,
+            ..Default::default()
+        };
 
-        let product = Product::try_from(value).unwrap();
-        assert_eq!(product.id, "id");
-        assert_eq!(product.name, "name");
-        assert_eq!(product.price, 1.0);
+        // WHEN putting an item at version 2
+        let res = store.put(&product, Condition::Version(2)).await?;
+
+        // THEN the product is at version 3
+        assert_eq!(res.product.version, 3);
+        // AND it keeps the creation time of the product it replaced
+        assert_eq!(
+            res.product.created_at,
+            Some(Utc.timestamp_opt(1_600_000_000, 0).unwrap())
+        );
+        // AND the previous product is returned
+        let previous = res.previous.expect("Expected previous product to be Some");
+        assert_eq!(previous.name, "test0");
+        assert_eq!(previous.version, 2);
+        // AND the request matches the expected request
+        conn.assert_requests_match(&[]);
+
+        Ok(())
     }
 
-    #[test]
-    fn product_to_dynamodb() -> Result<(), Error> {
+    #[tokio::test]
+    async fn test_put_version_mismatch() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item at another version
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}, "version": {"N": "3"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
         let product = Product {
-            id: "id".to_owned(),
-            name: "name".to_owned(),
-            price: 1.5,
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: Money::new(Decimal::new(15, 1), "USD"),
+            version: 0,
+            ..Default::default()
         };
 
-        let value: HashMap<String, AttributeValue> = (&product).into();
-        assert_eq!(value.get("id").unwrap().as_s().unwrap(), "id");
-        assert_eq!(value.get("name").unwrap().as_s().unwrap(), "name");
-        assert_eq!(value.get("price").unwrap().as_n().unwrap(), "1.5");
+        // WHEN putting an item at version 2
+        let res = store.put(&product, Condition::Version(2)).await;
+
+        // THEN the precondition fails
+        assert!(matches!(res, Err(Error::PreconditionFailed(_))));
+        // AND the request matches the expected request
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item at version 2
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test0"}, "sku": {"S": "SKU-1"}, "price": {"N": "1.0"}, "version": {"N": "2"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN patching its name and removing its SKU
+        let patch = MergePatch(serde_json::from_str(r#"{"name":"test1","sku":null}"#).unwrap());
+        let res = store
+            .patch("1", &patch, &|_| Ok(()), Condition::Any)
+            .await?;
+
+        // THEN the product is patched and at version 3
+        assert_eq!(res.product.name, "test1");
+        assert_eq!(res.product.sku, None);
+        assert_eq!(res.product.version, 3);
+        assert_eq!(res.previous.unwrap().version, 2);
+        // AND only the changed attributes are written, if the version did not
+        // change
+        let body = request_body(&conn, 1);
+        assert_eq!(
+            body["UpdateExpression"],
+            "SET #a3 = :a3, #a8 = :a8 REMOVE #a5"
+        );
+        assert_eq!(body["ExpressionAttributeNames"]["#a3"], "name");
+        assert_eq!(body["ExpressionAttributeNames"]["#a5"], "sku");
+        assert_eq!(body["ExpressionAttributeNames"]["#a8"], "version");
+        assert_eq!(body["ExpressionAttributeValues"][":a3"]["S"], "test1");
+        assert_eq!(body["ExpressionAttributeValues"][":a8"]["N"], "3");
+        assert_eq!(body["ConditionExpression"], "#version = :version");
+        assert_eq!(body["ExpressionAttributeValues"][":version"]["N"], "2");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_not_found() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore without the item
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(
+                    r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN patching it
+        let patch = MergePatch(serde_json::from_str(r#"{"name":"test1"}"#).unwrap());
+        let res = store.patch("1", &patch, &|_| Ok(()), Condition::Any).await;
+
+        // THEN it is not found
+        assert!(matches!(res, Err(Error::NotFound(_))));
+        // AND nothing is written
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore in soft-delete mode with an item at version 2
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "version": {"N": "2"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_soft_delete(Duration::days(30));
+
+        // WHEN deleting the item
+        let deleted = store.delete("1", Condition::Any).await?.unwrap();
+
+        // THEN the tombstone is returned, at version 3
+        assert!(deleted.is_deleted());
+        assert_eq!(deleted.version, 3);
+        // AND the item is updated instead of deleted, if the version did not
+        // change
+        let body = request_body(&conn, 1);
+        assert_eq!(
+            body["UpdateExpression"],
+            "SET #a2 = :a2, #a6 = :a6, #a9 = :a9"
+        );
+        assert_eq!(body["ExpressionAttributeNames"]["#a2"], "deleted_at");
+        assert_eq!(body["ExpressionAttributeNames"]["#a6"], "purge_at");
+        let purge_at = body["ExpressionAttributeValues"][":a6"]["N"]
+            .as_str()
+            .unwrap()
+            .parse::<i64>()
+            .unwrap();
+        assert_eq!(
+            purge_at,
+            (deleted.deleted_at.unwrap() + Duration::days(30)).timestamp()
+        );
+        assert_eq!(body["ConditionExpression"], "#version = :version");
+        assert_eq!(body["ExpressionAttributeValues"][":version"]["N"], "2");
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_get_tombstone() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a soft-deleted item
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "deleted_at": {"S": "2024-01-01T00:00:00Z"}, "purge_at": {"N": "1706745600"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting the item
+        let res = store.get("1").await?;
+
+        // THEN it is hidden
+        assert_eq!(res, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trash() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore in soft-delete mode
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","Limit":20,"FilterExpression":"attribute_exists(deleted_at)"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "deleted_at": {"S": "2024-01-01T00:00:00Z"}, "purge_at": {"N": "1706745600"}}]}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Scan")
+                .body(SdkBody::from(r#"{"TableName":"test","Limit":20,"FilterExpression":"attribute_not_exists(deleted_at)"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": []}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_soft_delete(Duration::days(30));
+
+        // WHEN listing the trash and all items
+        let trash = store.trash(None, 20).await?;
+        let all = store.all(None, 20).await?;
+
+        // THEN the trash has the soft-deleted item
+        assert_eq!(trash.products.len(), 1);
+        assert!(trash.products[0].is_deleted());
+        // AND it is filtered out of the other items
+        assert!(all.products.is_empty());
+        // AND the requests match the expected requests
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a soft-deleted item at version 3
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "version": {"N": "3"}, "deleted_at": {"S": "2024-01-01T00:00:00Z"}, "purge_at": {"N": "1706745600"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.UpdateItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_soft_delete(Duration::days(30));
+
+        // WHEN restoring the item
+        let restored = store.restore("1").await?;
+
+        // THEN it is restored at version 4
+        assert!(!restored.is_deleted());
+        assert_eq!(restored.version, 4);
+        assert!(restored.updated_at.is_some());
+        // AND both soft-delete attributes are removed, if the version did not
+        // change
+        let body = request_body(&conn, 1);
+        assert!(body["UpdateExpression"]
+            .as_str()
+            .unwrap()
+            .ends_with("REMOVE #a2, #a6"));
+        assert_eq!(body["ExpressionAttributeNames"]["#a2"], "deleted_at");
+        assert_eq!(body["ExpressionAttributeNames"]["#a6"], "purge_at");
+        assert_eq!(body["ExpressionAttributeValues"][":version"]["N"], "3");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_live() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item that is not deleted
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN restoring it
+        let res = store.restore("1").await;
+
+        // THEN it is not found in the trash
+        assert!(matches!(res, Err(Error::NotFound(_))));
+        // AND nothing is written
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    /// Parse the body of the request sent to the mock connection
+    fn request_body(conn: &TestConnection<SdkBody>, index: usize) -> serde_json::Value {
+        serde_json::from_slice(conn.requests()[index].actual.body().bytes().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batch_duplicate_get() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "version": {"N": "1"}}]}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN getting it twice in a batch
+        let request = crate::BatchRequest {
+            get: vec!["1".to_string(), "1".to_string()],
+            ..Default::default()
+        };
+        let res =
+            crate::domain::batch_products(&store, &Default::default(), true, &request).await?;
+
+        // THEN it is returned for both
+        let statuses = res.get.iter().map(|r| r.status).collect::<Vec<_>>();
+        assert_eq!(statuses, vec![200, 200]);
+        // AND its key is only sent once
+        let body = request_body(&conn, 0);
+        assert_eq!(
+            body["RequestItems"]["test"]["Keys"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_many() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with an item at version 2
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}, "version": {"N": "2"}}]}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+        let products = ["1", "2"].map(|id| Product {
+            id: id.to_string(),
+            name: format!("test{}", id),
+            price: Money::parse(id).unwrap(),
+            ..Default::default()
+        });
+
+        // WHEN putting both items
+        let res = store.put_many(&products).await?;
+
+        // THEN both are stored with their new versions
+        let versions = res
+            .iter()
+            .map(|res| res.as_ref().unwrap().product.version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![3, 1]);
+        assert!(!res[0].as_ref().unwrap().is_created());
+        assert!(res[1].as_ref().unwrap().is_created());
+        // AND the current items are read consistently
+        let body = request_body(&conn, 0);
+        assert_eq!(body["RequestItems"]["test"]["ConsistentRead"], true);
+        // AND each item is written on the condition that it did not change
+        let body = request_body(&conn, 1);
+        let items = body["TransactItems"].as_array().unwrap();
+        assert_eq!(
+            items[0]["Put"]["ConditionExpression"],
+            "#version = :version"
+        );
+        assert_eq!(
+            items[0]["Put"]["ExpressionAttributeValues"][":version"]["N"],
+            "2"
+        );
+        assert_eq!(
+            items[1]["Put"]["ConditionExpression"],
+            "attribute_not_exists(id)"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_many_changed_concurrently() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore without items
+        // AND a table where one item is created concurrently
+        let ok = || {
+            (
+                get_request_builder()
+                    .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            )
+        };
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type":"com.amazonaws.dynamodb.v20120810#TransactionCanceledException","message":"Transaction cancelled","CancellationReasons":[{"Code":"ConditionalCheckFailed"},{"Code":"None"}]}"#))
+                .unwrap(),
+        ), ok(), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Responses": {"test": [{"id": {"S": "1"}, "name": {"S": "other"}, "price": {"N": "1.0"}, "version": {"N": "1"}}]}}"#))
+                .unwrap(),
+        ), ok()]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+        let products = ["1", "2"].map(|id| Product {
+            id: id.to_string(),
+            name: format!("test{}", id),
+            price: Money::parse(id).unwrap(),
+            ..Default::default()
+        });
+
+        // WHEN putting both items
+        let res = store.put_many(&products).await?;
+
+        // THEN the other item is written again on its own
+        assert!(res[1].as_ref().unwrap().is_created());
+        let body = request_body(&conn, 2);
+        let items = body["TransactItems"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["Put"]["Item"]["id"]["S"], "2");
+        // AND the item created concurrently is read again and replaced
+        let outcome = res[0].as_ref().unwrap();
+        assert_eq!(outcome.product.version, 2);
+        assert_eq!(outcome.previous.as_ref().unwrap().name, "other");
+        let body = request_body(&conn, 4);
+        let items = body["TransactItems"].as_array().unwrap();
+        assert_eq!(
+            items[0]["Put"]["ExpressionAttributeValues"][":version"]["N"],
+            "1"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_many_invalid_item() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore without items
+        // AND a table that rejects one of the items
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.BatchGetItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type":"com.amazonaws.dynamodb.v20120810#TransactionCanceledException","message":"Transaction cancelled","CancellationReasons":[{"Code":"ValidationError"},{"Code":"None"}]}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+        let products = ["1", "2"].map(|id| Product {
+            id: id.to_string(),
+            name: format!("test{}", id),
+            price: Money::parse(id).unwrap(),
+            ..Default::default()
+        });
+
+        // WHEN putting both items
+        let res = store.put_many(&products).await?;
+
+        // THEN the rejected item fails at once, without being retried
+        assert!(matches!(res[0], Err(Error::UnprocessableEntity(_))));
+        // AND the other item is written again on its own
+        assert!(res[1].as_ref().unwrap().is_created());
+        assert_eq!(conn.requests().len(), 3);
+        let body = request_body(&conn, 2);
+        let items = body["TransactItems"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["Put"]["Item"]["id"]["S"], "2");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_many() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with one of two items
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Attributes": {"id": {"S": "1"}, "name": {"S": "test1"}, "price": {"N": "1.0"}, "version": {"N": "1"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string());
+
+        // WHEN deleting both items
+        let res = store
+            .delete_many(&["1".to_string(), "2".to_string()])
+            .await?;
+
+        // THEN the existing item is returned
+        assert_eq!(res[0].as_ref().unwrap().as_ref().unwrap().id, "1");
+        // AND the missing item is reported as such
+        assert!(res[1].as_ref().unwrap().is_none());
+        // AND each delete returns the deleted item
+        let body = request_body(&conn, 0);
+        assert_eq!(body["ReturnValues"], "ALL_OLD");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_outbox() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore in outbox mode with an item at version 2
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}, "version": {"N": "2"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox".to_string());
+        let product = Product {
+            id: "1".to_string(),
+            name: "test1".to_string(),
+            price: Money::new(Decimal::new(15, 1), "USD"),
+            version: 0,
+            ..Default::default()
+        };
+
+        // WHEN putting an item
+        let res = store.put(&product, Condition::Any).await?;
+
+        // THEN the product is at version 3
+        assert_eq!(res.product.version, 3);
+        assert_eq!(res.previous.unwrap().version, 2);
+        // AND the product is written on the condition that it did not change
+        let body = request_body(&conn, 1);
+        assert_eq!(
+            body["TransactItems"][0],
+            serde_json::json!({"Put": {
+                "TableName": "test",
+                "Item": {
+                    "id": {"S": "1"}, "name": {"S": "test1"},
+                    "tags": {"L": []}, "image_urls": {"L": []}, "attributes": {"M": {}},
+                    "price": {"N": "1.5"}, "currency": {"S": "USD"},
+                    "status": {"S": "active"}, "version": {"N": "3"}
+                },
+                "ConditionExpression": "#version = :version",
+                "ExpressionAttributeNames": {"#version": "version"},
+                "ExpressionAttributeValues": {":version": {"N": "2"}}
+            }})
+        );
+        // AND an update event is written in the same transaction
+        let outbox = &body["TransactItems"][1]["Put"];
+        assert_eq!(outbox["TableName"], "outbox");
+        assert_eq!(outbox["Item"]["pk"]["S"], "outbox");
+        let event: Event =
+            serde_json::from_str(outbox["Item"]["event"]["S"].as_str().unwrap()).unwrap();
+        assert_eq!(outbox["Item"]["id"]["S"], event.event_id());
+        match event {
+            Event::Updated { old, new, .. } => {
+                assert_eq!(old.name, "test0");
+                assert_eq!(new.name, "test1");
+            }
+            _ => panic!("unexpected event type"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_outbox_canceled() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore in outbox mode with an item that is deleted
+        // concurrently
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "test0"}, "price": {"N": "1.0"}, "version": {"N": "2"}}}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.TransactWriteItems")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type":"com.amazonaws.dynamodb.v20120810#TransactionCanceledException","message":"Transaction cancelled","CancellationReasons":[{"Code":"ConditionalCheckFailed"},{"Code":"None"}]}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"test","Key":{"id":{"S":"1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox".to_string());
+
+        // WHEN deleting the item
+        store.delete("1", Condition::Any).await?;
+
+        // THEN the delete is retried after the transaction is canceled
+        assert_eq!(conn.requests().len(), 3);
+        let body = request_body(&conn, 1);
+        assert_eq!(
+            body["TransactItems"][0]["Delete"]["ConditionExpression"],
+            "#version = :version"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim_taken() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore with a completed idempotency record
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#))
+                .unwrap(),
+        ), (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(r#"{"TableName":"idempotency","Key":{"key":{"S":"key-1"}},"ConsistentRead":true}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Item": {"key": {"S": "key-1"}, "fingerprint": {"S": "a"}, "expires_at": {"N": "32503680000"}, "response": {"S": "{\"status\":201,\"headers\":{},\"body\":\"{}\"}"}}}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string())
+            .with_idempotency("idempotency".to_string());
+        let record = IdempotencyRecord {
+            fingerprint: "b".to_string(),
+            response: None,
+            expires_at: Utc::now(),
+        };
+
+        // WHEN claiming the key
+        let current = store.claim("key-1", &record).await?;
+
+        // THEN the current record is returned
+        let current = current.expect("Expected the key to be taken");
+        assert_eq!(current.fingerprint, "a");
+        assert_eq!(current.response.unwrap().status, 201);
+        // AND the claim was conditional on the key being free or expired
+        let body = request_body(&conn, 0);
+        assert_eq!(body["TableName"], "idempotency");
+        assert_eq!(
+            body["ConditionExpression"],
+            "attribute_not_exists(#key) OR expires_at <= :now"
+        );
+        assert_eq!(body["Item"]["fingerprint"]["S"], "b");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_complete_reclaimed() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore where the key was claimed again
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.PutItem")
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store = DynamoDBStore::new(client, "test".to_string())
+            .with_idempotency("idempotency".to_string());
+        let record = IdempotencyRecord {
+            fingerprint: "a".to_string(),
+            response: Some(RecordedResponse {
+                status: 201,
+                headers: Default::default(),
+                body: "{}".to_string(),
+            }),
+            expires_at: Utc::now(),
+        };
+
+        // WHEN completing the key
+        store.complete("key-1", &record).await?;
+
+        // THEN the write was conditional on the key still being in progress
+        // with the same fingerprint
+        assert_eq!(conn.requests().len(), 1);
+        let body = request_body(&conn, 0);
+        assert_eq!(
+            body["ConditionExpression"],
+            "fingerprint = :fingerprint AND attribute_not_exists(#response)"
+        );
+        assert_eq!(body["ExpressionAttributeValues"][":fingerprint"]["S"], "a");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pending() -> Result<(), Error> {
+        // GIVEN a DynamoDBStore in outbox mode with a pending event
+        let conn = TestConnection::new(vec![(
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.Query")
+                .body(SdkBody::from(r#"{"TableName":"outbox","ConsistentRead":true,"Limit":10,"KeyConditionExpression":"pk = :pk","ExpressionAttributeValues":{":pk":{"S":"outbox"}}}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"Items": [{"pk": {"S": "outbox"}, "id": {"S": "e1"}, "event": {"S": "{\"type\":\"Deleted\",\"schema_version\":3,\"event_id\":\"e1\",\"product\":{\"id\":\"1\",\"name\":\"test\",\"price\":{\"amount\":\"1.5\",\"currency\":\"USD\"},\"version\":2}}"}}]}"#))
+                .unwrap(),
+        )]);
+        let client = Client::from_conf_conn(get_mock_config().await, conn.clone());
+        let store =
+            DynamoDBStore::new(client, "test".to_string()).with_outbox("outbox".to_string());
+
+        // WHEN getting pending events
+        let events = store.pending(10).await?;
+
+        // THEN the event is returned
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_id(), "e1");
+        assert!(matches!(events[0], Event::Deleted { .. }));
+        // AND the request matches the expected request
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn product_from_dynamodb() {
+        let mut value = HashMap::new();
+        value.insert("id".to_owned(), AttributeValue::S("id".to_owned()));
+        value.insert("name".to_owned(), AttributeValue::S("name".to_owned()));
+        value.insert("price".to_owned(), AttributeValue::N("0.1".to_owned()));
+
+        let product = Product::try_from(value).unwrap();
+        assert_eq!(product.id, "id");
+        assert_eq!(product.name, "name");
+        assert_eq!(product.price, Money::new(Decimal::new(1, 1), "USD"));
+        assert_eq!(product.version, 0);
+    }
+
+    #[test]
+    fn product_to_dynamodb() -> Result<(), Error> {
+        let product = Product {
+            id: "id".to_owned(),
+            name: "name".to_owned(),
+            price: Money::new(Decimal::new(15, 1), "EUR"),
+            version: 2,
+            ..Default::default()
+        };
+
+        let value: HashMap<String, AttributeValue> = (&product).into();
+        assert_eq!(value.get("id").unwrap().as_s().unwrap(), "id");
+        assert_eq!(value.get("name").unwrap().as_s().unwrap(), "name");
+        assert_eq!(value.get("price").unwrap().as_n().unwrap(), "1.5");
+        assert_eq!(value.get("currency").unwrap().as_s().unwrap(), "EUR");
+        assert_eq!(value.get("version").unwrap().as_n().unwrap(), "2");
+
+        Ok(())
+    }
+
+    #[test]
+    fn product_from_dynamodb_missing_field() {
+        let mut value = HashMap::new();
+        value.insert("id".to_owned(), AttributeValue::S("id".to_owned()));
+        value.insert("price".to_owned(), AttributeValue::N("1".to_owned()));
+
+        assert!(matches!(Product::try_from(value), Err(Error::ItemError(_))));
+    }
+
+    #[test]
+    fn product_from_dynamodb_large_integer_price() {
+        let mut value = HashMap::new();
+        value.insert("id".to_owned(), AttributeValue::S("id".to_owned()));
+        value.insert("name".to_owned(), AttributeValue::S("name".to_owned()));
+        value.insert(
+            "price".to_owned(),
+            AttributeValue::N("9007199254740993".to_owned()),
+        );
+
+        let product = Product::try_from(value).unwrap();
+        assert_eq!(
+            product.price.amount,
+            Decimal::from(9_007_199_254_740_993u64)
+        );
+    }
+
+    #[test]
+    fn product_dynamodb_roundtrip() {
+        let product = Product {
+            id: "id".to_owned(),
+            name: "name".to_owned(),
+            price: Money::new(Decimal::new(1050, 2), "EUR"),
+            version: 7,
+            ..Default::default()
+        };
+
+        let value: HashMap<String, AttributeValue> = (&product).into();
+        assert_eq!(Product::try_from(value).unwrap(), product);
+    }
 }
@@ -4,63 +4,483 @@
 //! used in production, but rather as a simple implementation for local
 //! testing purposes.
 
-use super::{Store, StoreDelete, StoreGet, StoreGetAll, StorePut};
-use crate::{Error, Product, ProductRange};
+use super::{
+    decode_query_key, encode_query_key, new_event_id, Condition, PatchCheck, SortKey, Store,
+    StoreBatchDelete, StoreBatchGet, StoreBatchPut, StoreDelete, StoreExport, StoreGet,
+    StoreGetAll, StoreIdempotency, StoreOutbox, StorePatch, StorePut, StoreQuery, StoreTrash,
+};
+use crate::{Error, Event, IdempotencyRecord, MergePatch, Product, ProductRange, PutOutcome};
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::sync::RwLock;
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::{Mutex, RwLock};
 
+/// In-memory store
+///
+/// Products are kept sorted by id, so that paginating through them with the
+/// `next` cursor returns them in a stable order.
 #[derive(Default)]
 pub struct MemoryStore {
-    data: RwLock<HashMap<String, Product>>,
+    inner: RwLock<Inner>,
+    /// Idempotency records by key
+    idempotency: Mutex<HashMap<String, IdempotencyRecord>>,
+}
+
+/// Products and outbox, behind a single lock so that they change together
+#[derive(Default)]
+struct Inner {
+    products: BTreeMap<String, Product>,
+    /// Ids of the products of each category, sorted by each sort key
+    categories: BTreeMap<String, CategoryIndex>,
+    /// Pending events by id, if the store is in outbox mode
+    outbox: Option<BTreeMap<String, Event>>,
+    /// Soft-deleted products by id
+    trash: BTreeMap<String, Product>,
+    /// How long soft-deleted products are kept, if the store is in
+    /// soft-delete mode
+    retention: Option<Duration>,
+}
+
+/// Products of a category, as (sort key, id) pairs
+#[derive(Default)]
+struct CategoryIndex {
+    by_name: BTreeSet<(String, String)>,
+    by_price: BTreeSet<(Decimal, String)>,
 }
 
 impl MemoryStore {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Create a store in outbox mode
+    ///
+    /// Every put and delete also records an event, to be published through
+    /// `StoreOutbox`.
+    pub fn with_outbox() -> Self {
+        Self {
+            inner: RwLock::new(Inner {
+                outbox: Some(BTreeMap::new()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Switch the store to soft-delete mode
+    ///
+    /// Deleted products are kept in the trash for `retention`. Expired ones
+    /// are purged the next time the trash is read or written.
+    pub fn with_soft_delete(self, retention: Duration) -> Self {
+        self.inner.write().unwrap().retention = Some(retention);
+        self
+    }
+}
+
+/// Page of products sorted by id, starting right after `next`
+fn page(products: &BTreeMap<String, Product>, next: Option<&str>, limit: usize) -> ProductRange {
+    let start = match next {
+        Some(next) => Bound::Excluded(next),
+        None => Bound::Unbounded,
+    };
+    let mut iter = products
+        .range::<str, _>((start, Bound::Unbounded))
+        .map(|(_, v)| v);
+    let products: Vec<Product> = iter.by_ref().take(limit).cloned().collect();
+
+    // Only return a cursor if there are products left
+    let next = match iter.next() {
+        Some(_) => products.last().map(|p| p.id.clone()),
+        None => None,
+    };
+
+    ProductRange { products, next }
+}
+
+impl Inner {
+    /// Store a product and index it, returning the product it replaced
+    fn insert(&mut self, product: Product) -> Option<Product> {
+        let previous = self.products.insert(product.id.clone(), product.clone());
+        if let Some(previous) = &previous {
+            self.unindex(previous);
+        }
+        if let Some(category) = &product.category {
+            let index = self.categories.entry(category.clone()).or_default();
+            index.by_name.insert((product.name, product.id.clone()));
+            index.by_price.insert((product.price.amount, product.id));
+        }
+        previous
+    }
+
+    /// Remove a product and its index entries
+    fn remove(&mut self, id: &str) -> Option<Product> {
+        let product = self.products.remove(id)?;
+        self.unindex(&product);
+        Some(product)
+    }
+
+    fn unindex(&mut self, product: &Product) {
+        let category = match &product.category {
+            Some(category) => category,
+            None => return,
+        };
+        if let Some(index) = self.categories.get_mut(category) {
+            index
+                .by_name
+                .remove(&(product.name.clone(), product.id.clone()));
+            index
+                .by_price
+                .remove(&(product.price.amount, product.id.clone()));
+            if index.by_name.is_empty() {
+                self.categories.remove(category);
+            }
+        }
+    }
+
+    fn put(&mut self, product: &Product, condition: Condition) -> Result<PutOutcome, Error> {
+        let stored = self.products.get(&product.id);
+        let current = stored.map(|p| p.version);
+        if !condition.matches(current) {
+            return Err(Error::PreconditionFailed("Product version does not match"));
+        }
+
+        // A new product replaces the tombstone of a deleted one, but carries
+        // on with its version
+        let tombstone = self.trash.remove(&product.id);
+        let product = Product {
+            version: current.or(tombstone.map(|p| p.version)).unwrap_or(0) + 1,
+            created_at: stored.and_then(|p| p.created_at).or(product.created_at),
+            ..product.clone()
+        };
+        let previous = self.insert(product.clone());
+        self.record(|event_id| match previous.clone() {
+            Some(old) => Event::updated(event_id, old, product.clone()),
+            None => Event::Created {
+                event_id,
+                product: product.clone(),
+            },
+        });
+        Ok(PutOutcome { product, previous })
+    }
+
+    fn delete(&mut self, id: &str, condition: Condition) -> Result<Option<Product>, Error> {
+        let current = self.products.get(id).map(|p| p.version);
+        if !condition.matches(current) {
+            return Err(Error::PreconditionFailed("Product version does not match"));
+        }
+
+        self.purge(Utc::now());
+        let deleted = match (self.remove(id), self.retention) {
+            (Some(product), Some(_)) => {
+                let tombstone = Product {
+                    version: product.version + 1,
+                    deleted_at: Some(Utc::now()),
+                    ..product
+                };
+                self.trash.insert(tombstone.id.clone(), tombstone.clone());
+                Some(tombstone)
+            }
+            (deleted, _) => deleted,
+        };
+        if let Some(product) = deleted.clone() {
+            self.record(|event_id| Event::Deleted { event_id, product });
+        }
+        Ok(deleted)
+    }
+
+    fn restore(&mut self, id: &str) -> Result<Product, Error> {
+        self.purge(Utc::now());
+        let tombstone = self
+            .trash
+            .remove(id)
+            .ok_or(Error::NotFound("Product not found in trash"))?;
+        let product = Product {
+            version: tombstone.version + 1,
+            updated_at: Some(Utc::now()),
+            deleted_at: None,
+            ..tombstone
+        };
+        self.insert(product.clone());
+        self.record(|event_id| Event::Restored {
+            event_id,
+            product: product.clone(),
+        });
+        Ok(product)
+    }
+
+    /// Purge the tombstones that are past the retention period
+    fn purge(&mut self, now: DateTime<Utc>) {
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return,
+        };
+        let expired = self
+            .trash
+            .values()
+            .filter(|p| p.deleted_at.is_some_and(|at| at + retention <= now))
+            .map(|p| p.id.clone())
+            .collect::<Vec<_>>();
+        for id in expired {
+            if let Some(product) = self.trash.remove(&id) {
+                self.record(|event_id| Event::Purged { event_id, product });
+            }
+        }
+    }
+
+    fn record(&mut self, event: impl FnOnce(String) -> Event) {
+        if let Some(outbox) = &mut self.outbox {
+            let event_id = new_event_id();
+            outbox.insert(event_id.clone(), event(event_id));
+        }
+    }
 }
 
 impl Store for MemoryStore {}
 
 #[async_trait]
 impl StoreGetAll for MemoryStore {
-    async fn all(&self, _: Option<&str>) -> Result<ProductRange, Error> {
-        Ok(ProductRange {
-            products: self
-                .data
-                .read()
-                .unwrap()
-                .iter()
-                .map(|(_, v)| v.clone())
-                .collect(),
-            next: None,
-        })
+    async fn all(&self, next: Option<&str>, limit: usize) -> Result<ProductRange, Error> {
+        Ok(page(&self.inner.read().unwrap().products, next, limit))
+    }
+}
+
+impl StoreExport for MemoryStore {
+    /// Stream a snapshot of the products, taken when called
+    ///
+    /// The products are already in memory, so there is nothing to gain from
+    /// reading them in segments.
+    fn export(&self, _segments: u32) -> BoxStream<'_, Result<Product, Error>> {
+        let products = self
+            .inner
+            .read()
+            .unwrap()
+            .products
+            .values()
+            .cloned()
+            .map(Ok)
+            .collect::<Vec<_>>();
+        stream::iter(products).boxed()
     }
 }
 
 #[async_trait]
 impl StoreGet for MemoryStore {
     async fn get(&self, id: &str) -> Result<Option<Product>, Error> {
-        Ok(self.data.read().unwrap().get(id).cloned())
+        Ok(self.inner.read().unwrap().products.get(id).cloned())
+    }
+}
+
+#[async_trait]
+impl StoreQuery for MemoryStore {
+    async fn by_category(
+        &self,
+        category: &str,
+        sort: SortKey,
+        next: Option<&str>,
+        limit: usize,
+    ) -> Result<ProductRange, Error> {
+        let next = next.map(decode_query_key).transpose()?;
+        let inner = self.inner.read().unwrap();
+        let index = match inner.categories.get(category) {
+            Some(index) => index,
+            None => return Ok(ProductRange::default()),
+        };
+
+        // Start right after the last product of the previous page
+        let mut ids: Box<dyn Iterator<Item = &String>> = match (sort, next) {
+            (SortKey::Name, None) => Box::new(index.by_name.iter().map(|(_, id)| id)),
+            (SortKey::Name, Some(next)) => Box::new(
+                index
+                    .by_name
+                    .range((Bound::Excluded(next), Bound::Unbounded))
+                    .map(|(_, id)| id),
+            ),
+            (SortKey::Price, None) => Box::new(index.by_price.iter().map(|(_, id)| id)),
+            (SortKey::Price, Some((price, id))) => {
+                let price = price
+                    .parse::<Decimal>()
+                    .map_err(|_| Error::ClientError("Invalid pagination cursor"))?;
+                Box::new(
+                    index
+                        .by_price
+                        .range((Bound::Excluded((price, id)), Bound::Unbounded))
+                        .map(|(_, id)| id),
+                )
+            }
+        };
+        let products: Vec<Product> = ids
+            .by_ref()
+            .take(limit)
+            .map(|id| inner.products[id].clone())
+            .collect();
+
+        // Only return a cursor if there are products left
+        let next = match (ids.next(), products.last()) {
+            (Some(_), Some(last)) => Some(match sort {
+                SortKey::Name => encode_query_key(&last.name, &last.id),
+                SortKey::Price => encode_query_key(&last.price.amount.to_string(), &last.id),
+            }),
+            _ => None,
+        };
+
+        Ok(ProductRange { products, next })
     }
 }
 
 #[async_trait]
 impl StorePut for MemoryStore {
-    async fn put(&self, product: &Product) -> Result<(), Error> {
-        self.data
-            .write()
-            .unwrap()
-            .insert(product.id.clone(), product.clone());
-        Ok(())
+    async fn put(&self, product: &Product, condition: Condition) -> Result<PutOutcome, Error> {
+        // Hold the write lock while checking the condition, so that the
+        // check and the write happen atomically.
+        self.inner.write().unwrap().put(product, condition)
+    }
+}
+
+#[async_trait]
+impl StorePatch for MemoryStore {
+    async fn patch(
+        &self,
+        id: &str,
+        patch: &MergePatch,
+        check: PatchCheck<'_>,
+        condition: Condition,
+    ) -> Result<PutOutcome, Error> {
+        // Hold the write lock from the read to the write
+        let mut inner = self.inner.write().unwrap();
+        let current = inner
+            .products
+            .get(id)
+            .ok_or(Error::NotFound("Product not found"))?;
+        if !condition.matches(Some(current.version)) {
+            return Err(Error::PreconditionFailed("Product version does not match"));
+        }
+
+        let version = current.version;
+        let product = patch.apply(current)?;
+        check(&product)?;
+        inner.put(&product, Condition::Version(version))
     }
 }
 
 #[async_trait]
 impl StoreDelete for MemoryStore {
-    async fn delete(&self, id: &str) -> Result<(), Error> {
-        self.data.write().unwrap().remove(id);
+    async fn delete(&self, id: &str, condition: Condition) -> Result<Option<Product>, Error> {
+        self.inner.write().unwrap().delete(id, condition)
+    }
+}
+
+#[async_trait]
+impl StoreBatchGet for MemoryStore {
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error> {
+        let inner = self.inner.read().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| inner.products.get(id).cloned())
+            .collect())
+    }
+}
+
+/// Batches are applied under a single lock, so other callers see either none
+/// or all of their changes.
+#[async_trait]
+impl StoreBatchPut for MemoryStore {
+    async fn put_many(
+        &self,
+        products: &[Product],
+    ) -> Result<Vec<Result<PutOutcome, Error>>, Error> {
+        let mut inner = self.inner.write().unwrap();
+        Ok(products
+            .iter()
+            .map(|product| inner.put(product, Condition::Any))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StoreBatchDelete for MemoryStore {
+    async fn delete_many(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<Result<Option<Product>, Error>>, Error> {
+        let mut inner = self.inner.write().unwrap();
+        Ok(ids
+            .iter()
+            .map(|id| inner.delete(id, Condition::Any))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StoreTrash for MemoryStore {
+    async fn trash(&self, next: Option<&str>, limit: usize) -> Result<ProductRange, Error> {
+        // Take the write lock to purge expired tombstones first
+        let mut inner = self.inner.write().unwrap();
+        inner.purge(Utc::now());
+        Ok(page(&inner.trash, next, limit))
+    }
+
+    async fn restore(&self, id: &str) -> Result<Product, Error> {
+        self.inner.write().unwrap().restore(id)
+    }
+}
+
+#[async_trait]
+impl StoreIdempotency for MemoryStore {
+    async fn claim(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let mut records = self.idempotency.lock().unwrap();
+        // Drop expired records, so that they do not pile up
+        let now = Utc::now();
+        records.retain(|_, record| !record.is_expired(now));
+
+        match records.get(key) {
+            Some(current) => Ok(Some(current.clone())),
+            None => {
+                records.insert(key.to_owned(), record.clone());
+                Ok(None)
+            }
+        }
+    }
+
+    async fn complete(&self, key: &str, record: &IdempotencyRecord) -> Result<(), Error> {
+        // Leave the record of a request that claimed the key again as it is
+        let mut records = self.idempotency.lock().unwrap();
+        if let Some(current) = records.get_mut(key) {
+            if current.fingerprint == record.fingerprint && current.response.is_none() {
+                *current = record.clone();
+            }
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        self.idempotency.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreOutbox for MemoryStore {
+    async fn pending(&self, limit: usize) -> Result<Vec<Event>, Error> {
+        let inner = self.inner.read().unwrap();
+        Ok(match &inner.outbox {
+            Some(outbox) => outbox.values().take(limit).cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
+    async fn acknowledge(&self, event_ids: &[String]) -> Result<(), Error> {
+        if let Some(outbox) = &mut self.inner.write().unwrap().outbox {
+            for event_id in event_ids {
+                outbox.remove(event_id);
+            }
+        }
         Ok(())
     }
 }
@@ -68,20 +488,23 @@ impl StoreDelete for MemoryStore {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Error;
+    use crate::{Error, Money, RecordedResponse};
 
     struct ConstProduct<'a> {
         id: &'a str,
         name: &'a str,
-        price: f64,
+        price: &'a str,
+        version: u64,
     }
 
-    impl Into<Product> for ConstProduct<'_> {
-        fn into(self) -> Product {
+    impl From<ConstProduct<'_>> for Product {
+        fn from(value: ConstProduct<'_>) -> Product {
             Product {
-                id: self.id.to_string(),
-                name: self.name.to_string(),
-                price: self.price,
+                id: value.id.to_string(),
+                name: value.name.to_string(),
+                price: Money::parse(value.price).unwrap(),
+                version: value.version,
+                ..Default::default()
             }
         }
     }
@@ -89,12 +512,14 @@ mod tests {
     const PRODUCT_0: ConstProduct = ConstProduct {
         id: "1",
         name: "foo",
-        price: 10.0,
+        price: "10.0",
+        version: 1,
     };
     const PRODUCT_1: ConstProduct = ConstProduct {
         id: "2",
         name: "foo",
-        price: 10.0,
+        price: "10.0",
+        version: 1,
     };
 
     #[tokio::test]
@@ -104,7 +529,7 @@ mod tests {
 
         // WHEN we get the length of all products
         // THEN we get 0
-        assert_eq!(store.data.read().unwrap().len(), 0);
+        assert_eq!(store.inner.read().unwrap().products.len(), 0);
         Ok(())
     }
 
@@ -114,7 +539,7 @@ mod tests {
         let store = MemoryStore::new();
 
         // WHEN we get all products
-        let all = store.all(None).await?;
+        let all = store.all(None, 20).await?;
 
         // THEN we get an empty list
         assert_eq!(all.products.len(), 0);
@@ -128,12 +553,12 @@ mod tests {
         let product0: Product = PRODUCT_0.into();
         let store = MemoryStore::new();
         {
-            let mut data = store.data.write().unwrap();
+            let data = &mut store.inner.write().unwrap().products;
             data.insert(product0.id.clone(), product0.clone());
         }
 
         // WHEN we get all products
-        let all = store.all(None).await?;
+        let all = store.all(None, 20).await?;
 
         // THEN we get the product
         assert_eq!(all.products.len(), 1);
@@ -149,13 +574,13 @@ mod tests {
         let product1: Product = PRODUCT_1.into();
         let store = MemoryStore::new();
         {
-            let mut data = store.data.write().unwrap();
+            let data = &mut store.inner.write().unwrap().products;
             data.insert(product0.id.clone(), product0.clone());
             data.insert(product1.id.clone(), product1.clone());
         }
 
         // WHEN we get all products
-        let all = store.all(None).await?;
+        let all = store.all(None, 20).await?;
 
         // THEN we get the products
         assert_eq!(all.products.len(), 2);
@@ -165,23 +590,80 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_all_limit() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        {
+            let data = &mut store.inner.write().unwrap().products;
+            data.insert(product0.id.clone(), product0.clone());
+            data.insert(product1.id.clone(), product1.clone());
+        }
+
+        // WHEN we get the first page of products
+        let all = store.all(None, 1).await?;
+
+        // THEN we get the first product
+        assert_eq!(all.products, vec![product0.clone()]);
+        // AND a cursor to the next page
+        assert_eq!(all.next, Some(product0.id.clone()));
+
+        // WHEN we get the next page of products
+        let all = store.all(all.next.as_deref(), 1).await?;
+
+        // THEN we get the second product
+        assert_eq!(all.products, vec![product1]);
+        // AND no cursor
+        assert_eq!(all.next, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_next_missing() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let product0: Product = PRODUCT_0.into();
+        let product1: Product = PRODUCT_1.into();
+        let store = MemoryStore::new();
+        {
+            let data = &mut store.inner.write().unwrap().products;
+            data.insert(product0.id.clone(), product0.clone());
+            data.insert(product1.id.clone(), product1.clone());
+        }
+
+        // WHEN we get products after an id that is not in the store
+        let all = store.all(Some("15"), 20).await?;
+
+        // THEN we get the products that come after that id
+        assert_eq!(all.products, vec![product1]);
+        assert_eq!(all.next, None);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_delete() -> Result<(), Error> {
         // GIVEN a store with a product
         let product0: Product = PRODUCT_0.into();
         let store = MemoryStore::new();
         {
-            let mut data = store.data.write().unwrap();
+            let data = &mut store.inner.write().unwrap().products;
             data.insert(product0.id.clone(), product0.clone());
         }
 
         // WHEN deleting the product
-        store.delete(&product0.id).await?;
+        let deleted = store.delete(&product0.id, Condition::Any).await?;
 
-        // THEN the length of the store is 0
-        assert_eq!(store.data.read().unwrap().len(), 0);
+        // THEN the deleted product is returned
+        assert_eq!(deleted, Some(product0.clone()));
+        // AND the length of the store is 0
+        assert_eq!(store.inner.read().unwrap().products.len(), 0);
         // AND the product is not returned
         assert_eq!(store.get(&product0.id).await?, None);
+        // AND deleting it again deletes nothing
+        assert_eq!(store.delete(&product0.id, Condition::Any).await?, None);
 
         Ok(())
     }
@@ -193,16 +675,16 @@ mod tests {
         let product1: Product = PRODUCT_1.into();
         let store = MemoryStore::new();
         {
-            let mut data = store.data.write().unwrap();
+            let data = &mut store.inner.write().unwrap().products;
             data.insert(product0.id.clone(), product0.clone());
             data.insert(product1.id.clone(), product1.clone());
         }
 
         // WHEN deleting the first product
-        store.delete(&product0.id).await?;
+        store.delete(&product0.id, Condition::Any).await?;
 
         // THEN the length of the store is 1
-        assert_eq!(store.data.read().unwrap().len(), 1);
+        assert_eq!(store.inner.read().unwrap().products.len(), 1);
         // AND the product is not returned
         assert_eq!(store.get(&product0.id).await?, None);
         // AND the second product is returned
@@ -217,7 +699,7 @@ mod tests {
         let product0: Product = PRODUCT_0.into();
         let store = MemoryStore::new();
         {
-            let mut data = store.data.write().unwrap();
+            let data = &mut store.inner.write().unwrap().products;
             data.insert(product0.id.clone(), product0.clone());
         }
 
@@ -237,10 +719,12 @@ mod tests {
         let product0: Product = PRODUCT_0.into();
 
         // WHEN inserting a product
-        store.put(&product0).await?;
+        let outcome = store.put(&product0, Condition::Any).await?;
 
-        // THEN the length of the store is 1
-        assert_eq!(store.data.read().unwrap().len(), 1);
+        // THEN the product is created
+        assert!(outcome.is_created());
+        // AND the length of the store is 1
+        assert_eq!(store.inner.read().unwrap().products.len(), 1);
         // AND the product is returned
         assert_eq!(store.get(&product0.id).await?, Some(product0));
 
@@ -255,15 +739,462 @@ mod tests {
         let product1: Product = PRODUCT_1.into();
 
         // WHEN inserting two products
-        store.put(&product0).await?;
-        store.put(&product1).await?;
+        store.put(&product0, Condition::Any).await?;
+        store.put(&product1, Condition::Any).await?;
 
         // THEN the length of the store is 2
-        assert_eq!(store.data.read().unwrap().len(), 2);
+        assert_eq!(store.inner.read().unwrap().products.len(), 2);
         // AND the products are returned
         assert_eq!(store.get(&product0.id).await?, Some(product0));
         assert_eq!(store.get(&product1.id).await?, Some(product1));
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_put_version() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0, Condition::Any).await?;
+
+        // WHEN replacing the product at its current version
+        let outcome = store.put(&product0, Condition::Version(1)).await?;
+
+        // THEN the version is incremented
+        assert_eq!(outcome.product.version, 2);
+        // AND the previous product is returned
+        assert_eq!(outcome.previous, Some(product0.clone()));
+        // AND the stored product has the new version
+        assert_eq!(store.get(&product0.id).await?, Some(outcome.product));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_version_mismatch() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0, Condition::Any).await?;
+
+        // WHEN replacing the product at another version
+        let res = store.put(&product0, Condition::Version(2)).await;
+
+        // THEN the precondition fails
+        assert!(matches!(res, Err(Error::PreconditionFailed(_))));
+        // AND the product is unchanged
+        assert_eq!(store.get(&product0.id).await?, Some(product0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_not_exists() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0, Condition::Any).await?;
+
+        // WHEN creating the product again
+        let res = store.put(&product0, Condition::NotExists).await;
+
+        // THEN the precondition fails
+        assert!(matches!(res, Err(Error::PreconditionFailed(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch() -> Result<(), Error> {
+        // GIVEN a store in outbox mode with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::with_outbox();
+        store.put(&product0, Condition::Any).await?;
+
+        // WHEN patching its name at its current version
+        let patch = MergePatch(serde_json::from_str(r#"{"name":"bar"}"#).unwrap());
+        let outcome = store
+            .patch(&product0.id, &patch, &|_| Ok(()), Condition::Version(1))
+            .await?;
+
+        // THEN only the name changes and the version is incremented
+        assert_eq!(outcome.product.name, "bar");
+        assert_eq!(outcome.product.price, product0.price);
+        assert_eq!(outcome.product.version, 2);
+        assert_eq!(store.get(&product0.id).await?, Some(outcome.product));
+        // AND an update event is recorded
+        assert!(matches!(store.pending(10).await?[1], Event::Updated { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_rejected() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0, Condition::Any).await?;
+        let patch = MergePatch(serde_json::from_str(r#"{"name":"bar"}"#).unwrap());
+
+        // WHEN patching a missing product, at another version, or with a
+        // failing check
+        let missing = store.patch("3", &patch, &|_| Ok(()), Condition::Any).await;
+        let mismatch = store
+            .patch(&product0.id, &patch, &|_| Ok(()), Condition::Version(2))
+            .await;
+        let invalid = store
+            .patch(
+                &product0.id,
+                &patch,
+                &|_| Err(Error::ClientError("invalid")),
+                Condition::Any,
+            )
+            .await;
+
+        // THEN the patch fails
+        assert!(matches!(missing, Err(Error::NotFound(_))));
+        assert!(matches!(mismatch, Err(Error::PreconditionFailed(_))));
+        assert!(matches!(invalid, Err(Error::ClientError(_))));
+        // AND the product is unchanged
+        assert_eq!(store.get(&product0.id).await?, Some(product0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_version_mismatch() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let product0: Product = PRODUCT_0.into();
+        let store = MemoryStore::new();
+        store.put(&product0, Condition::Any).await?;
+
+        // WHEN deleting the product at another version
+        let res = store.delete(&product0.id, Condition::Version(2)).await;
+
+        // THEN the precondition fails
+        assert!(matches!(res, Err(Error::PreconditionFailed(_))));
+        // AND the product is still there
+        assert_eq!(store.get(&product0.id).await?, Some(product0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outbox() -> Result<(), Error> {
+        // GIVEN a store in outbox mode
+        let store = MemoryStore::with_outbox();
+
+        // WHEN we create, update and delete a product
+        let product: Product = PRODUCT_0.into();
+        store.put(&product, Condition::Any).await?;
+        store.put(&product, Condition::Any).await?;
+        store.delete(&product.id, Condition::Any).await?;
+
+        // THEN an event is recorded for each change, in order
+        let events = store.pending(10).await?;
+        assert!(matches!(events[0], Event::Created { .. }));
+        assert!(matches!(events[1], Event::Updated { .. }));
+        assert!(matches!(events[2], Event::Deleted { .. }));
+        assert_eq!(events.len(), 3);
+
+        // WHEN we acknowledge the first two events
+        store
+            .acknowledge(&[
+                events[0].event_id().to_string(),
+                events[1].event_id().to_string(),
+            ])
+            .await?;
+
+        // THEN only the last one is pending
+        assert_eq!(store.pending(10).await?, vec![events[2].clone()]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outbox_precondition_failed() -> Result<(), Error> {
+        // GIVEN a store in outbox mode
+        let store = MemoryStore::with_outbox();
+
+        // WHEN a put fails its precondition
+        let res = store.put(&PRODUCT_0.into(), Condition::Exists).await;
+
+        // THEN no event is recorded
+        assert!(res.is_err());
+        assert!(store.pending(10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outbox_disabled() -> Result<(), Error> {
+        // GIVEN a store that is not in outbox mode
+        let store = MemoryStore::new();
+
+        // WHEN we put a product
+        store.put(&PRODUCT_0.into(), Condition::Any).await?;
+
+        // THEN no event is recorded
+        assert!(store.pending(10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_by_category() -> Result<(), Error> {
+        // GIVEN a store with products in two categories
+        let store = MemoryStore::new();
+        for (id, name, price, category) in [
+            ("1", "c", "3", "books"),
+            ("2", "a", "2", "books"),
+            ("3", "b", "1", "books"),
+            ("4", "d", "4", "games"),
+        ] {
+            let product = Product {
+                category: Some(category.to_string()),
+                ..ConstProduct {
+                    id,
+                    name,
+                    price,
+                    version: 0,
+                }
+                .into()
+            };
+            store.put(&product, Condition::Any).await?;
+        }
+
+        // WHEN querying a category by name
+        let range = store.by_category("books", SortKey::Name, None, 10).await?;
+        // THEN the products of the category are sorted by name
+        let ids = range
+            .products
+            .iter()
+            .map(|p| p.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["2", "3", "1"]);
+
+        // WHEN querying it by price, one product at a time
+        let mut ids = Vec::new();
+        let mut next = None;
+        loop {
+            let range = store
+                .by_category("books", SortKey::Price, next.as_deref(), 1)
+                .await?;
+            ids.extend(range.products.into_iter().map(|p| p.id));
+            next = range.next;
+            if next.is_none() {
+                break;
+            }
+        }
+        // THEN the products are sorted by price
+        assert_eq!(ids, vec!["3", "2", "1"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_by_category_after_change() -> Result<(), Error> {
+        // GIVEN a store with a product in a category
+        let store = MemoryStore::new();
+        let product = Product {
+            category: Some("books".to_string()),
+            ..PRODUCT_0.into()
+        };
+        store.put(&product, Condition::Any).await?;
+
+        // WHEN the product moves to another category
+        let moved = Product {
+            category: Some("games".to_string()),
+            ..product.clone()
+        };
+        store.put(&moved, Condition::Any).await?;
+
+        // THEN it is only found in the new category
+        let books = store.by_category("books", SortKey::Name, None, 10).await?;
+        assert!(books.products.is_empty());
+        let games = store.by_category("games", SortKey::Name, None, 10).await?;
+        assert_eq!(games.products.len(), 1);
+
+        // WHEN it is deleted
+        store.delete(&product.id, Condition::Any).await?;
+        // THEN it is no longer found
+        let games = store.by_category("games", SortKey::Name, None, 10).await?;
+        assert!(games.products.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch() -> Result<(), Error> {
+        // GIVEN a store with a product
+        let store = MemoryStore::new();
+        let product0: Product = PRODUCT_0.into();
+        store.put(&product0, Condition::Any).await?;
+
+        // WHEN putting it along with a new product
+        let product1: Product = PRODUCT_1.into();
+        let res = store
+            .put_many(&[product0.clone(), product1.clone()])
+            .await?;
+
+        // THEN the first one is replaced and the second one created
+        let outcomes = res.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(outcomes[0].product.version, 2);
+        assert!(outcomes[1].is_created());
+        // AND both can be retrieved at once, ignoring missing ids
+        let ids = ["1", "2", "3"].map(String::from);
+        assert_eq!(store.get_many(&ids).await?.len(), 2);
+
+        // WHEN deleting both
+        let res = store.delete_many(&ids[..2]).await?;
+
+        // THEN they are gone
+        assert!(res.iter().all(Result::is_ok));
+        assert!(store.get_many(&ids).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export() -> Result<(), Error> {
+        // GIVEN a store with two products
+        let store = MemoryStore::new();
+        store.put(&PRODUCT_0.into(), Condition::Any).await?;
+        store.put(&PRODUCT_1.into(), Condition::Any).await?;
+
+        // WHEN exporting the store
+        let products = store.export(4).collect::<Vec<_>>().await;
+
+        // THEN every product is returned
+        assert_eq!(products.len(), 2);
+        assert!(products.iter().all(Result::is_ok));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete() -> Result<(), Error> {
+        // GIVEN a store in outbox and soft-delete mode with a product
+        let store = MemoryStore::with_outbox().with_soft_delete(Duration::days(30));
+        store.put(&PRODUCT_0.into(), Condition::Any).await?;
+
+        // WHEN deleting the product
+        let deleted = store.delete(PRODUCT_0.id, Condition::Any).await?.unwrap();
+
+        // THEN the tombstone is returned, at the next version
+        assert!(deleted.is_deleted());
+        assert_eq!(deleted.version, 2);
+        // AND the product is hidden
+        assert_eq!(store.get(PRODUCT_0.id).await?, None);
+        assert!(store.all(None, 10).await?.products.is_empty());
+        // AND it is in the trash
+        assert_eq!(store.trash(None, 10).await?.products, vec![deleted]);
+
+        // WHEN restoring the product
+        let restored = store.restore(PRODUCT_0.id).await?;
+
+        // THEN it is back, at the next version
+        assert!(!restored.is_deleted());
+        assert_eq!(restored.version, 3);
+        assert_eq!(store.get(PRODUCT_0.id).await?, Some(restored));
+        // AND the trash is empty
+        assert!(store.trash(None, 10).await?.products.is_empty());
+        // AND an event is recorded for each change
+        let events = store.pending(10).await?;
+        assert!(matches!(events[1], Event::Deleted { .. }));
+        assert!(matches!(events[2], Event::Restored { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete_purge() -> Result<(), Error> {
+        // GIVEN a store in soft-delete mode without retention
+        let store = MemoryStore::with_outbox().with_soft_delete(Duration::zero());
+        store.put(&PRODUCT_0.into(), Condition::Any).await?;
+        store.delete(PRODUCT_0.id, Condition::Any).await?;
+
+        // WHEN reading the trash
+        let trash = store.trash(None, 10).await?;
+
+        // THEN the tombstone was purged
+        assert!(trash.products.is_empty());
+        assert!(matches!(
+            store.restore(PRODUCT_0.id).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            store.pending(10).await?.last(),
+            Some(Event::Purged { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_over_tombstone() -> Result<(), Error> {
+        // GIVEN a store in soft-delete mode with a deleted product
+        let store = MemoryStore::new().with_soft_delete(Duration::days(30));
+        store.put(&PRODUCT_0.into(), Condition::Any).await?;
+        store.delete(PRODUCT_0.id, Condition::Any).await?;
+
+        // WHEN putting a product with the same id, if it does not exist
+        let res = store.put(&PRODUCT_0.into(), Condition::NotExists).await?;
+
+        // THEN the product is created, carrying on with the version
+        assert!(res.is_created());
+        assert_eq!(res.product.version, 3);
+        // AND the tombstone is gone
+        assert!(store.trash(None, 10).await?.products.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_without_soft_delete() -> Result<(), Error> {
+        // GIVEN a store that is not in soft-delete mode with a deleted product
+        let store = MemoryStore::new();
+        store.put(&PRODUCT_0.into(), Condition::Any).await?;
+        store.delete(PRODUCT_0.id, Condition::Any).await?;
+
+        // WHEN restoring the product
+        let res = store.restore(PRODUCT_0.id).await;
+
+        // THEN it is not found
+        assert!(matches!(res, Err(Error::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_complete_after_reclaim() -> Result<(), Error> {
+        // GIVEN a key that expired and was claimed again by another request
+        let store = MemoryStore::new();
+        let record = |fingerprint: &str, expires_at| IdempotencyRecord {
+            fingerprint: fingerprint.to_string(),
+            response: None,
+            expires_at,
+        };
+        store.claim("key-1", &record("a", Utc::now())).await?;
+        let later = Utc::now() + Duration::minutes(5);
+        assert_eq!(store.claim("key-1", &record("b", later)).await?, None);
+
+        // WHEN the first request completes
+        let completed = IdempotencyRecord {
+            response: Some(RecordedResponse {
+                status: 201,
+                headers: Default::default(),
+                body: "{}".to_string(),
+            }),
+            ..record("a", later)
+        };
+        store.complete("key-1", &completed).await?;
+
+        // THEN the record of the second request is left unchanged
+        let current = store.claim("key-1", &record("c", later)).await?;
+        assert_eq!(current, Some(record("b", later)));
+
+        Ok(())
+    }
 }
@@ -1,13 +1,64 @@
-use crate::{Error, Product, ProductRange};
+use crate::{Error, Event, IdempotencyRecord, MergePatch, Product, ProductRange, PutOutcome};
 use async_trait::async_trait;
+use futures::stream::BoxStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 mod dynamodb;
 mod memory;
 
-pub use dynamodb::DynamoDBStore;
+pub use dynamodb::{item, DynamoDBStore};
 pub use memory::MemoryStore;
 
-pub trait Store: StoreGetAll + StoreGet + StorePut + StoreDelete {}
+pub trait Store:
+    StoreGetAll
+    + StoreGet
+    + StoreQuery
+    + StorePut
+    + StorePatch
+    + StoreDelete
+    + StoreBatchGet
+    + StoreBatchPut
+    + StoreBatchDelete
+    + StoreExport
+    + StoreTrash
+{
+}
+
+/// Precondition for a write operation
+///
+/// Stores check the condition against the current version of the product
+/// atomically with the write, and return `Error::PreconditionFailed` without
+/// modifying anything if it does not hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Condition {
+    /// Always write
+    #[default]
+    Any,
+    /// The product must exist
+    Exists,
+    /// The product must not exist
+    NotExists,
+    /// The product must exist and be at this version
+    Version(u64),
+    /// The product must not exist or be at another version
+    NotVersion(u64),
+}
+
+impl Condition {
+    /// Check the condition against the current version of a product
+    ///
+    /// `current` is `None` if the product does not exist.
+    pub fn matches(&self, current: Option<u64>) -> bool {
+        match self {
+            Condition::Any => true,
+            Condition::Exists => current.is_some(),
+            Condition::NotExists => current.is_none(),
+            Condition::Version(version) => current == Some(*version),
+            Condition::NotVersion(version) => current != Some(*version),
+        }
+    }
+}
 
 /// Trait for retrieving all products
 ///
@@ -17,9 +68,14 @@ pub trait Store: StoreGetAll + StoreGet + StorePut + StoreDelete {}
 /// A given store could return only a partial list of all the products. If
 /// this is the case, the `next` parameter should be used to retrieve the
 /// next page of products.
+///
+/// `next` is the id of the last product of the previous page: the store
+/// returns up to `limit` products that come after it, and sets `next` in the
+/// returned range to the id of the last product it returned if there might
+/// be more products to fetch.
 #[async_trait]
 pub trait StoreGetAll: Send + Sync {
-    async fn all(&self, next: Option<&str>) -> Result<ProductRange, Error>;
+    async fn all(&self, next: Option<&str>, limit: usize) -> Result<ProductRange, Error>;
 }
 
 /// Trait for retrieving a single product
@@ -28,14 +84,230 @@ pub trait StoreGet: Send + Sync {
     async fn get(&self, id: &str) -> Result<Option<Product>, Error>;
 }
 
+/// Order of the products returned by a query
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Name,
+    /// Price amount, regardless of the currency
+    Price,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "name" => Ok(SortKey::Name),
+            "price" => Ok(SortKey::Price),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Trait for querying products by an indexed attribute
+///
+/// Products are returned in ascending order of `sort`. As with
+/// `StoreGetAll`, the store sets `next` in the returned range if there might
+/// be more products to fetch, and the caller passes it back to get the next
+/// page. Its format is up to the store.
+#[async_trait]
+pub trait StoreQuery: Send + Sync {
+    /// Retrieve the products of a category
+    async fn by_category(
+        &self,
+        category: &str,
+        sort: SortKey,
+        next: Option<&str>,
+        limit: usize,
+    ) -> Result<ProductRange, Error>;
+}
+
+/// Trait for reading the whole catalog
+///
+/// Unlike `StoreGetAll`, which returns one page at a time, this streams every
+/// product, reading up to `segments` parts of the store in parallel. Products
+/// come in no particular order.
+pub trait StoreExport: Send + Sync {
+    fn export(&self, segments: u32) -> BoxStream<'_, Result<Product, Error>>;
+}
+
 /// Trait for storing a single product
+///
+/// The store ignores the version of the given product: it bumps the version
+/// of the stored product on every write and returns the product as stored,
+/// along with the product it replaced if there was one.
+///
+/// When replacing a product, the store keeps the `created_at` of the product
+/// it replaces.
 #[async_trait]
 pub trait StorePut: Send + Sync {
-    async fn put(&self, product: &Product) -> Result<(), Error>;
+    async fn put(&self, product: &Product, condition: Condition) -> Result<PutOutcome, Error>;
+}
+
+/// Check run by the store on a patched product before storing it
+pub type PatchCheck<'a> = &'a (dyn Fn(&Product) -> Result<(), Error> + Sync);
+
+/// Trait for partially updating a single product
+///
+/// The store applies the patch to the current product, runs `check` on the
+/// result and stores it, bumping its version as with `StorePut`. The product
+/// must exist, otherwise this returns `Error::NotFound`.
+///
+/// The patch is applied atomically: if the product changes in the meantime,
+/// the store either applies the patch again on the new product, or fails if
+/// the condition pinned a version.
+#[async_trait]
+pub trait StorePatch: Send + Sync {
+    async fn patch(
+        &self,
+        id: &str,
+        patch: &MergePatch,
+        check: PatchCheck<'_>,
+        condition: Condition,
+    ) -> Result<PutOutcome, Error>;
 }
 
 /// Trait for deleting a single product
+///
+/// Returns the product that was deleted, or `None` if there was no product
+/// with this id. In soft-delete mode, the product is kept as a tombstone, see
+/// `StoreTrash`, and the tombstone is returned.
 #[async_trait]
 pub trait StoreDelete: Send + Sync {
-    async fn delete(&self, id: &str) -> Result<(), Error>;
+    async fn delete(&self, id: &str, condition: Condition) -> Result<Option<Product>, Error>;
+}
+
+/// Trait for soft-deleted products
+///
+/// In soft-delete mode, stores keep deleted products as tombstones with a
+/// `deleted_at` time instead of removing them. Tombstones are hidden from
+/// every other read, can be restored until they are purged, and are purged
+/// once the retention period of the store has passed.
+///
+/// Putting a product with the id of a tombstone replaces the tombstone with
+/// a new product. Stores that are not in soft-delete mode have no tombstones.
+#[async_trait]
+pub trait StoreTrash: Send + Sync {
+    /// Retrieve a page of tombstones, paginated as with `StoreGetAll`
+    async fn trash(&self, next: Option<&str>, limit: usize) -> Result<ProductRange, Error>;
+    /// Restore a soft-deleted product
+    ///
+    /// Returns the restored product, with its version bumped and its update
+    /// time set to now, or `Error::NotFound` if there is no tombstone with
+    /// this id.
+    async fn restore(&self, id: &str) -> Result<Product, Error>;
+}
+
+/// Trait for retrieving several products at once
+#[async_trait]
+pub trait StoreBatchGet: Send + Sync {
+    /// Retrieve the products with the given ids
+    ///
+    /// Products that do not exist are left out, and the others are returned
+    /// in no particular order.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Product>, Error>;
+}
+
+/// Trait for storing several products at once
+///
+/// Each product is stored as with `StorePut` and `Condition::Any`. The ids of
+/// the products must be unique.
+///
+/// The store returns one result per product, in the same order, so that a
+/// product that could not be stored does not fail the others. The outer
+/// error is for failures that affect the whole batch.
+#[async_trait]
+pub trait StoreBatchPut: Send + Sync {
+    async fn put_many(&self, products: &[Product])
+        -> Result<Vec<Result<PutOutcome, Error>>, Error>;
+}
+
+/// Trait for deleting several products at once
+///
+/// Each product is deleted as with `StoreDelete` and `Condition::Any`, so
+/// its result is the deleted product, or `None` if there was none. The
+/// results are returned as with `StoreBatchPut`.
+#[async_trait]
+pub trait StoreBatchDelete: Send + Sync {
+    async fn delete_many(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<Result<Option<Product>, Error>>, Error>;
+}
+
+/// Trait for reading events from the outbox
+///
+/// In outbox mode, stores write an event in the outbox atomically with every
+/// change to a product. Events stay there until they are acknowledged, so
+/// that a relay can publish them with at-least-once delivery.
+///
+/// Stores that are not in outbox mode have no pending events.
+#[async_trait]
+pub trait StoreOutbox: Send + Sync {
+    /// Return up to `limit` pending events, oldest first
+    async fn pending(&self, limit: usize) -> Result<Vec<Event>, Error>;
+    /// Remove published events from the outbox
+    async fn acknowledge(&self, event_ids: &[String]) -> Result<(), Error>;
+}
+
+/// Trait for recording requests by idempotency key
+///
+/// A key is claimed before the request is handled, completed with its
+/// response afterwards, or released if the request failed and can be retried.
+/// Records expire, after which the key can be claimed again.
+///
+/// Stores that are not set up to record keys let every claim succeed, so
+/// repeated keys are handled as new requests.
+#[async_trait]
+pub trait StoreIdempotency: Send + Sync {
+    /// Claim a key with an in-progress record
+    ///
+    /// Returns `None` if the key was free or expired, and the current record
+    /// otherwise, leaving it unchanged.
+    async fn claim(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error>;
+    /// Replace the record of a claimed key
+    ///
+    /// This leaves the record unchanged if the key was claimed again in the
+    /// meantime, with another fingerprint or by a request that completed.
+    async fn complete(&self, key: &str, record: &IdempotencyRecord) -> Result<(), Error>;
+    /// Free a claimed key
+    async fn release(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Encode the position of the last product of a query page
+///
+/// This is the value of the sort key and the id of the product, which are
+/// enough to resume the query from there.
+fn encode_query_key(value: &str, id: &str) -> String {
+    serde_json::to_string(&(value, id)).unwrap()
+}
+
+/// Decode a position encoded with `encode_query_key`
+fn decode_query_key(next: &str) -> Result<(String, String), Error> {
+    serde_json::from_str(next).map_err(|_| Error::ClientError("Invalid pagination cursor"))
+}
+
+/// Generate the id of a new outbox event
+///
+/// Ids start with the creation time in milliseconds followed by a counter,
+/// so that sorting them sorts the events of a process by creation time. The
+/// random suffix keeps ids unique across processes.
+fn new_event_id() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!(
+        "{:012x}{:08x}{:012x}",
+        millis,
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        rand::random::<u64>() & 0xffff_ffff_ffff
+    )
 }
@@ -7,9 +7,21 @@ pub fn setup_tracing() {
     tracing::subscriber::set_global_default(subscriber).expect("failed to set tracing subscriber");
 }
 
+/// Load the secret signing pagination cursors
+///
+/// This fails if `CURSOR_SECRET` is not set.
+pub fn setup_cursors() -> Result<(), crate::Error> {
+    crate::cursor::init()
+}
+
 /// Initialize a store
+///
+/// If `OUTBOX_TABLE_NAME` is set, the store is in outbox mode and writes
+/// events into that table along with every change. If
+/// `IDEMPOTENCY_TABLE_NAME` is set, idempotency keys are recorded in that
+/// table. Soft delete is configured by `soft_delete_retention`.
 #[instrument]
-pub async fn get_store() -> impl store::Store {
+pub async fn get_store() -> impl store::Store + store::StoreOutbox + store::StoreIdempotency {
     // Get AWS Configuration
     let config = aws_config::load_from_env().await;
 
@@ -20,7 +32,55 @@ pub async fn get_store() -> impl store::Store {
         table_name
     );
     let client = aws_sdk_dynamodb::Client::new(&config);
-    store::DynamoDBStore::new(client, table_name)
+    let store = store::DynamoDBStore::new(client, table_name);
+
+    let store = match soft_delete_retention() {
+        Some(retention) => {
+            info!(
+                "Keeping deleted products for {} day(s)",
+                retention.num_days()
+            );
+            store.with_soft_delete(retention)
+        }
+        None => store,
+    };
+
+    let store = match std::env::var("IDEMPOTENCY_TABLE_NAME") {
+        Ok(idempotency_table_name) if !idempotency_table_name.is_empty() => {
+            info!("Using idempotency table: {}", idempotency_table_name);
+            store.with_idempotency(idempotency_table_name)
+        }
+        _ => store,
+    };
+
+    match std::env::var("OUTBOX_TABLE_NAME") {
+        Ok(outbox_table_name) if !outbox_table_name.is_empty() => {
+            info!("Using outbox table: {}", outbox_table_name);
+            store.with_outbox(outbox_table_name)
+        }
+        _ => store,
+    }
+}
+
+/// Whether deleting a missing product is an error
+///
+/// This is on unless `STRICT_DELETE` is set to `false`, in which case such
+/// deletes succeed without content.
+pub fn strict_delete() -> bool {
+    !matches!(std::env::var("STRICT_DELETE").as_deref(), Ok("false"))
+}
+
+/// How long soft-deleted products are kept before being purged
+///
+/// Soft delete is on if `SOFT_DELETE_RETENTION_DAYS` is set to a number of
+/// days greater than zero.
+pub fn soft_delete_retention() -> Option<chrono::Duration> {
+    let days = std::env::var("SOFT_DELETE_RETENTION_DAYS")
+        .ok()
+        .filter(|days| !days.is_empty())?
+        .parse::<i64>()
+        .expect("SOFT_DELETE_RETENTION_DAYS must be a number of days");
+    (days > 0).then(|| chrono::Duration::days(days))
 }
 
 /// Create an event service
@@ -33,5 +93,15 @@ pub async fn get_event_bus() -> impl event_bus::EventBus<E = crate::Event> {
     let event_bus_name = std::env::var("EVENT_BUS_NAME").expect("EVENT_BUS_NAME must be set");
     info!("Initializing EventBridge bus with name: {}", event_bus_name);
     let client = aws_sdk_eventbridge::Client::new(&config);
-    event_bus::EventBridgeBus::new(client, event_bus_name)
+
+    // Events are published in the EventBridge envelope unless the
+    // EVENT_ENVELOPE environment variable selects another one
+    let envelope = match std::env::var("EVENT_ENVELOPE") {
+        Ok(envelope) => envelope
+            .parse()
+            .expect("EVENT_ENVELOPE must be 'eventbridge' or 'cloudevents'"),
+        Err(_) => event_bus::Envelope::default(),
+    };
+    info!("Publishing events with envelope: {:?}", envelope);
+    event_bus::EventBridgeBus::new(client, event_bus_name).with_envelope(envelope)
 }
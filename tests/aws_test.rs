@@ -6,20 +6,20 @@
 //! This assumes that there is an environment variable called `REST_API`
 //! which points to the endpoint of the Amazon API Gateway API.
 
-use float_cmp::approx_eq;
-use products::{Product, ProductRange};
+use products::{Money, Product, ProductRange};
 use rand::distributions::Alphanumeric;
 use rand::prelude::*;
 use reqwest::StatusCode;
+use rust_decimal::Decimal;
 use std::env;
 
 type E = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 fn get_random_string(length: usize) -> String {
     let mut rng = rand::thread_rng();
-    return (0..length)
+    (0..length)
         .map(|_| rng.sample(Alphanumeric) as char)
-        .collect();
+        .collect()
 }
 
 #[tokio::test]
@@ -32,7 +32,9 @@ async fn test_flow() -> Result<(), E> {
     let product = Product {
         id: get_random_string(16),
         name: get_random_string(16),
-        price: rng.gen::<f64>() * 256.0,
+        price: Money::new(Decimal::new(rng.gen_range(0..25600), 2), "USD"),
+        version: 0,
+        ..Default::default()
     };
 
     // Put new product
@@ -44,6 +46,16 @@ async fn test_flow() -> Result<(), E> {
         .await?;
     assert_eq!(res.status(), StatusCode::CREATED);
 
+    // Replace product
+    println!("PUT existing product");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.text().await?.contains("Product updated"));
+
     // Get product
     println!("GET product");
     let res = client
@@ -54,7 +66,7 @@ async fn test_flow() -> Result<(), E> {
     let res_product: Product = res.json().await?;
     assert_eq!(res_product.id, product.id);
     assert_eq!(res_product.name, product.name);
-    assert!(approx_eq!(f64, res_product.price, product.price));
+    assert_eq!(res_product.price, product.price);
 
     // Get all products
     println!("GET all products");
@@ -62,7 +74,7 @@ async fn test_flow() -> Result<(), E> {
     assert_eq!(res.status(), StatusCode::OK);
     let res_products: ProductRange = res.json().await?;
     // At least one product should be returned
-    assert!(res_products.products.len() >= 1);
+    assert!(!res_products.products.is_empty());
 
     // Delete product
     println!("DELETE product");
@@ -80,6 +92,53 @@ async fn test_flow() -> Result<(), E> {
         .await?;
     assert_eq!(res.status(), StatusCode::NOT_FOUND);
 
+    // Delete product again
+    println!("DELETE product again");
+    let res = client
+        .delete(format!("{}/{}", api_url, product.id))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_product() -> Result<(), E> {
+    let client = reqwest::Client::new();
+    let api_url: String = env::var("API_URL").expect("API_URL not set");
+
+    // Create a product without an id
+    println!("POST new product");
+    let res = client
+        .post(&api_url)
+        .json(&serde_json::json!({
+            "name": get_random_string(16),
+            "price": {"amount": "1.00", "currency": "USD"},
+        }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let location = res.headers()["Location"].to_str()?.to_string();
+    let product: Product = res.json().await?;
+    assert_eq!(location, format!("/{}", product.id));
+
+    // Get the product
+    println!("GET created product");
+    let res = client
+        .get(format!("{}/{}", api_url, product.id))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Delete the product
+    println!("DELETE product");
+    let res = client
+        .delete(format!("{}/{}", api_url, product.id))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
     Ok(())
 }
 
@@ -91,7 +150,9 @@ async fn test_put_product_with_invalid_id() -> Result<(), E> {
     let product = Product {
         id: "invalid id".to_string(),
         name: get_random_string(16),
-        price: 0.0,
+        price: Money::new(Decimal::ZERO, "USD"),
+        version: 0,
+        ..Default::default()
     };
 
     // Put new product
@@ -110,6 +171,28 @@ async fn test_put_product_with_invalid_id() -> Result<(), E> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_restore_product_not_in_trash() -> Result<(), E> {
+    let client = reqwest::Client::new();
+    let api_url: String = env::var("API_URL").expect("API_URL not set");
+
+    // Restore a product that was never deleted, with or without soft delete
+    println!("POST restore missing product");
+    let res = client
+        .post(format!("{}/{}/restore", api_url, get_random_string(16)))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // List the trash
+    println!("GET trash");
+    let res = client.get(format!("{}/trash", api_url)).send().await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    res.json::<ProductRange>().await?;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_put_product_empty() -> Result<(), E> {
     let client = reqwest::Client::new();
@@ -119,6 +202,10 @@ async fn test_put_product_empty() -> Result<(), E> {
     println!("PUT new product");
     let res = client.put(format!("{}/empty-id", api_url)).send().await?;
     assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.headers()["Content-Type"].to_str()?,
+        "application/problem+json"
+    );
     assert!(res.text().await?.contains("Empty request body"));
 
     Ok(())
@@ -144,3 +231,211 @@ async fn test_put_product_invalid_body() -> Result<(), E> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_get_products_pagination() -> Result<(), E> {
+    let client = reqwest::Client::new();
+    let api_url: String = env::var("API_URL").expect("API_URL not set");
+
+    // Get a single product
+    println!("GET first page of products");
+    let res = client.get(&api_url).query(&[("limit", "1")]).send().await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res_products: ProductRange = res.json().await?;
+    assert!(res_products.products.len() <= 1);
+
+    // Get the next page
+    if let Some(next) = res_products.next {
+        println!("GET next page of products");
+        let res = client
+            .get(&api_url)
+            .query(&[("limit", "1"), ("next", &next)])
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    // Use a forged cursor
+    println!("GET products with an invalid cursor");
+    let res = client
+        .get(&api_url)
+        .query(&[("next", "not-a-cursor")])
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(res.text().await?.contains("Invalid pagination cursor"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_products_by_category() -> Result<(), E> {
+    let client = reqwest::Client::new();
+    let api_url: String = env::var("API_URL").expect("API_URL not set");
+
+    // Put a product in a category of its own
+    let category = get_random_string(16);
+    let product = Product {
+        id: get_random_string(16),
+        name: get_random_string(16),
+        category: Some(category.clone()),
+        price: Money::new(Decimal::new(100, 2), "USD"),
+        ..Default::default()
+    };
+    println!("PUT new product in a category");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    // List the products of the category
+    //
+    // The index is eventually consistent, so the product might take a moment
+    // to show up.
+    println!("GET products of the category");
+    let mut found = false;
+    for _ in 0..10 {
+        let res = client
+            .get(&api_url)
+            .query(&[("category", category.as_str()), ("sort", "price")])
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res_products: ProductRange = res.json().await?;
+        if res_products.products.iter().any(|p| p.id == product.id) {
+            found = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    }
+    assert!(found);
+
+    // Delete the product
+    println!("DELETE product");
+    let res = client
+        .delete(format!("{}/{}", api_url, product.id))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_put_product_if_match() -> Result<(), E> {
+    let client = reqwest::Client::new();
+    let api_url: String = env::var("API_URL").expect("API_URL not set");
+
+    let product = Product {
+        id: get_random_string(16),
+        name: get_random_string(16),
+        price: Money::new(Decimal::ZERO, "USD"),
+        version: 0,
+        ..Default::default()
+    };
+
+    // Put new product, only if it doesn't exist
+    println!("PUT new product with If-None-Match");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .header("If-None-Match", "*")
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let etag = res.headers()["ETag"].to_str()?.to_string();
+
+    // Put the product again, only if it doesn't exist
+    println!("PUT existing product with If-None-Match");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .header("If-None-Match", "*")
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Get product
+    println!("GET product");
+    let res = client
+        .get(format!("{}/{}", api_url, product.id))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["ETag"].to_str()?, etag);
+
+    // Update product at the current version
+    println!("PUT product with If-Match");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .header("If-Match", &etag)
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(res.headers()["ETag"].to_str()?, etag);
+
+    // Update product at a stale version
+    println!("PUT product with a stale If-Match");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .header("If-Match", &etag)
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Delete product at a stale version
+    println!("DELETE product with a stale If-Match");
+    let res = client
+        .delete(format!("{}/{}", api_url, product.id))
+        .header("If-Match", &etag)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Delete product
+    println!("DELETE product");
+    let res = client
+        .delete(format!("{}/{}", api_url, product.id))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_put_product_invalid_fields() -> Result<(), E> {
+    let client = reqwest::Client::new();
+    let api_url: String = env::var("API_URL").expect("API_URL not set");
+
+    let product = Product {
+        id: get_random_string(16),
+        name: "".to_string(),
+        price: Money::new(Decimal::new(-1, 0), "USD"),
+        version: 0,
+        ..Default::default()
+    };
+
+    // Put new product
+    println!("PUT invalid product");
+    let res = client
+        .put(format!("{}/{}", api_url, product.id))
+        .json(&product)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let problem: serde_json::Value = res.json().await?;
+    let fields = problem["invalid-params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["name", "price"]);
+
+    Ok(())
+}